@@ -2,13 +2,32 @@ pub mod consts {
     // MEM-AP register addresses
     pub const MEM_AP_CSW: u32 = 0x00;
     pub const MEM_AP_TAR: u32 = 0x04;
+    pub const MEM_AP_TAR_UPPER: u32 = 0x08;
     pub const MEM_AP_DRW: u32 = 0x0C;
+    pub const MEM_AP_BD0: u32 = 0x10;
+    pub const MEM_AP_BD1: u32 = 0x14;
+    pub const MEM_AP_BD2: u32 = 0x18;
+    pub const MEM_AP_BD3: u32 = 0x1C;
+    pub const MEM_AP_CFG: u32 = 0xF4;
+
+    // MEM-AP CFG bitfields
+    pub const CFG_BE: u32 = 0x00000001;
+    pub const CFG_LA: u32 = 0x00000002;
+    pub const CFG_LD: u32 = 0x00000004;
 
     // Common AP register addresses
+    pub const AP_BASE_UPPER: u32 = 0xF0;
     pub const AP_BASE: u32 = 0xF8;
     pub const AP_IDR: u32 = 0xFC;
     pub const APSEL_SHIFT: u32 = 24;
 
+    // AP BASE bitfields
+    pub const BASE_PRESENT: u32 = 0x00000001;
+    pub const BASE_FORMAT: u32 = 0x00000002;
+    pub const BASE_ADDR_MASK: u32 = 0xFFFFF000;
+    // Legacy format value for "no debug entry present".
+    pub const BASE_NOT_PRESENT: u32 = 0xFFFFFFFF;
+
     // AP IDR bitfields:
     // [31:28] Revision
     // [27:24] JEP106 continuation (0x4 for ARM)
@@ -43,6 +62,10 @@ pub mod consts {
     pub const CSW_SIZE8: u32 =  0x00000000;
     pub const CSW_SIZE16: u32 =  0x00000001;
     pub const CSW_SIZE32: u32 =  0x00000002;
+    // Only with the large data extension (CFG.LD)
+    pub const CSW_SIZE64: u32 =  0x00000003;
+    pub const CSW_SIZE128: u32 =  0x00000004;
+    pub const CSW_SIZE256: u32 =  0x00000005;
     pub const CSW_ADDRINC: u32 = 0x00000030;
     pub const CSW_NADDRINC: u32 = 0x00000000;
     pub const CSW_SADDRINC: u32 = 0x00000010;
@@ -50,21 +73,190 @@ pub mod consts {
     pub const CSW_DBGSTAT: u32 = 0x00000040;
     pub const CSW_TINPROG: u32 = 0x00000080;
     pub const CSW_HPROT: u32 = 0x02000000;
+    pub const CSW_HPROT_MASK: u32 = 0x1F000000;
+    pub const CSW_HPROT_DATA: u32 = 0x01000000;
+    pub const CSW_HPROT_PRIVILEGED: u32 = 0x02000000;
+    pub const CSW_HPROT_BUFFERABLE: u32 = 0x04000000;
+    pub const CSW_HPROT_CACHEABLE: u32 = 0x08000000;
+    pub const CSW_HNONSEC: u32 = 0x40000000;
     pub const CSW_MSTRTYPE: u32 = 0x20000000;
     pub const CSW_MSTRCORE: u32 = 0x00000000;
     pub const CSW_MSTRDBG: u32 = 0x20000000;
     pub const CSW_RESERVED: u32 = 0x01000000;
 
-    pub const CSW_VALUE: u32 = (CSW_RESERVED | CSW_MSTRDBG | CSW_HPROT | CSW_DBGSTAT | CSW_SADDRINC) as u32;
+    // AXI-AP CSW fields
+    pub const CSW_AXI_PRIVILEGED: u32 = 0x10000000;
+    pub const CSW_AXI_NONSECURE: u32 = 0x20000000;
+    pub const CSW_AXI_INSTRUCTION: u32 = 0x40000000;
+    pub const CSW_AXI_CACHE_MASK: u32 = 0x0F000000;
+    pub const CSW_AXI_CACHE_SHIFT: u32 = 24;
+    pub const CSW_AXI_DOMAIN_MASK: u32 = 0x00006000;
+    pub const CSW_AXI_DOMAIN_SHIFT: u32 = 13;
+    pub const CSW_AXI_ACE_ENABLE: u32 = 0x00001000;
+
+    pub const CSW_VALUE: u32 = CSW_RESERVED | CSW_MSTRDBG | CSW_HPROT | CSW_DBGSTAT | CSW_SADDRINC;
 }
 
+use crate::dap_access::{DebugPortError, SharedError, TransferErrorKind};
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io;
+
 pub type AccessPortNumber = u16;
 
-#[derive(Debug)]
+/// A byte which did not read back as it was written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mismatch {
+    pub address: u64,
+    pub expected: u8,
+    pub actual: u8,
+}
+
+/// A failed access through an access port.
+///
+/// Every variant names the AP. Errors of the probe are kept as `source`, failed DP register
+/// accesses are wrapped as `DebugPort`.
+#[derive(Debug, Clone, PartialEq)]
 pub enum AccessPortError {
-    ProbeError,
-    InvalidAccessPortNumber,
-    MemoryNotAligned,
+    /// The probe failed the transfer.
+    ProbeError { ap: AccessPortNumber, source: SharedError },
+    /// The target kept answering WAIT, the stalled transfer was aborted.
+    WaitTimeout { ap: AccessPortNumber, source: SharedError },
+    /// The target answered FAULT for the access at `address`.
+    /// `ctrl_stat` holds CTRL/STAT as read before its sticky flags were cleared.
+    Fault { ap: AccessPortNumber, address: u64, ctrl_stat: u32 },
+    /// The target answered with an invalid acknowledge.
+    ProtocolError { ap: AccessPortNumber, source: SharedError },
+    /// The data read from the target had a parity error.
+    ParityError { ap: AccessPortNumber, source: SharedError },
+    /// A sticky error flag was set in CTRL/STAT. The flags have been cleared.
+    StickyError { ap: AccessPortNumber, ctrl_stat: u32 },
+    /// The value polled at `address` did not show up in time.
+    Timeout { ap: AccessPortNumber, address: u64 },
+    /// The address does not fit into TAR, the MEM-AP does not support large physical addresses.
+    AddressTooLarge { ap: AccessPortNumber, address: u64 },
+    /// The MEM-AP supports neither accesses of `size` bytes nor word accesses to make them up.
+    UnsupportedAccessSize { ap: AccessPortNumber, size: usize },
+    /// The written data did not read back as written, the mismatching bytes in address order.
+    VerifyFailed { ap: AccessPortNumber, mismatches: Vec<Mismatch> },
+    InvalidAccessPortNumber { ap: AccessPortNumber },
+    /// The access of `size` bytes at `address` is not aligned to its size.
+    MemoryNotAligned { ap: AccessPortNumber, address: u64, size: usize },
+    /// An access to a DP register failed.
+    DebugPort(DebugPortError),
+}
+
+impl AccessPortError {
+    /// Returns the AP of the access, `None` if a DP register access failed.
+    pub fn ap(&self) -> Option<AccessPortNumber> {
+        match self {
+            AccessPortError::ProbeError { ap, .. }
+            | AccessPortError::WaitTimeout { ap, .. }
+            | AccessPortError::Fault { ap, .. }
+            | AccessPortError::ProtocolError { ap, .. }
+            | AccessPortError::ParityError { ap, .. }
+            | AccessPortError::StickyError { ap, .. }
+            | AccessPortError::Timeout { ap, .. }
+            | AccessPortError::AddressTooLarge { ap, .. }
+            | AccessPortError::UnsupportedAccessSize { ap, .. }
+            | AccessPortError::VerifyFailed { ap, .. }
+            | AccessPortError::InvalidAccessPortNumber { ap }
+            | AccessPortError::MemoryNotAligned { ap, .. } => Some(*ap),
+            AccessPortError::DebugPort(_) => None,
+        }
+    }
+
+    /// Returns the kind of the failed transfer behind the error, `None` if no transfer failed.
+    pub fn kind(&self) -> Option<TransferErrorKind> {
+        match self {
+            AccessPortError::ProbeError { .. } => Some(TransferErrorKind::Other),
+            AccessPortError::WaitTimeout { .. } => Some(TransferErrorKind::Wait),
+            AccessPortError::Fault { .. } => Some(TransferErrorKind::Fault),
+            AccessPortError::ProtocolError { .. } => Some(TransferErrorKind::Protocol),
+            AccessPortError::ParityError { .. } => Some(TransferErrorKind::Parity),
+            AccessPortError::DebugPort(error) => Some(error.kind),
+            _ => None,
+        }
+    }
+
+    /// Returns the target address the error is about, if it is known.
+    pub fn address(&self) -> Option<u64> {
+        match self {
+            AccessPortError::Fault { address, .. }
+            | AccessPortError::Timeout { address, .. }
+            | AccessPortError::AddressTooLarge { address, .. }
+            | AccessPortError::MemoryNotAligned { address, .. } => Some(*address),
+            AccessPortError::VerifyFailed { mismatches, .. } => mismatches.first().map(|m| m.address),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AccessPortError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccessPortError::ProbeError { ap, .. } => write!(f, "AP {}: the probe failed the transfer", ap),
+            AccessPortError::WaitTimeout { ap, .. } => write!(f, "AP {}: the target kept answering WAIT", ap),
+            AccessPortError::Fault { ap, address, ctrl_stat } => {
+                write!(f, "AP {}: fault at {:#010X} (CTRL/STAT {:#010X})", ap, address, ctrl_stat)
+            },
+            AccessPortError::ProtocolError { ap, .. } => write!(f, "AP {}: invalid acknowledge", ap),
+            AccessPortError::ParityError { ap, .. } => write!(f, "AP {}: parity error in read data", ap),
+            AccessPortError::StickyError { ap, ctrl_stat } => write!(f, "AP {}: sticky error (CTRL/STAT {:#010X})", ap, ctrl_stat),
+            AccessPortError::Timeout { ap, address } => write!(f, "AP {}: timed out polling {:#010X}", ap, address),
+            AccessPortError::AddressTooLarge { ap, address } => write!(f, "AP {}: address {:#X} does not fit into TAR", ap, address),
+            AccessPortError::UnsupportedAccessSize { ap, size } => write!(f, "AP {}: {}-byte accesses are not supported", ap, size),
+            AccessPortError::VerifyFailed { ap, mismatches } => match mismatches.first() {
+                Some(first) => write!(
+                    f,
+                    "AP {}: verify failed for {} bytes, first at {:#010X} (expected {:#04X}, read {:#04X})",
+                    ap,
+                    mismatches.len(),
+                    first.address,
+                    first.expected,
+                    first.actual
+                ),
+                None => write!(f, "AP {}: verify failed", ap),
+            },
+            AccessPortError::InvalidAccessPortNumber { ap } => write!(f, "AP {} does not exist", ap),
+            AccessPortError::MemoryNotAligned { ap, address, size } => {
+                write!(f, "AP {}: {}-byte access at {:#010X} is not aligned", ap, size, address)
+            },
+            AccessPortError::DebugPort(error) => error.fmt(f),
+        }
+    }
+}
+
+impl Error for AccessPortError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AccessPortError::ProbeError { source, .. }
+            | AccessPortError::WaitTimeout { source, .. }
+            | AccessPortError::ProtocolError { source, .. }
+            | AccessPortError::ParityError { source, .. } => Some(source.get()),
+            AccessPortError::DebugPort(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<DebugPortError> for AccessPortError {
+    fn from(error: DebugPortError) -> Self {
+        AccessPortError::DebugPort(error)
+    }
+}
+
+/// Timeouts become `TimedOut`, everything else `Other` with the `AccessPortError` inside.
+#[cfg(feature = "std")]
+impl From<AccessPortError> for io::Error {
+    fn from(error: AccessPortError) -> Self {
+        match error {
+            AccessPortError::WaitTimeout { .. } | AccessPortError::Timeout { .. } => io::Error::new(io::ErrorKind::TimedOut, error),
+            error => io::Error::other(error),
+        }
+    }
 }
 
 // pub trait AccessPort {
@@ -95,7 +287,7 @@ pub enum AccessPortError {
 //     let idr = debug_port.read_ap(((access_port as u32) << consts::APSEL_SHIFT) | consts::AP_IDR as u32)
 //                         .map_err(|e| AccessPortError::from(e))?;
 //     if idr == 0 {
-//         return Err(AccessPortError::InvalidAccessPortNumber);
+//         return Err(AccessPortError::InvalidAccessPortNumber { ap: access_port });
 //     }
     
 //     // Extract IDR fields used for lookup.
@@ -0,0 +1,849 @@
+use crate::access_port::consts::*;
+use crate::access_port::AccessPortError;
+use crate::dap_access::consts::*;
+#[cfg(feature = "std")]
+use crate::dap_access::DAPAccess;
+use crate::dap_access::{DebugPortError, Transfer, TransferError, TransferErrorKind, TransferKind, DEBUG_PORT};
+use crate::memory_interface::{
+    byte_lanes,
+    in_word,
+    mismatches,
+    split_bytes,
+    sticky_abort,
+    transfer_mode,
+    MemoryInterface,
+    Retries,
+    ToMemoryReadSize,
+    VerifyPolicy,
+    MAX_WORD_COUNT,
+    POLL_BATCH_SIZE
+};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::sync::{mpsc, Arc, Mutex};
+#[cfg(feature = "std")]
+use std::task::{Context, Poll, Waker};
+#[cfg(feature = "std")]
+use std::thread;
+
+/// The async counterpart of `DAPAccess`, e.g. for probes behind a network connection or USB.
+///
+/// The futures are `Send`, so accesses can run in tasks of multi-threaded runtimes.
+/// `ThreadedDAP` turns a blocking `DAPAccess` into one and `BlockingDAP` goes the other way.
+pub trait AsyncDAPAccess: Send {
+    type Error: TransferError;
+
+    /// Reads the DAP register on the specified port and address
+    fn read_register(&mut self, port: u16, addr: u32) -> impl Future<Output = Result<u32, Self::Error>> + Send;
+
+    /// Writes a value to the DAP register on the specified port and address
+    fn write_register(&mut self, port: u16, addr: u32, value: u32) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Runs a batch of transfers in order and marks each one as done.
+    ///
+    /// Stops at the first failing transfer. Probes which can queue transfers should
+    /// override this to save round trips.
+    fn batch(&mut self, transfers: &mut [Transfer]) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        async move {
+            for transfer in transfers.iter_mut() {
+                match transfer.kind {
+                    TransferKind::Read => transfer.value = self.read_register(transfer.port, transfer.addr).await?,
+                    TransferKind::Write => self.write_register(transfer.port, transfer.addr, transfer.value).await?,
+                }
+                transfer.done = true;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Waits out the delay of the retry policy, if `interface` has a way to wait.
+async fn delay(interface: &MemoryInterface) {
+    let delay = interface.retry_policy().delay;
+    if let Some(sleep) = interface.async_sleep() {
+        if delay > Duration::from_millis(0) {
+            sleep(delay).await;
+        }
+    }
+}
+
+/// Runs an access and repeats it as the retry policy allows.
+macro_rules! with_retries {
+    ($memory:expr, $access:expr) => {{
+        let mut retries = Retries::default();
+        loop {
+            match $access {
+                Err(error) if $memory.interface.retry(&error, &mut retries) => (),
+                result => break result,
+            }
+            delay($memory.interface).await;
+        }
+    }};
+}
+
+/// Accesses the memory behind a MEM-AP through an `AsyncDAPAccess`, see `MemoryInterface::asynchronous`.
+///
+/// The reads, writes, `verify_block` and `wait_for_value` match the blocking ones of
+/// `MemoryInterface`, but go out in as few batches as possible. The delay between retries
+/// is awaited with the `AsyncSleep` of the interface, as the clock would block the thread.
+///
+/// There is no async counterpart of `compare_block`, `MemoryWindow`, the memory tools or
+/// the struct and cursor helpers yet, those need a blocking `DAPAccess`, e.g. `BlockingDAP`.
+pub struct AsyncMemory<'a, A: AsyncDAPAccess> {
+    interface: &'a MemoryInterface,
+    debug_port: &'a mut A,
+}
+
+impl<'a, A: AsyncDAPAccess> AsyncMemory<'a, A> {
+    pub fn new(interface: &'a MemoryInterface, debug_port: &'a mut A) -> Self {
+        Self { interface, debug_port }
+    }
+
+    pub async fn read<S: ToMemoryReadSize>(&mut self, addr: u64) -> Result<S, AccessPortError> {
+        let mut values = with_retries!(self, self.read_units::<S>(addr, 1).await)?;
+        Ok(values.remove(0))
+    }
+
+    pub async fn read_block<S: ToMemoryReadSize>(&mut self, addr: u64, data: &mut [S]) -> Result<(), AccessPortError> {
+        let values = with_retries!(self, self.read_units::<S>(addr, data.len()).await)?;
+        for (data, value) in data.iter_mut().zip(values) {
+            *data = value;
+        }
+        Ok(())
+    }
+
+    pub async fn write<S: ToMemoryReadSize>(&mut self, addr: u64, data: S) -> Result<(), AccessPortError> {
+        self.write_block(addr, core::slice::from_ref(&data)).await
+    }
+
+    pub async fn write_block<S: ToMemoryReadSize>(&mut self, addr: u64, data: &[S]) -> Result<(), AccessPortError> {
+        with_retries!(self, self.write_units(addr, data).await)?;
+        let expected = self.interface.memory_bytes(data);
+        self.verify_written(addr, &expected).await
+    }
+
+    /// Reads `data.len()` bytes from `addr`, which does not have to be aligned, like `MemoryInterface::read_bytes`.
+    pub async fn read_bytes(&mut self, addr: u64, data: &mut [u8]) -> Result<(), AccessPortError> {
+        with_retries!(self, self.read_bytes_once(addr, data).await)
+    }
+
+    /// Writes `data` to `addr`, which does not have to be aligned, like `MemoryInterface::write_bytes`.
+    pub async fn write_bytes(&mut self, addr: u64, data: &[u8]) -> Result<(), AccessPortError> {
+        with_retries!(self, self.write_bytes_once(addr, data).await)?;
+        self.verify_written(addr, data).await
+    }
+
+    /// Checks that the memory at `addr` holds `data` without reading it back, like `MemoryInterface::verify_block`.
+    pub async fn verify_block<S: ToMemoryReadSize>(&mut self, addr: u64, data: &[S]) -> Result<bool, AccessPortError> {
+        let (csw, values) = self.interface.verify_values(addr, data)?;
+        with_retries!(self, self.pushed_transfer(TRNVERIFY, 0xF, csw, addr, &values).await)
+            .map(|ctrl_stat| ctrl_stat & CTRLSTAT_STICKYCMP == 0)
+    }
+
+    /// Polls the word at `addr` until the bits selected by `mask` equal those of `value`,
+    /// like `MemoryInterface::wait_for_value`.
+    pub async fn wait_for_value(&mut self, addr: u64, value: u32, mask: u32, timeout: Duration) -> Result<(), AccessPortError> {
+        let interface = self.interface;
+        let ap = interface.access_port();
+        if (addr & value.alignment_mask()) != 0 {
+            return Err(AccessPortError::MemoryNotAligned { ap, address: addr, size: 4 });
+        }
+        let start = interface.clock().map(|clock| (clock.now)());
+        match byte_lanes(mask) {
+            Some(lanes) => {
+                let values = [value; POLL_BATCH_SIZE];
+                loop {
+                    let ctrl_stat = self.pushed_transfer(TRNCOMPARE, lanes, interface.poll_csw(), addr, &values).await?;
+                    if ctrl_stat & CTRLSTAT_STICKYCMP != 0 {
+                        return Ok(());
+                    }
+                    if interface.expired(start, timeout) {
+                        return Err(AccessPortError::Timeout { ap, address: addr });
+                    }
+                }
+            },
+            None => loop {
+                if self.read::<u32>(addr).await? & mask == value & mask {
+                    return Ok(());
+                }
+                if interface.expired(start, timeout) {
+                    return Err(AccessPortError::Timeout { ap, address: addr });
+                }
+            },
+        }
+    }
+
+    /// Checks the bytes written to `addr` according to the verify policy, like `MemoryInterface::compare_bytes`.
+    async fn verify_written(&mut self, addr: u64, expected: &[u8]) -> Result<(), AccessPortError> {
+        let interface = self.interface;
+        match interface.verify_policy() {
+            VerifyPolicy::Off => return Ok(()),
+            VerifyPolicy::ReadBack => (),
+            VerifyPolicy::PushedVerify => {
+                let equal = if addr & 0x3 == 0 && expected.len() & 0x3 == 0 {
+                    let words = expected.chunks(4).map(|bytes| interface.bytes_value(bytes)).collect::<Vec<_>>();
+                    self.verify_block(addr, &words).await?
+                } else if interface.capabilities().supports_size(CSW_SIZE8) {
+                    self.verify_block(addr, expected).await?
+                } else {
+                    false
+                };
+                if equal {
+                    return Ok(());
+                }
+            },
+        }
+        let mut actual = vec![0; expected.len()];
+        self.read_bytes(addr, &mut actual).await?;
+        let mismatches = mismatches(addr, expected, &actual);
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(AccessPortError::VerifyFailed { ap: self.interface.access_port(), mismatches })
+        }
+    }
+
+    /// Reads `count` values of type `S` from `addr` in a single batch.
+    async fn read_units<S: ToMemoryReadSize>(&mut self, addr: u64, count: usize) -> Result<Vec<S>, AccessPortError> {
+        let interface = self.interface;
+        let ap = interface.access_port();
+        let size = interface.access_size::<S>(addr)?;
+        let mut transfers = vec![Transfer::write(ap, MEM_AP_CSW, interface.csw(size))];
+        let mut reads = Vec::with_capacity(count);
+        for i in 0..count {
+            let addr = addr + (i * S::SIZE) as u64;
+            transfers.extend(interface.tar_transfers(if in_word::<S>(size) { addr & !0x3 } else { addr })?);
+            reads.push(transfers.len());
+            transfers.extend((0..S::WORD_COUNT).map(|_| Transfer::read(ap, MEM_AP_DRW)));
+        }
+        self.run(&mut transfers).await?;
+        let mut words = [0; MAX_WORD_COUNT];
+        let words = &mut words[..S::WORD_COUNT];
+        Ok(reads.iter().enumerate().map(|(i, first)| {
+            for (word, transfer) in words.iter_mut().zip(&transfers[*first..]) {
+                *word = transfer.value;
+            }
+            interface.decode_drw(addr + (i * S::SIZE) as u64, words)
+        }).collect())
+    }
+
+    /// Writes `data` to `addr` in a single batch.
+    ///
+    /// Values which have to be merged into the words around them take a batch which reads
+    /// those words first, which is not atomic.
+    async fn write_units<S: ToMemoryReadSize>(&mut self, addr: u64, data: &[S]) -> Result<(), AccessPortError> {
+        let interface = self.interface;
+        let ap = interface.access_port();
+        let size = interface.access_size::<S>(addr)?;
+        let csw = Transfer::write(ap, MEM_AP_CSW, interface.csw(size));
+        if !in_word::<S>(size) {
+            let mut transfers = vec![csw];
+            let mut words = [0; MAX_WORD_COUNT];
+            let words = &mut words[..S::WORD_COUNT];
+            for (i, value) in data.iter().enumerate() {
+                let addr = addr + (i * S::SIZE) as u64;
+                transfers.extend(interface.tar_transfers(addr)?);
+                interface.encode_drw(addr, value, words);
+                transfers.extend(words.iter().map(|word| Transfer::write(ap, MEM_AP_DRW, *word)));
+            }
+            return self.run(&mut transfers).await;
+        }
+
+        // The words the values fall into, in address order.
+        let mut words: Vec<(u64, u32)> = vec![];
+        for i in 0..data.len() {
+            let word = (addr + (i * S::SIZE) as u64) & !0x3;
+            if words.last().map(|(addr, _)| *addr) != Some(word) {
+                words.push((word, 0));
+            }
+        }
+        let mut transfers = vec![csw];
+        for (word, _) in &words {
+            transfers.extend(interface.tar_transfers(*word)?);
+            transfers.push(Transfer::read(ap, MEM_AP_DRW));
+        }
+        self.run(&mut transfers).await?;
+        let mut reads = transfers.iter().filter(|transfer| transfer.kind == TransferKind::Read);
+        for ((_, value), read) in words.iter_mut().zip(&mut reads) {
+            *value = read.value;
+        }
+        for (i, value) in data.iter().enumerate() {
+            let addr = addr + (i * S::SIZE) as u64;
+            if let Some((_, word)) = words.iter_mut().find(|(word, _)| *word == addr & !0x3) {
+                *word = interface.merge_unit(addr, value, *word);
+            }
+        }
+        let mut transfers = vec![];
+        for (word, value) in &words {
+            transfers.extend(interface.tar_transfers(*word)?);
+            transfers.push(Transfer::write(ap, MEM_AP_DRW, *value));
+        }
+        self.run(&mut transfers).await
+    }
+
+    async fn read_bytes_once(&mut self, addr: u64, data: &mut [u8]) -> Result<(), AccessPortError> {
+        let interface = self.interface;
+        for (offset, len) in split_bytes(addr, data.len()) {
+            let addr = addr + offset as u64;
+            let data = &mut data[offset..offset + len];
+            match len {
+                1 | 2 if !interface.capabilities().supports_size(len as u32 >> 1) => {
+                    // Taken from the word around it instead.
+                    let word = interface.value_bytes::<4>(self.read_units::<u32>(addr & !0x3, 1).await?[0]);
+                    let start = (addr & 0x3) as usize;
+                    data.copy_from_slice(&word[start..start + len]);
+                },
+                1 => data[0] = self.read_units::<u8>(addr, 1).await?[0],
+                2 => data.copy_from_slice(&interface.value_bytes::<2>(self.read_units::<u16>(addr, 1).await?[0].into())),
+                _ => self.read_words(addr, data).await?,
+            }
+        }
+        Ok(())
+    }
+
+    async fn write_bytes_once(&mut self, addr: u64, data: &[u8]) -> Result<(), AccessPortError> {
+        let interface = self.interface;
+        for (offset, len) in split_bytes(addr, data.len()) {
+            let addr = addr + offset as u64;
+            let data = &data[offset..offset + len];
+            match len {
+                1 | 2 if !interface.capabilities().supports_size(len as u32 >> 1) => {
+                    let mut word = interface.value_bytes::<4>(self.read_units::<u32>(addr & !0x3, 1).await?[0]);
+                    let start = (addr & 0x3) as usize;
+                    word[start..start + len].copy_from_slice(data);
+                    self.write_units(addr & !0x3, &[interface.bytes_value(&word)]).await?;
+                },
+                1 => self.write_units(addr, &data[..1]).await?,
+                2 => self.write_units(addr, &[interface.bytes_value(data) as u16]).await?,
+                _ => self.write_words(addr, data).await?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads whole words from the word aligned `addr` into `data`, one batch per TAR wrap block.
+    async fn read_words(&mut self, addr: u64, data: &mut [u8]) -> Result<(), AccessPortError> {
+        let interface = self.interface;
+        let ap = interface.access_port();
+        let mut offset = 0;
+        while offset < data.len() {
+            let tar = addr + offset as u64;
+            let len = interface.wrap_block_len(tar, data.len() - offset);
+            let mut transfers = vec![Transfer::write(ap, MEM_AP_CSW, interface.csw(CSW_SIZE32))];
+            transfers.extend(interface.tar_transfers(tar)?);
+            let first = transfers.len();
+            transfers.extend((0..len / 4).map(|_| Transfer::read(ap, MEM_AP_DRW)));
+            self.run(&mut transfers).await?;
+            for (bytes, transfer) in data[offset..offset + len].chunks_mut(4).zip(&transfers[first..]) {
+                bytes.copy_from_slice(&interface.value_bytes::<4>(transfer.value));
+            }
+            offset += len;
+        }
+        Ok(())
+    }
+
+    /// Writes whole words from `data` to the word aligned `addr`, one batch per TAR wrap block.
+    async fn write_words(&mut self, addr: u64, data: &[u8]) -> Result<(), AccessPortError> {
+        let interface = self.interface;
+        let ap = interface.access_port();
+        let mut offset = 0;
+        while offset < data.len() {
+            let tar = addr + offset as u64;
+            let len = interface.wrap_block_len(tar, data.len() - offset);
+            let mut transfers = vec![Transfer::write(ap, MEM_AP_CSW, interface.csw(CSW_SIZE32))];
+            transfers.extend(interface.tar_transfers(tar)?);
+            transfers.extend(data[offset..offset + len].chunks(4).map(|bytes| {
+                Transfer::write(ap, MEM_AP_DRW, interface.bytes_value(bytes))
+            }));
+            self.run(&mut transfers).await?;
+            offset += len;
+        }
+        Ok(())
+    }
+
+    /// Sets TRNMODE and the byte lanes compared by the pushed transfers in CTRL/STAT.
+    async fn set_transfer_mode(&mut self, mode: u32, lanes: u32) -> Result<(), AccessPortError> {
+        let ctrl_stat = self.debug_port.read_register(DEBUG_PORT, DP_CTRL_STAT).await.map_err(|e| DebugPortError::new(DP_CTRL_STAT, e))?;
+        self.debug_port.write_register(DEBUG_PORT, DP_CTRL_STAT, transfer_mode(ctrl_stat, mode, lanes)).await
+            .map_err(|e| DebugPortError::new(DP_CTRL_STAT, e).into())
+    }
+
+    /// Writes `values` to DRW in a pushed transfer mode and returns CTRL/STAT as it was afterwards.
+    ///
+    /// The sticky flags are cleared and the normal transfer mode is restored in any case.
+    async fn pushed_transfer(&mut self, mode: u32, lanes: u32, csw: u32, addr: u64, values: &[u32]) -> Result<u32, AccessPortError> {
+        self.set_transfer_mode(mode, lanes).await?;
+        let result = self.pushed_writes(csw, addr, values).await;
+        let ctrl_stat = self.clear_sticky_errors().await;
+        let restored = self.set_transfer_mode(TRNNORMAL, 0).await;
+        result?;
+        let ctrl_stat = ctrl_stat?;
+        restored?;
+        if ctrl_stat & (CTRLSTAT_STICKYERR | CTRLSTAT_WDATAERR) != 0 {
+            return Err(self.fault_address(ctrl_stat).await);
+        }
+        Ok(ctrl_stat)
+    }
+
+    /// Writes `values` to DRW, one batch per TAR wrap block.
+    async fn pushed_writes(&mut self, csw: u32, addr: u64, values: &[u32]) -> Result<(), AccessPortError> {
+        let interface = self.interface;
+        let ap = interface.access_port();
+        let mut offset = 0;
+        while offset < values.len() {
+            let (tar, len) = interface.pushed_chunk(csw, addr, offset, values.len());
+            let mut transfers = vec![Transfer::write(ap, MEM_AP_CSW, csw)];
+            transfers.extend(interface.tar_transfers(tar)?);
+            transfers.extend(values[offset..offset + len].iter().map(|value| Transfer::write(ap, MEM_AP_DRW, *value)));
+            match self.debug_port.batch(&mut transfers).await {
+                Ok(()) => (),
+                // A set sticky flag refuses the remaining transfers, CTRL/STAT tells which one it is.
+                Err(ref e) if e.kind() == TransferErrorKind::Fault => return Ok(()),
+                Err(e) => return Err(self.recover(e).await),
+            }
+            offset += len;
+        }
+        Ok(())
+    }
+
+    /// Reads CTRL/STAT and clears the sticky error flags, returns CTRL/STAT as it was before clearing.
+    async fn clear_sticky_errors(&mut self) -> Result<u32, AccessPortError> {
+        let ctrl_stat = self.debug_port.read_register(DEBUG_PORT, DP_CTRL_STAT).await.map_err(|e| DebugPortError::new(DP_CTRL_STAT, e))?;
+        let abort = sticky_abort(ctrl_stat);
+        if abort != 0 {
+            self.debug_port.write_register(DEBUG_PORT, DP_ABORT, abort).await.map_err(|e| DebugPortError::new(DP_ABORT, e))?;
+        }
+        Ok(ctrl_stat)
+    }
+
+    /// Runs `transfers` and brings the DP back into a usable state if they fail, like the blocking accesses.
+    async fn run(&mut self, transfers: &mut [Transfer]) -> Result<(), AccessPortError> {
+        match self.debug_port.batch(transfers).await {
+            Ok(()) => Ok(()),
+            Err(error) => Err(self.recover(error).await),
+        }
+    }
+
+    /// Aborts a stalled transfer after a WAIT timeout, or clears the sticky flags after a FAULT.
+    async fn recover(&mut self, error: A::Error) -> AccessPortError {
+        log::debug!("AP{} transfer failed: {:?}", self.interface.access_port(), error);
+        match error.kind() {
+            TransferErrorKind::Wait => {
+                if let Err(e) = self.debug_port.write_register(DEBUG_PORT, DP_ABORT, ABORT_DAPABORT).await {
+                    return DebugPortError::new(DP_ABORT, e).into();
+                }
+                self.interface.classify(error)
+            },
+            TransferErrorKind::Fault => self.fault().await,
+            _ => self.interface.classify(error),
+        }
+    }
+
+    /// Clears the sticky flags and builds the error for a FAULT.
+    async fn fault(&mut self) -> AccessPortError {
+        match self.clear_sticky_errors().await {
+            Ok(ctrl_stat) => self.fault_address(ctrl_stat).await,
+            Err(error) => error,
+        }
+    }
+
+    /// Builds the error for a FAULT once the sticky flags are cleared. TAR still holds the faulting address.
+    async fn fault_address(&mut self, ctrl_stat: u32) -> AccessPortError {
+        let interface = self.interface;
+        let ap = interface.access_port();
+        let mut transfers = [Transfer::read(ap, MEM_AP_TAR_UPPER), Transfer::read(ap, MEM_AP_TAR)];
+        let transfers = if interface.capabilities().large_address { &mut transfers[..] } else { &mut transfers[1..] };
+        if let Err(e) = self.debug_port.batch(transfers).await {
+            return interface.classify(e);
+        }
+        let upper = if transfers.len() > 1 { transfers[0].value } else { 0 };
+        AccessPortError::Fault {
+            ap,
+            address: ((upper as u64) << 32) | transfers[transfers.len() - 1].value as u64,
+            ctrl_stat,
+        }
+    }
+}
+
+/// Runs `future` to completion on the current thread, which sleeps while the future waits.
+///
+/// It blocks the thread, so it must not be called from the threads of an async runtime.
+#[cfg(feature = "std")]
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    struct Unpark(thread::Thread);
+
+    impl std::task::Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut context = Context::from_waker(&waker);
+    let mut future = core::pin::pin!(future);
+    loop {
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+/// Makes an `AsyncDAPAccess` usable wherever a `DAPAccess` is expected, by running each access with `block_on`.
+///
+/// Blocking drivers can use async probes this way, from threads outside of the async runtime.
+#[cfg(feature = "std")]
+pub struct BlockingDAP<A: AsyncDAPAccess> {
+    dap: A,
+}
+
+#[cfg(feature = "std")]
+impl<A: AsyncDAPAccess> BlockingDAP<A> {
+    pub fn new(dap: A) -> Self {
+        Self { dap }
+    }
+
+    pub fn inner(&mut self) -> &mut A {
+        &mut self.dap
+    }
+
+    pub fn into_inner(self) -> A {
+        self.dap
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A: AsyncDAPAccess> DAPAccess for BlockingDAP<A> {
+    type Error = A::Error;
+
+    fn read_register(&mut self, port: u16, addr: u32) -> Result<u32, Self::Error> {
+        block_on(self.dap.read_register(port, addr))
+    }
+
+    fn write_register(&mut self, port: u16, addr: u32, value: u32) -> Result<(), Self::Error> {
+        block_on(self.dap.write_register(port, addr, value))
+    }
+
+    fn batch(&mut self, transfers: &mut [Transfer]) -> Result<(), Self::Error> {
+        block_on(self.dap.batch(transfers))
+    }
+}
+
+#[cfg(feature = "std")]
+type Job<D> = Box<dyn FnOnce(&mut D) + Send>;
+
+/// Runs a blocking `DAPAccess` on a thread of its own and makes it an `AsyncDAPAccess`.
+///
+/// Each access is handed to the thread when it is called and its future completes once the
+/// thread has run it, so async tasks do not block while the probe works. Accesses run in
+/// the order they were called. The thread stops when the `ThreadedDAP` is dropped.
+#[cfg(feature = "std")]
+pub struct ThreadedDAP<D: DAPAccess + Send + 'static> {
+    jobs: Option<mpsc::Sender<Job<D>>>,
+    thread: Option<thread::JoinHandle<D>>,
+}
+
+#[cfg(feature = "std")]
+impl<D: DAPAccess + Send + 'static> ThreadedDAP<D> {
+    pub fn new(dap: D) -> Self {
+        let (jobs, receiver) = mpsc::channel::<Job<D>>();
+        let thread = thread::spawn(move || {
+            let mut dap = dap;
+            for job in receiver {
+                job(&mut dap);
+            }
+            dap
+        });
+        Self {
+            jobs: Some(jobs),
+            thread: Some(thread),
+        }
+    }
+
+    /// Stops the thread and returns the DAP. Panics if the DAP panicked on the thread.
+    pub fn into_inner(mut self) -> D {
+        self.jobs = None;
+        match self.thread.take().map(thread::JoinHandle::join) {
+            Some(Ok(dap)) => dap,
+            Some(Err(panic)) => std::panic::resume_unwind(panic),
+            None => unreachable!("the thread is only taken here and in drop"),
+        }
+    }
+
+    /// Hands `job` to the thread and returns a future for its result.
+    fn run<T: Send + 'static>(&self, job: impl FnOnce(&mut D) -> T + Send + 'static) -> Reply<T> {
+        let slot = Arc::new(Mutex::new(Slot { result: None, waker: None, lost: false }));
+        let completion = Completion(slot.clone());
+        let job: Job<D> = Box::new(move |dap| completion.complete(job(dap)));
+        if let Some(jobs) = &self.jobs {
+            // Without the thread the job is dropped, which marks the reply as lost.
+            let _ = jobs.send(job);
+        }
+        Reply(slot)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<D: DAPAccess + Send + 'static> Drop for ThreadedDAP<D> {
+    fn drop(&mut self) {
+        self.jobs = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<D: DAPAccess + Send + 'static> AsyncDAPAccess for ThreadedDAP<D> {
+    type Error = D::Error;
+
+    fn read_register(&mut self, port: u16, addr: u32) -> impl Future<Output = Result<u32, Self::Error>> + Send {
+        self.run(move |dap| dap.read_register(port, addr))
+    }
+
+    fn write_register(&mut self, port: u16, addr: u32, value: u32) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        self.run(move |dap| dap.write_register(port, addr, value))
+    }
+
+    fn batch(&mut self, transfers: &mut [Transfer]) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        let mut owned = transfers.to_vec();
+        let reply = self.run(move |dap| {
+            let result = dap.batch(&mut owned);
+            (owned, result)
+        });
+        async move {
+            let (owned, result) = reply.await;
+            transfers.copy_from_slice(&owned);
+            result
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+struct Slot<T> {
+    result: Option<T>,
+    waker: Option<Waker>,
+    /// The job was dropped without a result, because the thread panicked.
+    lost: bool,
+}
+
+/// Passes the result of a job to its `Reply` and wakes it, also if the job never ran.
+#[cfg(feature = "std")]
+struct Completion<T>(Arc<Mutex<Slot<T>>>);
+
+#[cfg(feature = "std")]
+impl<T> Completion<T> {
+    fn complete(self, result: T) {
+        self.0.lock().unwrap().result = Some(result);
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> Drop for Completion<T> {
+    fn drop(&mut self) {
+        let mut slot = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        slot.lost = slot.result.is_none();
+        if let Some(waker) = slot.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The result of a job run by a `ThreadedDAP`.
+#[cfg(feature = "std")]
+struct Reply<T>(Arc<Mutex<Slot<T>>>);
+
+#[cfg(feature = "std")]
+impl<T> Future for Reply<T> {
+    type Output = T;
+
+    fn poll(self: core::pin::Pin<&mut Self>, context: &mut Context) -> Poll<T> {
+        let mut slot = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(result) = slot.result.take() {
+            return Poll::Ready(result);
+        }
+        if slot.lost {
+            panic!("the DAP panicked on its thread");
+        }
+        slot.waker = Some(context.waker().clone());
+        Poll::Pending
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::{block_on, AsyncDAPAccess, BlockingDAP, ThreadedDAP};
+    use crate::access_port::consts::*;
+    use crate::access_port::{AccessPortError, Mismatch};
+    use crate::dap_access::consts::*;
+    use crate::dap_access::{DAPAccess, DEBUG_PORT};
+    use crate::memory_interface::{MemApCapabilities, MemoryInterface, VerifyPolicy};
+    use crate::probes::adiv5::AdiV5;
+    use crate::probes::sim::SimulatedDap;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+
+    fn threaded(sim: SimulatedDap) -> ThreadedDAP<AdiV5<SimulatedDap>> {
+        ThreadedDAP::new(AdiV5::new(sim))
+    }
+
+    fn assert_send<T: Send>(_: &T) {}
+
+    #[test]
+    fn read_write() {
+        let mut sim = SimulatedDap::new(0x100);
+        sim.memory.iter_mut().enumerate().for_each(|(i, byte)| *byte = i as u8);
+        let mut dap = threaded(sim);
+        let mut mi = MemoryInterface::new(0x0);
+        mi.set_verify_policy(VerifyPolicy::ReadBack);
+        let mut memory = mi.asynchronous(&mut dap);
+
+        assert_eq!(block_on(memory.read::<u32>(0x10)).unwrap(), 0x13121110);
+        assert_eq!(block_on(memory.read::<u16>(0x22)).unwrap(), 0x2322);
+        let mut data = [0_u8; 7];
+        block_on(memory.read_bytes(0x31, &mut data)).unwrap();
+        assert_eq!(data, [0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37]);
+
+        block_on(memory.write::<u64>(0x40, 0x0123_4567_89AB_CDEF)).unwrap();
+        block_on(memory.write_block::<u8>(0x49, &[0xA1, 0xA2, 0xA3])).unwrap();
+        block_on(memory.write_bytes(0x53, &[0xB1, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6])).unwrap();
+        let mut words = [0_u32; 2];
+        let future = memory.read_block(0x50, &mut words);
+        assert_send(&future);
+        block_on(future).unwrap();
+        assert_eq!(words, [0xB1525150, 0xB5B4B3B2]);
+
+        let memory = dap.into_inner().into_inner().memory;
+        assert_eq!(memory[0x40..0x48], [0xEF, 0xCD, 0xAB, 0x89, 0x67, 0x45, 0x23, 0x01]);
+        assert_eq!(memory[0x48..0x4D], [0x48, 0xA1, 0xA2, 0xA3, 0x4C]);
+        assert_eq!(memory[0x58..0x5A], [0xB6, 0x59]);
+    }
+
+    #[test]
+    fn without_narrow_accesses() {
+        let mut dap = threaded(SimulatedDap::new(0x100));
+        let mut mi = MemoryInterface::new(0x0);
+        mi.set_capabilities(MemApCapabilities {
+            sizes: 1 << CSW_SIZE32,
+            ..MemApCapabilities::default()
+        });
+        let mut memory = mi.asynchronous(&mut dap);
+        block_on(memory.write_bytes(0x10, &[0xFF; 8])).unwrap();
+        block_on(memory.write_bytes(0x11, &[1, 2, 3, 4, 5, 6])).unwrap();
+        let mut data = [0; 3];
+        block_on(memory.read_bytes(0x13, &mut data)).unwrap();
+        assert_eq!(data, [3, 4, 5]);
+        assert_eq!(dap.into_inner().into_inner().memory[0x10..0x18], [0xFF, 1, 2, 3, 4, 5, 6, 0xFF]);
+    }
+
+    #[test]
+    fn fault_and_wait() {
+        static SLEPT: AtomicU32 = AtomicU32::new(0);
+
+        let mut dap = threaded(SimulatedDap::new(0x100));
+        let mut mi = MemoryInterface::new(0x0);
+        let mut memory = mi.asynchronous(&mut dap);
+        let error = block_on(memory.read_bytes(0xF8, &mut [0; 0x10])).unwrap_err();
+        assert_eq!(error, AccessPortError::Fault { ap: 0, address: 0x100, ctrl_stat: 0x20 });
+        block_on(memory.write::<u32>(0x10, 0x1234_5678)).unwrap();
+
+        let mut sim = dap.into_inner().into_inner();
+        assert!(!sim.sticky_error());
+        sim.inject_wait(2);
+        let mut dap = threaded(sim);
+        mi.set_async_sleep(Some(|delay| {
+            assert_eq!(delay, Duration::from_millis(1));
+            SLEPT.fetch_add(1, Ordering::Relaxed);
+            Box::pin(async {})
+        }));
+        assert_eq!(block_on(mi.asynchronous(&mut dap).read::<u32>(0x10)).unwrap(), 0x1234_5678);
+        assert_eq!(SLEPT.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn verify_policy() {
+        let mut sim = SimulatedDap::new(0x100);
+        sim.set_read_only(0x16..0x18);
+        let mut dap = threaded(sim);
+        let mut mi = MemoryInterface::new(0x0);
+        for policy in [VerifyPolicy::ReadBack, VerifyPolicy::PushedVerify] {
+            mi.set_verify_policy(policy);
+            let mut memory = mi.asynchronous(&mut dap);
+            block_on(memory.write_block(0x20, &[1_u32, 2, 3])).unwrap();
+            block_on(memory.write_bytes(0x2D, &[4, 5, 6])).unwrap();
+            assert_eq!(block_on(memory.write_block(0x10, &[0x1122_3344_u32, 0x5566_7788])), Err(AccessPortError::VerifyFailed {
+                ap: 0,
+                mismatches: vec![
+                    Mismatch { address: 0x16, expected: 0x66, actual: 0x00 },
+                    Mismatch { address: 0x17, expected: 0x55, actual: 0x00 },
+                ],
+            }));
+            assert_eq!(block_on(memory.write_bytes(0x15, &[7, 8])), Err(AccessPortError::VerifyFailed {
+                ap: 0,
+                mismatches: vec![Mismatch { address: 0x16, expected: 8, actual: 0 }],
+            }));
+            assert_eq!(block_on(memory.verify_block(0x20, &[1_u32, 2, 3])), Ok(true));
+            assert_eq!(block_on(memory.verify_block(0x20, &[1_u32, 2, 4])), Ok(false));
+        }
+        assert!(!dap.into_inner().into_inner().fault_pending());
+    }
+
+    #[test]
+    fn wait_for_value() {
+        let mut dap = threaded(SimulatedDap::new(0x100));
+        let mi = MemoryInterface::new(0x0);
+        let timeout = Duration::from_millis(20);
+        let mut memory = mi.asynchronous(&mut dap);
+        block_on(memory.write(0x40, 0x1234_5678_u32)).unwrap();
+        assert_eq!(block_on(memory.wait_for_value(0x40, 0x1234_5678, 0xFFFF_FFFF, timeout)), Ok(()));
+        assert_eq!(block_on(memory.wait_for_value(0x40, 0xAA34_56BB, 0x00FF_FF00, timeout)), Ok(()));
+        assert_eq!(block_on(memory.wait_for_value(0x40, 0x0000_0008, 0x0000_000F, timeout)), Ok(()));
+
+        assert_eq!(block_on(memory.wait_for_value(0x40, 0x0000_0001, 0x0000_00FF, timeout)), Err(AccessPortError::Timeout { ap: 0, address: 0x40 }));
+        assert_eq!(block_on(memory.wait_for_value(0x40, 0x0000_0001, 0x0000_0001, timeout)), Err(AccessPortError::Timeout { ap: 0, address: 0x40 }));
+        assert_eq!(block_on(memory.wait_for_value(0x42, 0x1, 0x1, timeout)), Err(AccessPortError::MemoryNotAligned { ap: 0, address: 0x42, size: 4 }));
+        let mut dap = dap.into_inner();
+        assert_eq!(dap.read_register(DEBUG_PORT, DP_CTRL_STAT).unwrap() & CTRLSTAT_TRNMODE, 0);
+    }
+
+    #[test]
+    fn blocking_over_threaded() {
+        let mut sim = SimulatedDap::new(0x100);
+        sim.memory[0x10..0x14].copy_from_slice(&[1, 2, 3, 4]);
+        let mut dap = BlockingDAP::new(threaded(sim));
+        let mi = MemoryInterface::new(0x0);
+        assert_eq!(mi.read::<u32>(&mut dap, 0x10).unwrap(), 0x04030201);
+        mi.write_bytes(&mut dap, 0x21, b"blocking").unwrap();
+        assert_eq!(dap.into_inner().into_inner().into_inner().memory[0x21..0x29], *b"blocking");
+    }
+
+    #[test]
+    fn default_batch() {
+        struct Counter(u32);
+
+        impl AsyncDAPAccess for Counter {
+            type Error = crate::dap_access::MockError;
+
+            async fn read_register(&mut self, _port: u16, _addr: u32) -> Result<u32, Self::Error> {
+                self.0 += 1;
+                Ok(self.0)
+            }
+
+            async fn write_register(&mut self, _port: u16, _addr: u32, value: u32) -> Result<(), Self::Error> {
+                self.0 = value;
+                Ok(())
+            }
+        }
+
+        let mut transfers = [
+            crate::dap_access::Transfer::write(0, 0, 5),
+            crate::dap_access::Transfer::read(0, 0),
+        ];
+        block_on(Counter(0).batch(&mut transfers)).unwrap();
+        assert_eq!(transfers[1].value, 6);
+        assert!(transfers.iter().all(|transfer| transfer.done));
+    }
+}
@@ -0,0 +1,70 @@
+use coresight_rs::dap_access::DAPAccess;
+use coresight_rs::probes::adiv5::AdiV5;
+use coresight_rs::probes::jtag::Jtag;
+use coresight_rs::probes::network;
+use coresight_rs::probes::remote_bitbang::RemoteBitbang;
+use coresight_rs::probes::sim::SimulatedDap;
+use coresight_rs::probes::swd::Swd;
+use std::net::TcpListener;
+use std::process;
+
+const USAGE: &str = "usage: dap-server [--listen ADDR] TARGET
+
+Exposes a DAP to network clients.
+
+Targets:
+    sim                         a simulated target with 64 KiB of RAM
+    remote-bitbang-swd ADDR     a remote_bitbang server, driven over SWD
+    remote-bitbang-jtag ADDR    a remote_bitbang server, driven over JTAG
+
+The server listens on 127.0.0.1:7777 unless --listen is given.";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(1);
+}
+
+fn run<D: DAPAccess>(listen: &str, dap: &mut D) {
+    let listener = TcpListener::bind(listen).unwrap_or_else(|e| fail(&format!("cannot listen on {}: {}", listen, e)));
+    eprintln!("serving DAP on {}", listen);
+    if let Err(e) = network::listen(listener, dap) {
+        fail(&format!("server failed: {}", e));
+    }
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let mut listen = String::from("127.0.0.1:7777");
+    let mut target = vec![];
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = args.next().unwrap_or_else(|| fail("--listen needs an address")),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            },
+            _ => target.push(arg),
+        }
+    }
+
+    match target.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["sim"] => run(&listen, &mut AdiV5::new(SimulatedDap::new(64 * 1024))),
+        ["remote-bitbang-swd", addr] => {
+            let client = RemoteBitbang::connect(addr).unwrap_or_else(|e| fail(&format!("cannot connect to {}: {}", addr, e)));
+            let mut swd = Swd::new(client);
+            if let Err(e) = swd.connect() {
+                fail(&format!("cannot connect to the target: {}", e));
+            }
+            run(&listen, &mut AdiV5::new(swd));
+        },
+        ["remote-bitbang-jtag", addr] => {
+            let client = RemoteBitbang::connect(addr).unwrap_or_else(|e| fail(&format!("cannot connect to {}: {}", addr, e)));
+            let mut jtag = Jtag::new(client);
+            if let Err(e) = jtag.connect() {
+                fail(&format!("cannot connect to the target: {}", e));
+            }
+            run(&listen, &mut AdiV5::new(jtag));
+        },
+        _ => fail("unknown target"),
+    }
+}
@@ -0,0 +1,321 @@
+use crate::access_port::consts::*;
+use crate::dap_access::consts::*;
+use crate::dap_access::{DAPAccess, Transfer, TransferKind, DEBUG_PORT};
+use alloc::collections::BTreeMap;
+use alloc::vec;
+
+/// The sticky flags which CTRL/STAT sets when an AP transfer failed.
+const CTRLSTAT_TRANSFER_FAILED: u32 = CTRLSTAT_STICKYERR | CTRLSTAT_WDATAERR | CTRLSTAT_STICKYORUN;
+
+/// The ABORT bits which clear these flags.
+const ABORT_STICKY_CLEAR: u32 = ABORT_STKERRCLR | ABORT_WDERRCLR | ABORT_ORUNERRCLR;
+
+/// The register values last written to a MEM-AP.
+#[derive(Debug, Clone, Copy, Default)]
+struct ApCache {
+    csw: Option<u32>,
+    tar: Option<u32>,
+    tar_upper: Option<u32>,
+}
+
+impl ApCache {
+    /// Follows the auto-increment of TAR after a DRW access.
+    fn advance_tar(&mut self) {
+        let csw = match self.csw {
+            Some(csw) => csw,
+            None => {
+                self.tar = None;
+                return;
+            },
+        };
+        self.tar = match (csw & CSW_ADDRINC, self.tar) {
+            (CSW_NADDRINC, tar) => tar,
+            (CSW_SADDRINC, Some(tar)) if csw & CSW_SIZE <= CSW_SIZE32 => {
+                let next = tar.wrapping_add(1 << (csw & CSW_SIZE));
+                // Incrementing across a 1 KiB boundary is implementation defined.
+                if next & !0x3FF == tar & !0x3FF { Some(next) } else { None }
+            },
+            _ => None,
+        };
+    }
+}
+
+/// Skips writes to DP SELECT and MEM-AP CSW, TAR and TAR upper which would not change them.
+///
+/// The cache is dropped after every failed transfer, when the debug power domain goes down
+/// and when IDCODE is read, as that happens on every (re)connect. The MEM-AP values are also
+/// dropped when CTRL/STAT reports a failed transfer and when its flags are cleared. Call
+/// `invalidate` after resetting the target by other means.
+pub struct CachingDAP<D: DAPAccess> {
+    dap: D,
+    select: Option<u32>,
+    aps: BTreeMap<u16, ApCache>,
+}
+
+impl<D: DAPAccess> CachingDAP<D> {
+    pub fn new(dap: D) -> Self {
+        Self {
+            dap,
+            select: None,
+            aps: BTreeMap::new(),
+        }
+    }
+
+    pub fn inner(&mut self) -> &mut D {
+        &mut self.dap
+    }
+
+    pub fn into_inner(self) -> D {
+        self.dap
+    }
+
+    /// Forgets all cached values so they get written again on the next access.
+    pub fn invalidate(&mut self) {
+        self.select = None;
+        self.aps.clear();
+    }
+
+    /// Returns whether writing `value` would leave the register unchanged.
+    fn is_redundant(&self, port: u16, addr: u32, value: u32) -> bool {
+        let cached = if port == DEBUG_PORT {
+            match addr {
+                DP_SELECT => self.select,
+                _ => None,
+            }
+        } else {
+            match (self.aps.get(&port), addr) {
+                (Some(ap), MEM_AP_CSW) => ap.csw,
+                (Some(ap), MEM_AP_TAR) => ap.tar,
+                (Some(ap), MEM_AP_TAR_UPPER) => ap.tar_upper,
+                _ => None,
+            }
+        };
+        cached == Some(value)
+    }
+
+    /// Returns whether the effect of a read on the cache depends on the value read.
+    fn depends_on_value(port: u16, addr: u32) -> bool {
+        if port == DEBUG_PORT { addr == DP_CTRL_STAT } else { addr == MEM_AP_TAR || addr == MEM_AP_TAR_UPPER }
+    }
+
+    /// Updates the cache for a transfer which went through.
+    fn update(&mut self, kind: TransferKind, port: u16, addr: u32, value: u32) {
+        if port == DEBUG_PORT {
+            match (kind, addr) {
+                (TransferKind::Write, DP_SELECT) => self.select = Some(value),
+                (TransferKind::Read, DP_IDCODE) => self.invalidate(),
+                // A DAPABORT cancels the transfer in progress and the sticky flags are cleared
+                // after a failed one, either way TAR is unknown.
+                (TransferKind::Write, DP_ABORT) if value & (ABORT_DAPABORT | ABORT_STICKY_CLEAR) != 0 => self.aps.clear(),
+                (_, DP_CTRL_STAT) => {
+                    let power = match kind {
+                        TransferKind::Read => CDBGPWRUPACK | CSYSPWRUPACK,
+                        TransferKind::Write => CDBGPWRUPREQ | CSYSPWRUPREQ,
+                    };
+                    if value & power != power {
+                        self.invalidate();
+                    } else if kind == TransferKind::Read && value & CTRLSTAT_TRANSFER_FAILED != 0 {
+                        // JTAG-DP does not answer FAULT, a failed transfer only shows up here.
+                        // TAR stopped at the address which failed.
+                        self.aps.clear();
+                    }
+                },
+                _ => (),
+            }
+            return;
+        }
+
+        let ap = self.aps.entry(port).or_default();
+        match (kind, addr) {
+            (TransferKind::Write, MEM_AP_CSW) => ap.csw = Some(value),
+            // CSW has read-only bits, so a read does not tell what a write would compare to.
+            (TransferKind::Read, MEM_AP_CSW) => (),
+            (_, MEM_AP_TAR) => ap.tar = Some(value),
+            (_, MEM_AP_TAR_UPPER) => ap.tar_upper = Some(value),
+            (_, MEM_AP_DRW) => ap.advance_tar(),
+            // The banked data registers access memory without moving TAR.
+            (_, MEM_AP_BD0..=MEM_AP_BD3) => (),
+            (TransferKind::Read, MEM_AP_CFG | AP_BASE_UPPER | AP_BASE | AP_IDR) => (),
+            // Anything else might belong to an AP which is not a MEM-AP.
+            _ => *ap = ApCache::default(),
+        }
+    }
+}
+
+impl<D: DAPAccess> DAPAccess for CachingDAP<D> {
+    type Error = D::Error;
+
+    fn read_register(&mut self, port: u16, addr: u32) -> Result<u32, Self::Error> {
+        match self.dap.read_register(port, addr) {
+            Ok(value) => {
+                self.update(TransferKind::Read, port, addr, value);
+                Ok(value)
+            },
+            Err(e) => {
+                self.invalidate();
+                Err(e)
+            },
+        }
+    }
+
+    fn write_register(&mut self, port: u16, addr: u32, value: u32) -> Result<(), Self::Error> {
+        if self.is_redundant(port, addr, value) {
+            return Ok(());
+        }
+        match self.dap.write_register(port, addr, value) {
+            Ok(()) => {
+                self.update(TransferKind::Write, port, addr, value);
+                Ok(())
+            },
+            Err(e) => {
+                self.invalidate();
+                Err(e)
+            },
+        }
+    }
+
+    /// Drops the redundant writes and hands the rest to the DAP as a single batch.
+    fn batch(&mut self, transfers: &mut [Transfer]) -> Result<(), Self::Error> {
+        let mut needed = vec![];
+        let mut indices = vec![];
+        for (i, transfer) in transfers.iter().enumerate() {
+            match transfer.kind {
+                TransferKind::Write if self.is_redundant(transfer.port, transfer.addr, transfer.value) => continue,
+                TransferKind::Write => self.update(transfer.kind, transfer.port, transfer.addr, transfer.value),
+                // CTRL/STAT is checked once the value is known. Reading TAR leaves it unchanged.
+                TransferKind::Read if Self::depends_on_value(transfer.port, transfer.addr) => (),
+                TransferKind::Read => self.update(transfer.kind, transfer.port, transfer.addr, 0),
+            }
+            needed.push(*transfer);
+            indices.push(i);
+        }
+
+        let result = self.dap.batch(&mut needed);
+
+        // Everything up to the first transfer which did not go through is done.
+        let first_failed = indices.get(needed.iter().take_while(|t| t.done).count()).copied().unwrap_or(transfers.len());
+        for transfer in transfers[..first_failed].iter_mut() {
+            transfer.done = true;
+        }
+        for (transfer, &i) in needed.iter().zip(indices.iter()) {
+            if transfer.done {
+                transfers[i].value = transfer.value;
+            }
+        }
+        for transfer in needed.iter().filter(|t| t.done && t.kind == TransferKind::Read) {
+            if transfer.port == DEBUG_PORT && transfer.addr == DP_CTRL_STAT {
+                self.update(transfer.kind, transfer.port, transfer.addr, transfer.value);
+            }
+        }
+        if result.is_err() {
+            self.invalidate();
+        }
+        result
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::CachingDAP;
+    use crate::access_port::consts::*;
+    use crate::dap_access::consts::*;
+    use crate::dap_access::{DAPAccess, MockDAP, Transfer, DEBUG_PORT};
+    use crate::memory_interface::MemoryInterface;
+    use crate::probes::adiv5::AdiV5;
+    use crate::probes::sim::SimulatedDap;
+    use crate::recording::RecordingDAP;
+
+    /// Returns the writes which reached the mock, one line per write.
+    fn writes(dap: CachingDAP<RecordingDAP<MockDAP, Vec<u8>>>) -> Vec<String> {
+        let log = dap.into_inner().into_inner().unwrap().1;
+        String::from_utf8(log).unwrap().lines().filter(|l| l.starts_with('W')).map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn skips_redundant_writes() {
+        let mut dap = CachingDAP::new(RecordingDAP::new(MockDAP::new(), vec![]));
+        let mi = MemoryInterface::new(0);
+        mi.write(&mut dap, 0x10, 0xDEADBEEF_u32).unwrap();
+        assert_eq!(mi.read::<u32>(&mut dap, 0x10).unwrap(), 0xDEADBEEF);
+        mi.write(&mut dap, 0x20, 0x1234_5678_u32).unwrap();
+        assert_eq!(writes(dap), vec![
+            "W 0000 00000000 23000052 OK",
+            "W 0000 00000004 00000010 OK",
+            "W 0000 0000000C DEADBEEF OK",
+            "W 0000 00000004 00000010 OK",
+            "W 0000 00000004 00000020 OK",
+            "W 0000 0000000C 12345678 OK",
+        ]);
+    }
+
+    #[test]
+    fn follows_tar_increment() {
+        let mut dap = CachingDAP::new(RecordingDAP::new(MockDAP::new(), vec![]));
+        let mi = MemoryInterface::new(0);
+        mi.write_block(&mut dap, 0x10, &[1_u32, 2, 3]).unwrap();
+        // TAR already points at the next word after each DRW write.
+        assert_eq!(writes(dap).iter().filter(|w| w.starts_with("W 0000 00000004")).count(), 1);
+    }
+
+    #[test]
+    fn invalidated_on_error_and_power_loss() {
+        let mut dap = CachingDAP::new(RecordingDAP::new(MockDAP::new(), vec![]));
+        dap.write_register(0, MEM_AP_CSW, CSW_VALUE | CSW_SIZE32).unwrap();
+        // The mock does not know IDR, so this fails.
+        assert!(dap.read_register(0, AP_IDR).is_err());
+        dap.write_register(0, MEM_AP_CSW, CSW_VALUE | CSW_SIZE32).unwrap();
+        assert_eq!(writes(dap).len(), 2);
+
+        let mut dap = CachingDAP::new(AdiV5::new(SimulatedDap::new(0x100)));
+        dap.write_register(DEBUG_PORT, DP_CTRL_STAT, CDBGPWRUPREQ | CSYSPWRUPREQ).unwrap();
+        dap.write_register(0, MEM_AP_TAR, 0x10).unwrap();
+        dap.read_register(DEBUG_PORT, DP_CTRL_STAT).unwrap();
+        assert!(dap.is_redundant(0, MEM_AP_TAR, 0x10));
+        dap.write_register(DEBUG_PORT, DP_CTRL_STAT, 0).unwrap();
+        assert!(!dap.is_redundant(0, MEM_AP_TAR, 0x10));
+    }
+
+    #[test]
+    fn invalidated_on_sticky_flags() {
+        let mut dap = CachingDAP::new(AdiV5::new(SimulatedDap::new(0x100)));
+        dap.write_register(DEBUG_PORT, DP_CTRL_STAT, CDBGPWRUPREQ | CSYSPWRUPREQ).unwrap();
+        dap.write_register(DEBUG_PORT, DP_SELECT, 0).unwrap();
+        dap.write_register(0, MEM_AP_TAR, 0x10).unwrap();
+        // A failed transfer on JTAG-DP, which only shows up in CTRL/STAT.
+        dap.inner().raw().set_sticky_error();
+        let mut transfers = [Transfer::read(DEBUG_PORT, DP_CTRL_STAT)];
+        dap.batch(&mut transfers).unwrap();
+        assert_ne!(transfers[0].value & CTRLSTAT_STICKYERR, 0);
+        assert!(!dap.is_redundant(0, MEM_AP_TAR, 0x10));
+        assert!(dap.is_redundant(DEBUG_PORT, DP_SELECT, 0));
+
+        dap.write_register(DEBUG_PORT, DP_ABORT, ABORT_STKERRCLR).unwrap();
+        dap.write_register(0, MEM_AP_TAR, 0x10).unwrap();
+        dap.read_register(DEBUG_PORT, DP_CTRL_STAT).unwrap();
+        assert!(dap.is_redundant(0, MEM_AP_TAR, 0x10));
+        dap.write_register(DEBUG_PORT, DP_ABORT, ABORT_STKERRCLR).unwrap();
+        assert!(!dap.is_redundant(0, MEM_AP_TAR, 0x10));
+
+        dap.write_register(0, MEM_AP_TAR, 0x10).unwrap();
+        dap.inner().raw().set_sticky_error();
+        dap.read_register(DEBUG_PORT, DP_CTRL_STAT).unwrap();
+        assert!(!dap.is_redundant(0, MEM_AP_TAR, 0x10));
+    }
+
+    #[test]
+    fn batch_skips_redundant_writes() {
+        let mut dap = CachingDAP::new(RecordingDAP::new(MockDAP::new(), vec![]));
+        dap.write_register(0, MEM_AP_CSW, CSW_VALUE | CSW_SIZE32).unwrap();
+        let mut transfers = [
+            Transfer::write(0, MEM_AP_CSW, CSW_VALUE | CSW_SIZE32),
+            Transfer::write(0, MEM_AP_TAR, 0x10),
+            Transfer::write(0, MEM_AP_DRW, 0xDEADBEEF),
+            Transfer::write(0, MEM_AP_TAR, 0x10),
+            Transfer::read(0, MEM_AP_DRW),
+        ];
+        dap.batch(&mut transfers).unwrap();
+        assert!(transfers.iter().all(|t| t.done));
+        assert_eq!(transfers[4].value, 0xDEADBEEF);
+        assert_eq!(writes(dap).len(), 4);
+    }
+}
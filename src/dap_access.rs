@@ -1,20 +1,218 @@
+use crate::access_port::consts::*;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
 
-    use crate::access_port::consts::*;
+/// Port number which addresses the debug port itself instead of an access port.
+pub const DEBUG_PORT: u16 = 0xFFFF;
+
+pub mod consts {
+    // DP register addresses.
+    pub const DP_IDCODE: u32 = 0x0; // read-only
+    pub const DP_ABORT: u32 = 0x0; // write-only
+    pub const DP_CTRL_STAT: u32 = 0x4; // read-write
+    pub const DP_SELECT: u32 = 0x8; // write-only
+    pub const DP_RDBUFF: u32 = 0xC; // read-only
+
+    // DP Abort Register bit definitions
+    pub const ABORT_DAPABORT: u32 = 0x00000001;
+    pub const ABORT_STKCMPCLR: u32 = 0x00000002;
+    pub const ABORT_STKERRCLR: u32 = 0x00000004;
+    pub const ABORT_WDERRCLR: u32 = 0x00000008;
+    pub const ABORT_ORUNERRCLR: u32 = 0x00000010;
+
+    // DP Control / Status Register bit definitions
+    pub const CTRLSTAT_ORUNDETECT: u32 = 0x00000001;
+    pub const CTRLSTAT_STICKYORUN: u32 = 0x00000002;
+    pub const CTRLSTAT_TRNMODE: u32 = 0x0000000C;
+    pub const CTRLSTAT_STICKYCMP: u32 = 0x00000010;
+    pub const CTRLSTAT_STICKYERR: u32 = 0x00000020;
+    pub const CTRLSTAT_READOK: u32 = 0x00000040;
+    pub const CTRLSTAT_WDATAERR: u32 = 0x00000080;
+
+    pub const CSYSPWRUPACK: u32 = 0x80000000;
+    pub const CDBGPWRUPACK: u32 = 0x20000000;
+    pub const CSYSPWRUPREQ: u32 = 0x40000000;
+    pub const CDBGPWRUPREQ: u32 = 0x10000000;
+
+    pub const TRNNORMAL: u32 = 0x00000000;
+    pub const TRNVERIFY: u32 = 0x00000004;
+    pub const TRNCOMPARE: u32 = 0x00000008;
+    pub const MASKLANE: u32 = 0x00000f00;
+
+    // DP SELECT bitfields
+    pub const SELECT_APSEL_SHIFT: u32 = 24;
+    pub const SELECT_APBANKSEL_MASK: u32 = 0x000000F0;
+    pub const SELECT_DPBANKSEL_MASK: u32 = 0x0000000F;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransferKind {
+    Read,
+    Write,
+}
+
+/// A single register transfer of a batch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transfer {
+    pub kind: TransferKind,
+    pub port: u16,
+    pub addr: u32,
+    /// The value to write, or the value read once the transfer is done.
+    pub value: u32,
+    /// Set once the transfer went through.
+    pub done: bool,
+}
+
+impl Transfer {
+    pub fn read(port: u16, addr: u32) -> Self {
+        Self {
+            kind: TransferKind::Read,
+            port,
+            addr,
+            value: 0,
+            done: false,
+        }
+    }
+
+    pub fn write(port: u16, addr: u32, value: u32) -> Self {
+        Self {
+            kind: TransferKind::Write,
+            port,
+            addr,
+            value,
+            done: false,
+        }
+    }
+}
+
+/// The cause of a failed transfer, as far as the caller can react to it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransferErrorKind {
+    /// The target answered WAIT until the probe gave up.
+    Wait,
+    /// The target answered FAULT, a sticky error flag is set in CTRL/STAT.
+    Fault,
+    /// The target answered with an invalid acknowledge.
+    Protocol,
+    /// The data read from the target had a parity error.
+    Parity,
+    /// Anything else, e.g. a failing probe connection.
+    Other,
+}
+
+impl fmt::Display for TransferErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            TransferErrorKind::Wait => "WAIT",
+            TransferErrorKind::Fault => "FAULT",
+            TransferErrorKind::Protocol => "protocol error",
+            TransferErrorKind::Parity => "parity error",
+            TransferErrorKind::Other => "probe error",
+        })
+    }
+}
+
+/// Implemented by the error types of DAP accesses so callers can tell the causes apart.
+///
+/// The errors end up as the `source` of the errors of the layers above.
+pub trait TransferError: Error + Send + Sync + 'static {
+    fn kind(&self) -> TransferErrorKind {
+        TransferErrorKind::Other
+    }
+}
+
+/// An error of a lower layer, kept as the source of an error of a higher one.
+///
+/// Clones share the error. Two are equal if they share the same error.
+#[derive(Debug, Clone)]
+pub struct SharedError(Arc<dyn Error + Send + Sync>);
+
+impl SharedError {
+    pub fn new(error: impl Error + Send + Sync + 'static) -> Self {
+        SharedError(Arc::new(error))
+    }
+
+    pub fn get(&self) -> &(dyn Error + Send + Sync + 'static) {
+        &*self.0
+    }
+}
+
+impl PartialEq for SharedError {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Display for SharedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// A failed access to a DP register.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugPortError {
+    /// The DP register, one of the `DP_*` constants.
+    pub addr: u32,
+    pub kind: TransferErrorKind,
+    pub source: SharedError,
+}
+
+impl DebugPortError {
+    pub fn new(addr: u32, error: impl TransferError) -> Self {
+        Self {
+            addr,
+            kind: error.kind(),
+            source: SharedError::new(error),
+        }
+    }
+}
+
+impl fmt::Display for DebugPortError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "access to DP register {:#04X} failed with {}", self.addr, self.kind)
+    }
+}
+
+impl Error for DebugPortError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.source.get())
+    }
+}
 
 pub trait DAPAccess {
-    type Error: std::fmt::Debug;
+    type Error: TransferError;
 
     /// Reads the DAP register on the specified port and address
     fn read_register(&mut self, port: u16, addr: u32) -> Result<u32, Self::Error>;
 
     /// Writes a value to the DAP register on the specified port and address
     fn write_register(&mut self, port: u16, addr: u32, value: u32) -> Result<(), Self::Error>;
+
+    /// Runs a batch of transfers in order and marks each one as done.
+    ///
+    /// Stops at the first failing transfer. Probes which can queue transfers should
+    /// override this to save round trips.
+    fn batch(&mut self, transfers: &mut [Transfer]) -> Result<(), Self::Error> {
+        for transfer in transfers.iter_mut() {
+            match transfer.kind {
+                TransferKind::Read => transfer.value = self.read_register(transfer.port, transfer.addr)?,
+                TransferKind::Write => self.write_register(transfer.port, transfer.addr, transfer.value)?,
+            }
+            transfer.done = true;
+        }
+        Ok(())
+    }
 }
 
 pub struct MockDAP {
     pub data: Vec<u8>,
     width: u32,
     address: u32,
+    big_endian: bool,
 }
 
 #[derive(Debug)]
@@ -23,14 +221,45 @@ pub enum MockError {
     BadInstruction,
 }
 
+impl fmt::Display for MockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MockError::BadWidth => f.write_str("unsupported access width"),
+            MockError::BadInstruction => f.write_str("unsupported register"),
+        }
+    }
+}
+
+impl Error for MockError {}
+
+impl TransferError for MockError {}
+
 impl MockDAP {
     pub fn new() -> Self {
         Self {
             data: vec![0; 256],
             width: 4,
             address: 0,
+            big_endian: false,
         }
     }
+
+    /// Emulates a big-endian bus, the MEM-AP reports it in CFG.BE.
+    pub fn set_big_endian(&mut self, big_endian: bool) {
+        self.big_endian = big_endian;
+    }
+
+    /// Returns the position of the byte at `address` in DRW.
+    fn byte_shift(&self, address: usize) -> u32 {
+        let lane = (address & 0x3) as u32;
+        if self.big_endian { (3 - lane) * 8 } else { lane * 8 }
+    }
+}
+
+impl Default for MockDAP {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DAPAccess for MockDAP {
@@ -51,21 +280,11 @@ impl DAPAccess for MockDAP {
         } else if addr == MEM_AP_TAR {
             Ok(self.address)
         } else if addr == MEM_AP_DRW {
-            if self.width == 4 {
-                Ok(
-                    self.data[self.address as usize + 0] as u32 |
-                    ((self.data[self.address as usize + 1] as u32) << 08) |
-                    ((self.data[self.address as usize + 2] as u32) << 16) |
-                    ((self.data[self.address as usize + 3] as u32) << 24)
-                )
-            } else if self.width == 2 {
-                Ok(
-                    self.data[self.address as usize + 0] as u32 |
-                    ((self.data[self.address as usize + 1] as u32) << 08)
-                )
-            } else {
-                Ok(self.data[self.address as usize + 0] as u32)
-            }
+            let address = self.address as usize;
+            // Each byte goes onto the byte lane of its address, like on an AHB.
+            Ok((address..address + self.width as usize).fold(0, |acc, a| acc | (self.data[a] as u32) << self.byte_shift(a)))
+        } else if addr == MEM_AP_CFG {
+            Ok(if self.big_endian { CFG_BE } else { 0 })
         } else {
             Err(MockError::BadInstruction)
         }
@@ -90,16 +309,9 @@ impl DAPAccess for MockDAP {
             self.address = value;
             Ok(())
         } else if addr == MEM_AP_DRW {
-            if self.width == 4 {
-                self.data[self.address as usize + 0] = (value >> 00) as u8;
-                self.data[self.address as usize + 1] = (value >> 08) as u8;
-                self.data[self.address as usize + 2] = (value >> 16) as u8;
-                self.data[self.address as usize + 3] = (value >> 24) as u8;
-            } else if self.width == 2 {
-                self.data[self.address as usize + 0] = (value >> 00) as u8;
-                self.data[self.address as usize + 1] = (value >> 08) as u8;
-            } else {
-                self.data[self.address as usize + 0] = (value >> 00) as u8;
+            let address = self.address as usize;
+            for a in address..address + self.width as usize {
+                self.data[a] = (value >> self.byte_shift(a)) as u8;
             }
             Ok(())
         } else {
@@ -1,8 +1,23 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
 // mod component;
 // mod debug_port;
 pub mod access_port;
+pub mod async_access;
+pub mod cache;
+#[cfg(feature = "std")]
+pub mod memory_cursor;
 pub mod memory_interface;
+pub mod memory_tools;
+pub mod pipeline;
 // mod access_ports;
 
 pub mod dap_access;
+pub mod probes;
+#[cfg(feature = "std")]
+pub mod recording;
+pub mod target_struct;
+pub mod trace;
 pub mod target;
\ No newline at end of file
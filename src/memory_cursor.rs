@@ -0,0 +1,151 @@
+use crate::dap_access::DAPAccess;
+use crate::memory_interface::MemoryInterface;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+/// Reads and writes target memory in `range` through `std::io`.
+///
+/// Positions count from the start of the range. Like `io::Cursor`, the cursor can be moved
+/// past the end, where reads return nothing and writes are refused. Failed accesses are
+/// reported as `io::Error` of kind `Other` wrapping the `AccessPortError`, or `TimedOut`.
+pub struct TargetMemoryCursor<'a, D: DAPAccess> {
+    interface: &'a MemoryInterface,
+    debug_port: &'a mut D,
+    range: Range<u64>,
+    position: u64,
+}
+
+impl<'a, D: DAPAccess> TargetMemoryCursor<'a, D> {
+    pub fn new(interface: &'a MemoryInterface, debug_port: &'a mut D, range: Range<u64>) -> Self {
+        Self {
+            interface,
+            debug_port,
+            range,
+            position: 0,
+        }
+    }
+
+    pub fn range(&self) -> Range<u64> {
+        self.range.clone()
+    }
+
+    /// Returns the position relative to the start of the range.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: u64) {
+        self.position = position;
+    }
+
+    /// Returns the target address at the position, `None` past the end of the range.
+    pub fn address(&self) -> Option<u64> {
+        Some(self.range.start + self.position).filter(|addr| *addr < self.range.end)
+    }
+
+    /// Returns how many bytes are left between the position and the end of the range.
+    fn remaining(&self) -> usize {
+        self.range.end.saturating_sub(self.range.start).saturating_sub(self.position) as usize
+    }
+}
+
+impl<'a, D: DAPAccess> Read for TargetMemoryCursor<'a, D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.remaining());
+        if len == 0 {
+            return Ok(0);
+        }
+        let addr = self.range.start + self.position;
+        self.interface.read_bytes(self.debug_port, addr, &mut buf[..len])?;
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<'a, D: DAPAccess> Write for TargetMemoryCursor<'a, D> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.remaining());
+        if len == 0 {
+            return Ok(0);
+        }
+        let addr = self.range.start + self.position;
+        self.interface.write_bytes(self.debug_port, addr, &buf[..len])?;
+        self.position += len as u64;
+        Ok(len)
+    }
+
+    /// Writes go out at once, there is nothing to flush.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a, D: DAPAccess> Seek for TargetMemoryCursor<'a, D> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match position {
+            SeekFrom::Start(offset) => {
+                self.position = offset;
+                return Ok(offset);
+            },
+            SeekFrom::End(offset) => (self.range.end.saturating_sub(self.range.start), offset),
+            SeekFrom::Current(offset) => (self.position, offset),
+        };
+        match base.checked_add_signed(offset) {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            },
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative or overflowing position")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::memory_interface::MemoryInterface;
+    use crate::probes::adiv5::AdiV5;
+    use crate::probes::sim::SimulatedDap;
+    use std::io::{self, Read, Seek, SeekFrom, Write};
+
+    #[test]
+    fn read_write_seek() {
+        let mut sim = SimulatedDap::new(0x100);
+        sim.memory[0x10..0x20].copy_from_slice(b"0123456789ABCDEF");
+        let mut dap = AdiV5::new(sim);
+        let mi = MemoryInterface::new(0x0);
+        let mut cursor = mi.cursor(&mut dap, 0x13..0x1D);
+
+        let mut data = Vec::new();
+        io::copy(&mut cursor, &mut data).unwrap();
+        assert_eq!(data, b"3456789ABC");
+        assert_eq!(cursor.position(), 10);
+        assert_eq!(cursor.address(), None);
+        assert_eq!(cursor.read(&mut [0; 4]).unwrap(), 0);
+
+        assert_eq!(cursor.seek(SeekFrom::End(-3)).unwrap(), 7);
+        assert_eq!(cursor.address(), Some(0x1A));
+        assert_eq!(cursor.write(b"abcdef").unwrap(), 3);
+        assert_eq!(cursor.write_all(b"x").unwrap_err().kind(), io::ErrorKind::WriteZero);
+        assert_eq!(cursor.seek(SeekFrom::Current(-10)).unwrap(), 0);
+        assert_eq!(cursor.seek(SeekFrom::Current(-1)).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        cursor.write_all(b"-").unwrap();
+
+        let mut data = [0; 4];
+        cursor.seek(SeekFrom::Start(5)).unwrap();
+        cursor.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"89ab");
+        assert_eq!(dap.raw().memory[0x10..0x20], *b"012-456789abcDEF");
+    }
+
+    #[test]
+    fn fault() {
+        let mut dap = AdiV5::new(SimulatedDap::new(0x100));
+        let mi = MemoryInterface::new(0x0);
+        let mut cursor = mi.cursor(&mut dap, 0xF0..0x110);
+        cursor.seek(SeekFrom::Start(0x10)).unwrap();
+        let error = cursor.read(&mut [0; 4]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Other);
+        assert_eq!(error.to_string(), "AP 0: fault at 0x00000100 (CTRL/STAT 0x00000020)");
+        assert_eq!(cursor.position(), 0x10);
+    }
+}
@@ -1,200 +1,1515 @@
 use crate::access_port::{
     AccessPortNumber,
-    AccessPortError
+    AccessPortError,
+    Mismatch
 };
 use crate::access_port::consts::*;
-use crate::dap_access::DAPAccess;
+use crate::async_access::{AsyncDAPAccess, AsyncMemory};
+use crate::dap_access::consts::*;
+use crate::dap_access::{DAPAccess, DebugPortError, SharedError, Transfer, TransferError, TransferErrorKind, DEBUG_PORT};
+#[cfg(feature = "std")]
+use crate::memory_cursor::TargetMemoryCursor;
+use crate::memory_tools::MemoryTools;
+use crate::target_struct::{Endian, TargetStruct};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::time::Duration;
 
-pub enum MemoryReadSize {
-    U8 = CSW_SIZE8 as isize,
-    U16 = CSW_SIZE16 as isize,
-    U32 = CSW_SIZE32 as isize,
-}
+/// How many pushed compares `wait_for_value` batches before it checks for a match.
+pub(crate) const POLL_BATCH_SIZE: usize = 16;
+
+/// The CTRL/STAT flags which stay set after a failed transfer until they are cleared.
+const CTRLSTAT_STICKY_FLAGS: u32 = CTRLSTAT_STICKYORUN | CTRLSTAT_STICKYCMP | CTRLSTAT_STICKYERR | CTRLSTAT_WDATAERR;
+
+/// The most DRW accesses a single memory access takes, for 256-bit accesses.
+pub(crate) const MAX_WORD_COUNT: usize = 8;
+
+/// A value which memory accesses read and write as a whole.
+///
+/// Values go through DRW as 32-bit words. Values of up to a word sit in the low bits of
+/// the first word, wider ones are split least significant word first. Signed and float
+/// values are transferred as their bit patterns.
+pub trait ToMemoryReadSize: Sized {
+    /// The size in bytes, which is also the alignment an access needs.
+    const SIZE: usize;
+
+    /// The CSW.Size of an access of this size.
+    const CSW_SIZE: u32 = Self::SIZE.trailing_zeros();
+
+    /// The number of DRW accesses which make up one access.
+    ///
+    /// Accesses wider than 32 bits go through DRW one word after the other, with the
+    /// large data extension as a single access.
+    const WORD_COUNT: usize = if Self::SIZE > 4 { Self::SIZE / 4 } else { 1 };
+
+    /// Builds the value from the first `WORD_COUNT` words.
+    fn from_words(words: &[u32]) -> Self;
+
+    /// Stores the value into the first `WORD_COUNT` words.
+    fn to_words(&self, words: &mut [u32]);
 
-pub trait ToMemoryReadSize {
-    fn to_alignment_mask() -> u32;
-    fn to_memory_read_size() -> u32;
-    fn to_result(value: u32) -> Self;
-    fn to_input(value: &Self) -> u32;
+    /// Returns the mask of the address bits which have to be clear for an access of this value.
+    fn alignment_mask(&self) -> u64 {
+        Self::SIZE as u64 - 1
+    }
 }
 
-impl ToMemoryReadSize for u32 {
-    fn to_alignment_mask() -> u32 {
-        0x3
+/// A 256-bit value as its words, least significant first.
+impl ToMemoryReadSize for [u32; 8] {
+    const SIZE: usize = 32;
+
+    fn from_words(words: &[u32]) -> Self {
+        let mut value = [0; 8];
+        value.copy_from_slice(&words[..8]);
+        value
     }
 
-    fn to_memory_read_size() -> u32 {
-        CSW_SIZE32
+    fn to_words(&self, words: &mut [u32]) {
+        words[..8].copy_from_slice(self);
     }
+}
 
-    fn to_result(value: u32) -> Self {
-        value
+impl ToMemoryReadSize for u128 {
+    const SIZE: usize = 16;
+
+    fn from_words(words: &[u32]) -> Self {
+        words[..4].iter().rev().fold(0, |acc, word| (acc << 32) | *word as u128)
     }
 
-    fn to_input(value: &Self) -> u32 {
-        *value
+    fn to_words(&self, words: &mut [u32]) {
+        for (i, word) in words[..4].iter_mut().enumerate() {
+            *word = (*self >> (i * 32)) as u32;
+        }
     }
 }
 
-impl ToMemoryReadSize for u16 {
-    fn to_alignment_mask() -> u32 {
-        0x1
+impl ToMemoryReadSize for u64 {
+    const SIZE: usize = 8;
+
+    fn from_words(words: &[u32]) -> Self {
+        ((words[1] as u64) << 32) | words[0] as u64
     }
 
-    fn to_memory_read_size() -> u32 {
-        CSW_SIZE16
+    fn to_words(&self, words: &mut [u32]) {
+        words[0] = *self as u32;
+        words[1] = (*self >> 32) as u32;
     }
+}
+
+macro_rules! impl_narrow {
+    ($($ty:ty),*) => {
+        $(
+            impl ToMemoryReadSize for $ty {
+                const SIZE: usize = core::mem::size_of::<$ty>();
+
+                fn from_words(words: &[u32]) -> Self {
+                    words[0] as $ty
+                }
+
+                fn to_words(&self, words: &mut [u32]) {
+                    words[0] = *self as u32;
+                }
+            }
+        )*
+    };
+}
+
+impl_narrow!(u32, u16, u8);
+
+/// Implements `ToMemoryReadSize` for a type with the bit pattern of an unsigned one.
+macro_rules! impl_bits {
+    ($($ty:ty => $bits:ty, $from:expr, $to:expr;)*) => {
+        $(
+            impl ToMemoryReadSize for $ty {
+                const SIZE: usize = <$bits as ToMemoryReadSize>::SIZE;
+
+                fn from_words(words: &[u32]) -> Self {
+                    $from(<$bits as ToMemoryReadSize>::from_words(words))
+                }
+
+                fn to_words(&self, words: &mut [u32]) {
+                    $to(*self).to_words(words)
+                }
+            }
+        )*
+    };
+}
+
+impl_bits! {
+    i8 => u8, |bits| bits as i8, |value| value as u8;
+    i16 => u16, |bits| bits as i16, |value| value as u16;
+    i32 => u32, |bits| bits as i32, |value| value as u32;
+    i64 => u64, |bits| bits as i64, |value| value as u64;
+    i128 => u128, |bits| bits as i128, |value| value as u128;
+    f32 => u32, f32::from_bits, f32::to_bits;
+    f64 => u64, f64::from_bits, f64::to_bits;
+}
+
+/// Decides how often a failed memory access is repeated.
+///
+/// Retries repeat the whole access, e.g. a complete `write_block`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Retries after the target kept answering WAIT.
+    pub wait_retries: u32,
+    /// Retries after a FAULT. The sticky error flags are always cleared first.
+    pub fault_retries: u32,
+    /// Retries after protocol and parity errors.
+    pub error_retries: u32,
+    /// Pause before each retry, measured by the clock of the `MemoryInterface`. Async accesses
+    /// wait with the `AsyncSleep` set with `set_async_sleep` instead, or not at all without one.
+    pub delay: Duration,
+}
 
-    fn to_result(value: u32) -> Self {
-        value as u16
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            wait_retries: 3,
+            fault_retries: 0,
+            error_retries: 0,
+            delay: Duration::from_millis(1),
+        }
     }
+}
+
+/// The time source for the timeout of `wait_for_value` and the delay between retries.
+///
+/// Without the `std` feature there is no default clock. Retries then follow each other
+/// at once and timeouts expire after the first poll, unless a clock is set with `set_clock`.
+#[derive(Debug, Clone, Copy)]
+pub struct Clock {
+    /// Returns the time passed since some fixed point.
+    pub now: fn() -> Duration,
+    /// Waits for the given time.
+    pub sleep: fn(Duration),
+}
 
-    fn to_input(value: &Self) -> u32 {
-        *value as u32
+#[cfg(feature = "std")]
+impl Clock {
+    /// Measures time with `std::time::Instant` and waits with `std::thread::sleep`.
+    pub fn std() -> Self {
+        fn now() -> Duration {
+            static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+            START.get_or_init(std::time::Instant::now).elapsed()
+        }
+        Self { now, sleep: std::thread::sleep }
     }
 }
 
-impl ToMemoryReadSize for u8 {
-    fn to_alignment_mask() -> u32 {
-        0x0
+/// Returns a future which waits for the given time, for the delay between retries of async
+/// accesses, e.g. `|delay| Box::pin(tokio::time::sleep(delay))`.
+pub type AsyncSleep = fn(Duration) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// The retries used up so far by an access.
+#[derive(Debug, Default)]
+pub(crate) struct Retries {
+    waits: u32,
+    faults: u32,
+    errors: u32,
+}
+
+/// Decides whether writes are checked after they went through.
+///
+/// A failed check makes the write return `AccessPortError::VerifyFailed` with the bytes
+/// which differ. Retries do not apply to it.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum VerifyPolicy {
+    /// Writes are not checked.
+    #[default]
+    Off,
+    /// Every write is read back and compared.
+    ReadBack,
+    /// Writes are checked with pushed verify, which compares on the target without
+    /// sending the data back. Only a mismatch is read back to report it.
+    PushedVerify,
+}
+
+/// What a MEM-AP implements, as found by `MemoryInterface::probe`.
+///
+/// The default describes an AHB-AP with byte, halfword and word accesses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemApCapabilities {
+    /// The bus behind the MEM-AP from IDR, one of the `AP_TYPE_*` constants.
+    pub ap_type: u8,
+    /// CFG.BE, the memory is big-endian.
+    pub big_endian: bool,
+    /// CFG.LA, TAR and BASE have an upper half for addresses above 4 GiB.
+    pub large_address: bool,
+    /// CFG.LD, accesses wider than 32 bits are supported.
+    pub large_data: bool,
+    /// The accepted CSW.SIZE values, bit n is set for CSW.SIZE n.
+    pub sizes: u8,
+    /// CSW.AddrInc accepts packed transfers.
+    pub packed_transfers: bool,
+    /// The size of the block TAR auto-increments within, it wraps around at its end.
+    pub tar_wrap: u32,
+    /// The HPROT bits of CSW.Prot can be changed.
+    pub hprot_writable: bool,
+    /// The HNONSEC bit of CSW.Prot can be changed.
+    pub hnonsec_writable: bool,
+    /// The value CSW is programmed with, apart from SIZE and ADDRINC.
+    pub csw: u32,
+}
+
+impl MemApCapabilities {
+    pub fn supports_size(&self, size: u32) -> bool {
+        size < 8 && self.sizes & (1 << size) != 0
     }
+}
 
-    fn to_memory_read_size() -> u32 {
-        CSW_SIZE8
+impl Default for MemApCapabilities {
+    fn default() -> Self {
+        Self {
+            ap_type: AP_TYPE_AHB,
+            big_endian: false,
+            large_address: false,
+            large_data: false,
+            sizes: (1 << CSW_SIZE8) | (1 << CSW_SIZE16) | (1 << CSW_SIZE32),
+            packed_transfers: false,
+            tar_wrap: 0x400,
+            hprot_writable: true,
+            hnonsec_writable: false,
+            csw: CSW_VALUE & !CSW_ADDRINC,
+        }
     }
+}
+
+/// The bus attributes memory accesses are made with.
+///
+/// They are programmed into CSW in the layout of the bus behind the MEM-AP, see
+/// `MemApCapabilities::ap_type`. APB-APs ignore them. The default makes privileged data
+/// accesses as the debugger, in the secure state if the target allows it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AccessAttributes {
+    /// Privileged instead of user access, HPROT[1] or AxPROT[0].
+    pub privileged: bool,
+    /// Data access instead of instruction fetch, HPROT[0] or the inverse of AxPROT[2].
+    pub data: bool,
+    /// Non-secure access, HNONSEC or AxPROT[1].
+    pub non_secure: bool,
+    /// Bufferable access, HPROT[2]. AXI-APs take `axi_cache` instead.
+    pub bufferable: bool,
+    /// Cacheable access, HPROT[3]. AXI-APs take `axi_cache` instead.
+    pub cacheable: bool,
+    /// The access comes from the debugger instead of the core. Only on AHB-APs with MasterType.
+    pub debug_master: bool,
+    /// AxCACHE for AXI-APs.
+    pub axi_cache: u8,
+    /// AxDOMAIN for AXI-APs with ACE-Lite, `None` makes non-coherent accesses.
+    pub axi_domain: Option<u8>,
+}
 
-    fn to_result(value: u32) -> Self {
-        value as u8
+impl AccessAttributes {
+    /// Returns the CSW bits for an AP of `ap_type` and the mask of the bits they replace.
+    pub fn to_csw(&self, ap_type: u8) -> (u32, u32) {
+        let bit = |set: bool, bit: u32| if set { bit } else { 0 };
+        match ap_type {
+            AP_TYPE_AHB | AP_TYPE_AHB5 => {
+                let bits = bit(self.data, CSW_HPROT_DATA)
+                    | bit(self.privileged, CSW_HPROT_PRIVILEGED)
+                    | bit(self.bufferable, CSW_HPROT_BUFFERABLE)
+                    | bit(self.cacheable, CSW_HPROT_CACHEABLE)
+                    | bit(self.debug_master, CSW_MSTRDBG)
+                    | bit(self.non_secure, CSW_HNONSEC);
+                let mask = CSW_HPROT_DATA | CSW_HPROT_PRIVILEGED | CSW_HPROT_BUFFERABLE | CSW_HPROT_CACHEABLE | CSW_MSTRTYPE | CSW_HNONSEC;
+                (bits, mask)
+            },
+            AP_TYPE_AXI => {
+                let domain = match self.axi_domain {
+                    Some(domain) => CSW_AXI_ACE_ENABLE | ((domain as u32) << CSW_AXI_DOMAIN_SHIFT) & CSW_AXI_DOMAIN_MASK,
+                    None => 0,
+                };
+                let bits = bit(self.privileged, CSW_AXI_PRIVILEGED)
+                    | bit(self.non_secure, CSW_AXI_NONSECURE)
+                    | bit(!self.data, CSW_AXI_INSTRUCTION)
+                    | ((self.axi_cache as u32) << CSW_AXI_CACHE_SHIFT) & CSW_AXI_CACHE_MASK
+                    | domain;
+                let mask = CSW_AXI_PRIVILEGED | CSW_AXI_NONSECURE | CSW_AXI_INSTRUCTION | CSW_AXI_CACHE_MASK
+                    | CSW_AXI_DOMAIN_MASK | CSW_AXI_ACE_ENABLE;
+                (bits, mask)
+            },
+            _ => (0, 0),
+        }
     }
+}
 
-    fn to_input(value: &Self) -> u32 {
-        *value as u32
+impl Default for AccessAttributes {
+    fn default() -> Self {
+        Self {
+            privileged: true,
+            data: true,
+            non_secure: false,
+            bufferable: false,
+            cacheable: false,
+            debug_master: true,
+            axi_cache: 0,
+            axi_domain: None,
+        }
     }
 }
 
+#[derive(Clone)]
 pub struct MemoryInterface {
     access_port: AccessPortNumber,
+    retry_policy: RetryPolicy,
+    verify_policy: VerifyPolicy,
+    capabilities: MemApCapabilities,
+    attributes: AccessAttributes,
+    clock: Option<Clock>,
+    async_sleep: Option<AsyncSleep>,
 }
 
 impl MemoryInterface {
 
     pub fn new(access_port: AccessPortNumber) -> Self {
         Self {
-            access_port
+            access_port,
+            retry_policy: RetryPolicy::default(),
+            verify_policy: VerifyPolicy::default(),
+            capabilities: MemApCapabilities::default(),
+            attributes: AccessAttributes::default(),
+            #[cfg(feature = "std")]
+            clock: Some(Clock::std()),
+            #[cfg(not(feature = "std"))]
+            clock: None,
+            async_sleep: None,
         }
     }
 
-    fn read_reg(&self, debug_port: &mut impl DAPAccess, addr: u32) -> Result<u32, AccessPortError> {
-        debug_port.read_register(self.access_port, addr).or_else(|e| { println!("{:?}", e); Err(e) }).or_else(|_| Err(AccessPortError::ProbeError))
+    pub fn access_port(&self) -> AccessPortNumber {
+        self.access_port
+    }
+
+    pub fn attributes(&self) -> &AccessAttributes {
+        &self.attributes
+    }
+
+    /// Sets the bus attributes for all further accesses.
+    pub fn set_attributes(&mut self, attributes: AccessAttributes) {
+        self.attributes = attributes;
+    }
+
+    /// Returns a copy of this interface which accesses memory with `attributes`,
+    /// e.g. `mi.with_attributes(non_secure).read::<u32>(dap, addr)`.
+    pub fn with_attributes(&self, attributes: AccessAttributes) -> Self {
+        Self {
+            attributes,
+            ..self.clone()
+        }
+    }
+
+    pub fn clock(&self) -> Option<Clock> {
+        self.clock
+    }
+
+    /// Sets the time source for timeouts and retry delays, `None` for none.
+    pub fn set_clock(&mut self, clock: Option<Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn async_sleep(&self) -> Option<AsyncSleep> {
+        self.async_sleep
     }
 
-    fn write_reg(&self, debug_port: &mut impl DAPAccess, addr: u32, data: u32) -> Result<(), AccessPortError> {
-        debug_port.write_register(self.access_port, addr, data).or_else(|_| Err(AccessPortError::ProbeError))
+    /// Sets how async accesses wait between retries, `None` to retry at once.
+    pub fn set_async_sleep(&mut self, async_sleep: Option<AsyncSleep>) {
+        self.async_sleep = async_sleep;
     }
 
-    pub fn read<S: ToMemoryReadSize>(&self, debug_port: &mut impl DAPAccess, addr: u32) -> Result<S, AccessPortError> {
-        if (addr & S::to_alignment_mask()) == 0 {
-            self.write_reg(debug_port, MEM_AP_CSW, CSW_VALUE | S::to_memory_read_size() as u32)?;
-            self.write_reg(debug_port, MEM_AP_TAR, addr)?;
-            let result = self.read_reg(debug_port, MEM_AP_DRW)?;
-            Ok(S::to_result(result))
+    /// Returns whether `timeout` passed since `start`, a time taken from the clock.
+    pub(crate) fn expired(&self, start: Option<Duration>, timeout: Duration) -> bool {
+        match (self.clock, start) {
+            (Some(clock), Some(start)) => (clock.now)().saturating_sub(start) >= timeout,
+            _ => true,
+        }
+    }
+
+    pub fn capabilities(&self) -> &MemApCapabilities {
+        &self.capabilities
+    }
+
+    /// Overrides the capabilities, e.g. for a MEM-AP which is known not to support probing.
+    pub fn set_capabilities(&mut self, capabilities: MemApCapabilities) {
+        self.capabilities = capabilities;
+    }
+
+    /// Finds out what the MEM-AP implements from CFG and by writing CSW and reading it back.
+    ///
+    /// CSW is restored afterwards. The found capabilities are used for all further accesses,
+    /// apart from `tar_wrap`, which takes memory accesses to find, see `probe_tar_wrap`.
+    pub fn probe(&mut self, debug_port: &mut impl DAPAccess) -> Result<MemApCapabilities, AccessPortError> {
+        let idr = self.read_reg(debug_port, AP_IDR)?;
+        let cfg = self.read_reg(debug_port, MEM_AP_CFG)?;
+        let csw = self.read_reg(debug_port, MEM_AP_CSW)? & !(CSW_DBGSTAT | CSW_TINPROG);
+        let mut try_csw = |value: u32| -> Result<u32, AccessPortError> {
+            self.write_reg(debug_port, MEM_AP_CSW, value)?;
+            self.read_reg(debug_port, MEM_AP_CSW)
+        };
+
+        let mut sizes = 0;
+        for size in CSW_SIZE8..=CSW_SIZE256 {
+            if try_csw((csw & !CSW_SIZE) | size)? & CSW_SIZE == size {
+                sizes |= 1 << size;
+            }
+        }
+        let packed_transfers = try_csw((csw & !CSW_ADDRINC) | CSW_PADDRINC)? & CSW_ADDRINC == CSW_PADDRINC;
+        let changed = try_csw(csw ^ (CSW_HPROT_MASK | CSW_HNONSEC))? ^ csw;
+        self.write_reg(debug_port, MEM_AP_CSW, csw)?;
+
+        self.capabilities = MemApCapabilities {
+            ap_type: (idr & AP_IDR_TYPE_MASK) as u8,
+            big_endian: cfg & CFG_BE != 0,
+            large_address: cfg & CFG_LA != 0,
+            large_data: cfg & CFG_LD != 0,
+            sizes,
+            packed_transfers,
+            tar_wrap: self.capabilities.tar_wrap,
+            hprot_writable: changed & CSW_HPROT_MASK != 0,
+            hnonsec_writable: changed & CSW_HNONSEC != 0,
+            csw: csw & !(CSW_SIZE | CSW_ADDRINC),
+        };
+        Ok(self.capabilities)
+    }
+
+    /// Finds out how far TAR auto-increments by reading across the 1 and 2 KiB boundaries after `addr`.
+    ///
+    /// `addr` has to be 4 KiB aligned and the 2 KiB from there readable. Anything beyond
+    /// 2 KiB is reported as 4 KiB, the largest wrap size the architecture allows.
+    pub fn probe_tar_wrap(&mut self, debug_port: &mut impl DAPAccess, addr: u64) -> Result<u32, AccessPortError> {
+        if addr & 0xFFF != 0 {
+            return Err(AccessPortError::MemoryNotAligned { ap: self.access_port, address: addr, size: 0x1000 });
+        }
+        self.write_reg(debug_port, MEM_AP_CSW, self.csw(CSW_SIZE32))?;
+        let mut wrap = 0x400;
+        while wrap < 0x1000 {
+            self.write_tar(debug_port, addr + wrap as u64 - 4)?;
+            self.read_reg(debug_port, MEM_AP_DRW)?;
+            if self.read_reg(debug_port, MEM_AP_TAR)? as u64 != (addr + wrap as u64) & 0xFFFF_FFFF {
+                break;
+            }
+            wrap *= 2;
+        }
+        self.capabilities.tar_wrap = wrap;
+        Ok(wrap)
+    }
+
+    /// Reads CFG.BE and switches the byte lanes of all accesses to match, without a full `probe`.
+    pub fn detect_endianness(&mut self, debug_port: &mut impl DAPAccess) -> Result<bool, AccessPortError> {
+        let cfg = self.read_reg(debug_port, MEM_AP_CFG)?;
+        self.capabilities.big_endian = cfg & CFG_BE != 0;
+        Ok(self.capabilities.big_endian)
+    }
+
+    /// Returns the CSW value with the access attributes applied, without SIZE and ADDRINC.
+    fn csw_base(&self) -> u32 {
+        let (bits, mask) = self.attributes.to_csw(self.capabilities.ap_type);
+        (self.capabilities.csw & !mask) | bits
+    }
+
+    /// Returns the CSW value for accesses of `size` with TAR auto-increment.
+    pub(crate) fn csw(&self, size: u32) -> u32 {
+        self.csw_base() | CSW_SADDRINC | size
+    }
+
+    /// Returns the CSW value for polling a word, without TAR auto-increment.
+    pub(crate) fn poll_csw(&self) -> u32 {
+        self.csw_base() | CSW_NADDRINC | CSW_SIZE32
+    }
+
+    /// Checks that `addr` is aligned for accesses of type `S` and returns the CSW.Size to access it with.
+    ///
+    /// Without native accesses of that size, wider values go through as several word
+    /// accesses and narrower ones as accesses of the word around them. Neither is atomic.
+    pub(crate) fn access_size<S: ToMemoryReadSize>(&self, addr: u64) -> Result<u32, AccessPortError> {
+        if (addr & (S::SIZE as u64 - 1)) != 0 {
+            return Err(AccessPortError::MemoryNotAligned { ap: self.access_port, address: addr, size: S::SIZE });
+        }
+        if self.capabilities.supports_size(S::CSW_SIZE) {
+            Ok(S::CSW_SIZE)
+        } else if self.capabilities.supports_size(CSW_SIZE32) {
+            Ok(CSW_SIZE32)
         } else {
-            Err(AccessPortError::MemoryNotAligned)
+            Err(AccessPortError::UnsupportedAccessSize { ap: self.access_port, size: S::SIZE })
+        }
+    }
+
+    /// Returns the address of the ROM table or debug component the MEM-AP points to.
+    ///
+    /// BASE upper is included if the MEM-AP supports large physical addresses.
+    pub fn base_address(&self, debug_port: &mut impl DAPAccess) -> Result<Option<u64>, AccessPortError> {
+        let base = self.read_reg(debug_port, AP_BASE)?;
+        let upper = if self.capabilities.large_address { self.read_reg(debug_port, AP_BASE_UPPER)? } else { 0 };
+        if base == BASE_NOT_PRESENT || (base & BASE_FORMAT != 0 && base & BASE_PRESENT == 0) {
+            return Ok(None);
+        }
+        Ok(Some(((upper as u64) << 32) | (base & BASE_ADDR_MASK) as u64))
+    }
+
+    /// Splits `addr` into the values for TAR upper and TAR.
+    fn split_address(&self, addr: u64) -> Result<(u32, u32), AccessPortError> {
+        let upper = (addr >> 32) as u32;
+        if upper != 0 && !self.capabilities.large_address {
+            return Err(AccessPortError::AddressTooLarge { ap: self.access_port, address: addr });
+        }
+        Ok((upper, addr as u32))
+    }
+
+    fn write_tar<D: DAPAccess>(&self, debug_port: &mut D, addr: u64) -> Result<(), AccessPortError> {
+        let (upper, lower) = self.split_address(addr)?;
+        if self.capabilities.large_address {
+            self.write_reg(debug_port, MEM_AP_TAR_UPPER, upper)?;
+        }
+        self.write_reg(debug_port, MEM_AP_TAR, lower)
+    }
+
+    /// Returns the transfers which write `addr` to TAR.
+    pub(crate) fn tar_transfers(&self, addr: u64) -> Result<Vec<Transfer>, AccessPortError> {
+        let (upper, lower) = self.split_address(addr)?;
+        let mut transfers = vec![];
+        if self.capabilities.large_address {
+            transfers.push(Transfer::write(self.access_port, MEM_AP_TAR_UPPER, upper));
+        }
+        transfers.push(Transfer::write(self.access_port, MEM_AP_TAR, lower));
+        Ok(transfers)
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy
+    }
+
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
+    pub fn verify_policy(&self) -> VerifyPolicy {
+        self.verify_policy
+    }
+
+    pub fn set_verify_policy(&mut self, verify_policy: VerifyPolicy) {
+        self.verify_policy = verify_policy;
+    }
+
+    /// Maps an error of the DAP to an `AccessPortError` without touching the target.
+    pub(crate) fn classify(&self, error: impl TransferError) -> AccessPortError {
+        let ap = self.access_port;
+        let kind = error.kind();
+        let source = SharedError::new(error);
+        match kind {
+            TransferErrorKind::Wait => AccessPortError::WaitTimeout { ap, source },
+            TransferErrorKind::Protocol => AccessPortError::ProtocolError { ap, source },
+            TransferErrorKind::Parity => AccessPortError::ParityError { ap, source },
+            // Only reported with the address after recovering, see `recover`.
+            TransferErrorKind::Fault | TransferErrorKind::Other => AccessPortError::ProbeError { ap, source },
         }
     }
 
-    pub fn read_block_simple<S: ToMemoryReadSize>(&self, debug_port: &mut impl DAPAccess, addr: u32, data: &mut [S]) -> Result<(), AccessPortError> {
-        if (addr & S::to_alignment_mask()) == 0 {
-            let unit_size = std::mem::size_of::<S>() as u32;
-            let len = data.len() as u32;
-            self.write_reg(debug_port, MEM_AP_CSW, CSW_VALUE | S::to_memory_read_size() as u32)?;
-            for offset in 0..len {
-                let addr = addr + offset * unit_size;
-                self.write_reg(debug_port, MEM_AP_TAR, addr)?;
-                data[offset as usize] = S::to_result(self.read_reg(debug_port, MEM_AP_DRW)?);
+    /// Brings the DP back into a usable state after a failed transfer and builds the error.
+    ///
+    /// A stalled transfer is aborted after a WAIT timeout. After a FAULT the sticky error
+    /// flags are cleared and TAR is read back, which still holds the faulting address.
+    fn recover<D: DAPAccess>(&self, debug_port: &mut D, error: D::Error) -> AccessPortError {
+        log::debug!("AP{} transfer failed: {:?}", self.access_port, error);
+        match error.kind() {
+            TransferErrorKind::Wait => {
+                if let Err(e) = debug_port.write_register(DEBUG_PORT, DP_ABORT, ABORT_DAPABORT) {
+                    return DebugPortError::new(DP_ABORT, e).into();
+                }
+                self.classify(error)
+            },
+            TransferErrorKind::Fault => {
+                let ctrl_stat = match self.clear_sticky_errors(debug_port) {
+                    Ok(ctrl_stat) => ctrl_stat,
+                    Err(e) => return e,
+                };
+                self.fault(debug_port, ctrl_stat)
+            },
+            _ => self.classify(error),
+        }
+    }
+
+    /// Builds the error for a FAULT once the sticky flags are cleared. TAR still holds the faulting address.
+    fn fault(&self, debug_port: &mut impl DAPAccess, ctrl_stat: u32) -> AccessPortError {
+        let upper = if self.capabilities.large_address {
+            match debug_port.read_register(self.access_port, MEM_AP_TAR_UPPER) {
+                Ok(upper) => upper,
+                Err(e) => return self.classify(e),
             }
+        } else {
+            0
+        };
+        match debug_port.read_register(self.access_port, MEM_AP_TAR) {
+            Ok(address) => AccessPortError::Fault {
+                ap: self.access_port,
+                address: ((upper as u64) << 32) | address as u64,
+                ctrl_stat,
+            },
+            Err(e) => self.classify(e),
+        }
+    }
+
+    /// Reads CTRL/STAT and clears the sticky error flags which are set through ABORT.
+    ///
+    /// Returns CTRL/STAT as it was before clearing.
+    fn clear_sticky_errors(&self, debug_port: &mut impl DAPAccess) -> Result<u32, AccessPortError> {
+        let ctrl_stat = debug_port.read_register(DEBUG_PORT, DP_CTRL_STAT).map_err(|e| DebugPortError::new(DP_CTRL_STAT, e))?;
+        let abort = sticky_abort(ctrl_stat);
+        if abort != 0 {
+            debug_port.write_register(DEBUG_PORT, DP_ABORT, abort).map_err(|e| DebugPortError::new(DP_ABORT, e))?;
+        }
+        Ok(ctrl_stat)
+    }
+
+    /// Checks CTRL/STAT for sticky errors and clears them.
+    ///
+    /// JTAG-DPs do not FAULT failed transfers, so errors only show up here.
+    pub fn check_sticky_errors(&self, debug_port: &mut impl DAPAccess) -> Result<(), AccessPortError> {
+        let ctrl_stat = self.clear_sticky_errors(debug_port)?;
+        if ctrl_stat & CTRLSTAT_STICKY_FLAGS != 0 {
+            Err(AccessPortError::StickyError { ap: self.access_port, ctrl_stat })
+        } else {
             Ok(())
+        }
+    }
+
+    /// Returns whether the retry policy allows another try after `error` and counts it.
+    pub(crate) fn retry(&self, error: &AccessPortError, retries: &mut Retries) -> bool {
+        let policy = self.retry_policy;
+        let (count, limit) = match error {
+            // Only a FAULT of the access itself, the DP does not answer FAULT for other reasons.
+            AccessPortError::Fault { .. } => (&mut retries.faults, policy.fault_retries),
+            error => match error.kind() {
+                Some(TransferErrorKind::Wait) => (&mut retries.waits, policy.wait_retries),
+                Some(TransferErrorKind::Protocol) | Some(TransferErrorKind::Parity) => (&mut retries.errors, policy.error_retries),
+                _ => return false,
+            },
+        };
+        if *count < limit {
+            *count += 1;
+            true
         } else {
-            Err(AccessPortError::MemoryNotAligned)
+            false
         }
     }
 
-    pub fn read_block<S: ToMemoryReadSize + std::fmt::LowerHex + std::fmt::Debug>(
+    /// Runs `access` and repeats it as the retry policy allows.
+    fn with_retries<D: DAPAccess, T>(
+        &self,
+        debug_port: &mut D,
+        mut access: impl FnMut(&mut D) -> Result<T, AccessPortError>
+    ) -> Result<T, AccessPortError> {
+        let mut retries = Retries::default();
+        loop {
+            match access(debug_port) {
+                Err(error) if self.retry(&error, &mut retries) => (),
+                result => return result,
+            }
+            match self.clock {
+                Some(clock) if self.retry_policy.delay > Duration::from_millis(0) => (clock.sleep)(self.retry_policy.delay),
+                _ => (),
+            }
+        }
+    }
+
+    fn read_reg<D: DAPAccess>(&self, debug_port: &mut D, addr: u32) -> Result<u32, AccessPortError> {
+        debug_port.read_register(self.access_port, addr).map_err(|e| self.recover(debug_port, e))
+    }
+
+    fn write_reg<D: DAPAccess>(&self, debug_port: &mut D, addr: u32, data: u32) -> Result<(), AccessPortError> {
+        debug_port.write_register(self.access_port, addr, data).map_err(|e| self.recover(debug_port, e))
+    }
+
+    /// Returns how far a value of `size` bytes at `addr` is shifted up in DRW to sit on its byte lanes.
+    ///
+    /// On a big-endian bus the byte at the lowest address sits on the most significant lane.
+    fn lane_shift(&self, addr: u64, size: usize) -> u32 {
+        if size >= 4 {
+            return 0;
+        }
+        let lane = (addr & 0x3) as u32;
+        if self.capabilities.big_endian {
+            (4 - size as u32 - lane) * 8
+        } else {
+            lane * 8
+        }
+    }
+
+    /// Puts the words of a wide value into the order they go through DRW, lowest address first.
+    ///
+    /// `ToMemoryReadSize` keeps them least significant first, which a big-endian target
+    /// stores at the highest address.
+    fn word_order(&self, words: &mut [u32]) {
+        if self.capabilities.big_endian {
+            words.reverse();
+        }
+    }
+
+    /// Splits a value into its `N` bytes in target memory order.
+    pub(crate) fn value_bytes<const N: usize>(&self, value: u32) -> [u8; N] {
+        let mut bytes = [0; N];
+        self.store_value(value, &mut bytes);
+        bytes
+    }
+
+    /// Stores a value into up to four bytes in target memory order.
+    fn store_value(&self, value: u32, bytes: &mut [u8]) {
+        let len = bytes.len();
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            let shift = if self.capabilities.big_endian { len - 1 - i } else { i } * 8;
+            *byte = (value >> shift) as u8;
+        }
+    }
+
+    /// Returns the bytes `data` takes up in target memory.
+    pub(crate) fn memory_bytes<S: ToMemoryReadSize>(&self, data: &[S]) -> Vec<u8> {
+        let mut bytes = vec![0; data.len() * S::SIZE];
+        let mut words = [0; MAX_WORD_COUNT];
+        let words = &mut words[..S::WORD_COUNT];
+        for (value, bytes) in data.iter().zip(bytes.chunks_mut(S::SIZE)) {
+            value.to_words(words);
+            self.word_order(words);
+            for (word, bytes) in words.iter().zip(bytes.chunks_mut(4)) {
+                self.store_value(*word, bytes);
+            }
+        }
+        bytes
+    }
+
+    /// Builds a value from up to four bytes in target memory order.
+    pub(crate) fn bytes_value(&self, bytes: &[u8]) -> u32 {
+        bytes.iter().enumerate().fold(0, |value, (i, byte)| {
+            let shift = if self.capabilities.big_endian { bytes.len() - 1 - i } else { i } * 8;
+            value | (*byte as u32) << shift
+        })
+    }
+
+    /// Reads the value of type `S` at `addr` from DRW, TAR has to point to `addr` already.
+    ///
+    /// Values narrower than a word are taken from the byte lanes of their address.
+    fn read_drw<S: ToMemoryReadSize, D: DAPAccess>(&self, debug_port: &mut D, addr: u64) -> Result<S, AccessPortError> {
+        let mut words = [0; MAX_WORD_COUNT];
+        let words = &mut words[..S::WORD_COUNT];
+        for word in words.iter_mut() {
+            *word = self.read_reg(debug_port, MEM_AP_DRW)?;
+        }
+        Ok(self.decode_drw(addr, words))
+    }
+
+    /// Builds the value of type `S` at `addr` from the `S::WORD_COUNT` values read from DRW.
+    pub(crate) fn decode_drw<S: ToMemoryReadSize>(&self, addr: u64, words: &mut [u32]) -> S {
+        words[0] >>= self.lane_shift(addr, S::SIZE);
+        self.word_order(words);
+        S::from_words(words)
+    }
+
+    /// Fills `words` with the `S::WORD_COUNT` values which write `data` to `addr` through DRW.
+    pub(crate) fn encode_drw<S: ToMemoryReadSize>(&self, addr: u64, data: &S, words: &mut [u32]) {
+        data.to_words(words);
+        self.word_order(words);
+        words[0] <<= self.lane_shift(addr, S::SIZE);
+    }
+
+    /// Merges the value of type `S` at `addr` into `word`, the word around it.
+    pub(crate) fn merge_unit<S: ToMemoryReadSize>(&self, addr: u64, data: &S, word: u32) -> u32 {
+        let mut words = [0];
+        data.to_words(&mut words);
+        let shift = self.lane_shift(addr, S::SIZE);
+        let mask = ((1 << (S::SIZE * 8)) - 1) << shift;
+        (word & !mask) | ((words[0] << shift) & mask)
+    }
+
+    /// Writes the value of type `S` at `addr` to DRW, TAR has to point to `addr` already.
+    fn write_drw<S: ToMemoryReadSize, D: DAPAccess>(&self, debug_port: &mut D, addr: u64, data: &S) -> Result<(), AccessPortError> {
+        let mut words = [0; MAX_WORD_COUNT];
+        let words = &mut words[..S::WORD_COUNT];
+        self.encode_drw(addr, data, words);
+        for word in words.iter() {
+            self.write_reg(debug_port, MEM_AP_DRW, *word)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the value of type `S` at `addr` with accesses of CSW.Size `size`, CSW has to be set already.
+    fn read_unit<S: ToMemoryReadSize, D: DAPAccess>(&self, debug_port: &mut D, size: u32, addr: u64) -> Result<S, AccessPortError> {
+        let tar = if in_word::<S>(size) { addr & !0x3 } else { addr };
+        self.write_tar(debug_port, tar)?;
+        self.read_drw(debug_port, addr)
+    }
+
+    /// Writes the value of type `S` to `addr` with accesses of CSW.Size `size`, CSW has to be set already.
+    ///
+    /// Values which have to be merged into the word around them are read, modified and written.
+    fn write_unit<S: ToMemoryReadSize, D: DAPAccess>(&self, debug_port: &mut D, size: u32, addr: u64, data: &S) -> Result<(), AccessPortError> {
+        if !in_word::<S>(size) {
+            self.write_tar(debug_port, addr)?;
+            return self.write_drw(debug_port, addr, data);
+        }
+        self.write_tar(debug_port, addr & !0x3)?;
+        let word = self.read_reg(debug_port, MEM_AP_DRW)?;
+        self.write_tar(debug_port, addr & !0x3)?;
+        self.write_reg(debug_port, MEM_AP_DRW, self.merge_unit(addr, data, word))
+    }
+
+    pub fn read<S: ToMemoryReadSize>(&self, debug_port: &mut impl DAPAccess, addr: u64) -> Result<S, AccessPortError> {
+        self.with_retries(debug_port, |debug_port| self.read_once(debug_port, addr))
+    }
+
+    fn read_once<S: ToMemoryReadSize>(&self, debug_port: &mut impl DAPAccess, addr: u64) -> Result<S, AccessPortError> {
+        let size = self.access_size::<S>(addr)?;
+        self.write_reg(debug_port, MEM_AP_CSW, self.csw(size))?;
+        self.read_unit(debug_port, size, addr)
+    }
+
+    pub fn read_block_simple<S: ToMemoryReadSize>(&self, debug_port: &mut impl DAPAccess, addr: u64, data: &mut [S]) -> Result<(), AccessPortError> {
+        self.with_retries(debug_port, |debug_port| self.read_block_simple_once(debug_port, addr, data))
+    }
+
+    fn read_block_simple_once<S: ToMemoryReadSize>(&self, debug_port: &mut impl DAPAccess, addr: u64, data: &mut [S]) -> Result<(), AccessPortError> {
+        let size = self.access_size::<S>(addr)?;
+        let unit_size = S::SIZE as u32;
+        let len = data.len() as u32;
+        self.write_reg(debug_port, MEM_AP_CSW, self.csw(size))?;
+        for offset in 0..len {
+            let addr = addr + (offset * unit_size) as u64;
+            data[offset as usize] = self.read_unit(debug_port, size, addr)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_block<S: ToMemoryReadSize>(
         &self,
         debug_port: &mut impl DAPAccess,
-        addr: u32,
+        addr: u64,
         data: &mut [S]
     ) -> Result<(), AccessPortError> {
-        if (addr & S::to_alignment_mask()) == 0 {
-            let unit_size = std::mem::size_of::<S>() as u32;
-            let f = 4 / unit_size;
-            let missing_words_at_start = (4 - addr & 0x3) / f;
-            let missing_words_at_end = (data.len() as u32 - missing_words_at_start) % f;
-
-            let len = (data.len() as u32 - missing_words_at_start - missing_words_at_end) / f;
-
-            self.write_reg(debug_port, MEM_AP_CSW, CSW_VALUE | S::to_memory_read_size() as u32)?;
-            for offset in 0..missing_words_at_start {
-                let addr = addr + offset * unit_size;
-                self.write_reg(debug_port, MEM_AP_TAR, addr)?;
-                data[offset as usize] = S::to_result(self.read_reg(debug_port, MEM_AP_DRW)?);
+        self.with_retries(debug_port, |debug_port| self.read_block_once(debug_port, addr, data))
+    }
+
+    fn read_block_once<S: ToMemoryReadSize>(&self, debug_port: &mut impl DAPAccess, addr: u64, data: &mut [S]) -> Result<(), AccessPortError> {
+        // Values of a word and more can not be packed into words.
+        if S::WORD_COUNT > 1 {
+            return self.read_block_simple_once(debug_port, addr, data);
+        }
+        let size = self.access_size::<S>(addr)?;
+        let unit_size = S::SIZE as u32;
+        let f = 4 / unit_size;
+        let missing_words_at_start = (((addr as u32).wrapping_neg() & 0x3) / unit_size).min(data.len() as u32);
+        let missing_words_at_end = (data.len() as u32 - missing_words_at_start) % f;
+
+        let len = (data.len() as u32 - missing_words_at_start - missing_words_at_end) / f;
+
+        self.write_reg(debug_port, MEM_AP_CSW, self.csw(size))?;
+        for offset in 0..missing_words_at_start {
+            let addr = addr + (offset * unit_size) as u64;
+            data[offset as usize] = self.read_unit(debug_port, size, addr)?;
+        }
+
+        self.write_reg(debug_port, MEM_AP_CSW, self.csw(CSW_SIZE32))?;
+        for offset in 0..len {
+            let addr = addr + (missing_words_at_start * unit_size + offset * 4) as u64;
+            self.write_tar(debug_port, addr)?;
+            let num_units = 4 / unit_size;
+            let value = self.read_reg(debug_port, MEM_AP_DRW)?;
+            for i in 0..num_units {
+                let shift = self.lane_shift(addr + (i * unit_size) as u64, unit_size as usize);
+                data[(missing_words_at_start + offset * f + i) as usize] = S::from_words(&[value >> shift]);
             }
+        }
 
-            self.write_reg(debug_port, MEM_AP_CSW, CSW_VALUE | CSW_SIZE32)?;
-            for offset in 0..len {
-                let addr = addr + missing_words_at_start * unit_size + offset * 4;
-                self.write_reg(debug_port, MEM_AP_TAR, addr)?;
-                let num_units = 4 / unit_size;
-                let value = self.read_reg(debug_port, MEM_AP_DRW)?;
-                for i in 0..num_units {
-                    data[(missing_words_at_start + offset * f + i) as usize] = S::to_result(value >> (i * unit_size * 8));
-                }
+        self.write_reg(debug_port, MEM_AP_CSW, self.csw(size))?;
+        for offset in 0..missing_words_at_end {
+            let addr = addr + (missing_words_at_start * unit_size + len * 4 + offset * unit_size) as u64;
+            data[(missing_words_at_start + len * f + offset) as usize] = self.read_unit(debug_port, size, addr)?;
+        }
+        Ok(())
+    }
+
+    pub fn write<S: ToMemoryReadSize>(&self, debug_port: &mut impl DAPAccess, addr: u64, data: S) -> Result<(), AccessPortError> {
+        self.with_retries(debug_port, |debug_port| self.write_once(debug_port, addr, &data))?;
+        self.verify_written(debug_port, addr, core::slice::from_ref(&data))
+    }
+
+    fn write_once<S: ToMemoryReadSize>(&self, debug_port: &mut impl DAPAccess, addr: u64, data: &S) -> Result<(), AccessPortError> {
+        let size = self.access_size::<S>(addr)?;
+        self.write_reg(debug_port, MEM_AP_CSW, self.csw(size))?;
+        self.write_unit(debug_port, size, addr, data)
+    }
+
+    pub fn write_block<S: ToMemoryReadSize>(&self, debug_port: &mut impl DAPAccess, addr: u64, data: &[S]) -> Result<(), AccessPortError> {
+        self.with_retries(debug_port, |debug_port| self.write_block_once(debug_port, addr, data))?;
+        self.verify_written(debug_port, addr, data)
+    }
+
+    fn write_block_once<S: ToMemoryReadSize>(&self, debug_port: &mut impl DAPAccess, addr: u64, data: &[S]) -> Result<(), AccessPortError> {
+        let size = self.access_size::<S>(addr)?;
+        let len = data.len() as u32;
+        let unit_size = S::SIZE as u32;
+        self.write_reg(debug_port, MEM_AP_CSW, self.csw(size))?;
+        for offset in 0..len {
+            let addr = addr + (offset * unit_size) as u64;
+            self.write_unit(debug_port, size, addr, &data[offset as usize])?;
+        }
+        Ok(())
+    }
+
+    /// Reads `data.len()` bytes from `addr`, which does not have to be aligned.
+    ///
+    /// Unaligned edges are read with byte and halfword accesses and everything in between
+    /// with word accesses, which go out as one batch per TAR wrap block.
+    pub fn read_bytes(&self, debug_port: &mut impl DAPAccess, addr: u64, data: &mut [u8]) -> Result<(), AccessPortError> {
+        self.with_retries(debug_port, |debug_port| self.read_bytes_once(debug_port, addr, data))
+    }
+
+    fn read_bytes_once<D: DAPAccess>(&self, debug_port: &mut D, addr: u64, data: &mut [u8]) -> Result<(), AccessPortError> {
+        for (offset, len) in split_bytes(addr, data.len()) {
+            let addr = addr + offset as u64;
+            let data = &mut data[offset..offset + len];
+            match len {
+                1 | 2 if !self.capabilities.supports_size(len as u32 >> 1) => {
+                    // Taken from the word around it instead.
+                    let word = self.value_bytes::<4>(self.read_once::<u32>(debug_port, addr & !0x3)?);
+                    let start = (addr & 0x3) as usize;
+                    data.copy_from_slice(&word[start..start + len]);
+                },
+                1 => data[0] = self.read_once(debug_port, addr)?,
+                2 => data.copy_from_slice(&self.value_bytes::<2>(self.read_once::<u16>(debug_port, addr)?.into())),
+                _ => self.read_words(debug_port, addr, data)?,
             }
+        }
+        Ok(())
+    }
 
-            self.write_reg(debug_port, MEM_AP_CSW, CSW_VALUE | S::to_memory_read_size() as u32)?;
-            for offset in 0..missing_words_at_end {
-                let addr = addr + missing_words_at_start * unit_size + len * 4 + offset * unit_size;
-                self.write_reg(debug_port, MEM_AP_TAR, addr)?;
-                data[(missing_words_at_start + len * f + offset) as usize] = S::to_result(self.read_reg(debug_port, MEM_AP_DRW)?);
+    /// Writes `data` to `addr`, which does not have to be aligned.
+    ///
+    /// Uses the same accesses as `read_bytes`. If the MEM-AP does not support byte or halfword
+    /// accesses, the edges are merged into the words around them, which is not atomic.
+    pub fn write_bytes(&self, debug_port: &mut impl DAPAccess, addr: u64, data: &[u8]) -> Result<(), AccessPortError> {
+        self.with_retries(debug_port, |debug_port| self.write_bytes_once(debug_port, addr, data))?;
+        if self.verify_policy != VerifyPolicy::Off {
+            let mismatches = self.compare_bytes(debug_port, addr, data)?;
+            if !mismatches.is_empty() {
+                return Err(AccessPortError::VerifyFailed { ap: self.access_port, mismatches });
             }
-            Ok(())
-        } else {
-            Err(AccessPortError::MemoryNotAligned)
         }
+        Ok(())
+    }
+
+    fn write_bytes_once<D: DAPAccess>(&self, debug_port: &mut D, addr: u64, data: &[u8]) -> Result<(), AccessPortError> {
+        for (offset, len) in split_bytes(addr, data.len()) {
+            let addr = addr + offset as u64;
+            let data = &data[offset..offset + len];
+            match len {
+                1 | 2 if !self.capabilities.supports_size(len as u32 >> 1) => {
+                    let mut word = self.value_bytes::<4>(self.read_once::<u32>(debug_port, addr & !0x3)?);
+                    let start = (addr & 0x3) as usize;
+                    word[start..start + len].copy_from_slice(data);
+                    self.write_once(debug_port, addr & !0x3, &self.bytes_value(&word))?;
+                },
+                1 => self.write_once(debug_port, addr, &data[0])?,
+                2 => self.write_once(debug_port, addr, &(self.bytes_value(data) as u16))?,
+                _ => self.write_words(debug_port, addr, data)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads whole words from the word aligned `addr` into `data`.
+    fn read_words<D: DAPAccess>(&self, debug_port: &mut D, addr: u64, data: &mut [u8]) -> Result<(), AccessPortError> {
+        self.write_reg(debug_port, MEM_AP_CSW, self.csw(CSW_SIZE32))?;
+        let mut offset = 0;
+        while offset < data.len() {
+            let tar = addr + offset as u64;
+            let len = self.wrap_block_len(tar, data.len() - offset);
+            let mut transfers = self.tar_transfers(tar)?;
+            let first = transfers.len();
+            transfers.extend((0..len / 4).map(|_| Transfer::read(self.access_port, MEM_AP_DRW)));
+            debug_port.batch(&mut transfers).map_err(|e| self.recover(debug_port, e))?;
+            for (bytes, transfer) in data[offset..offset + len].chunks_mut(4).zip(&transfers[first..]) {
+                bytes.copy_from_slice(&self.value_bytes::<4>(transfer.value));
+            }
+            offset += len;
+        }
+        Ok(())
+    }
+
+    /// Writes whole words from `data` to the word aligned `addr`.
+    fn write_words<D: DAPAccess>(&self, debug_port: &mut D, addr: u64, data: &[u8]) -> Result<(), AccessPortError> {
+        self.write_reg(debug_port, MEM_AP_CSW, self.csw(CSW_SIZE32))?;
+        let mut offset = 0;
+        while offset < data.len() {
+            let tar = addr + offset as u64;
+            let len = self.wrap_block_len(tar, data.len() - offset);
+            let mut transfers = self.tar_transfers(tar)?;
+            transfers.extend(data[offset..offset + len].chunks(4).map(|bytes| {
+                Transfer::write(self.access_port, MEM_AP_DRW, self.bytes_value(bytes))
+            }));
+            debug_port.batch(&mut transfers).map_err(|e| self.recover(debug_port, e))?;
+            offset += len;
+        }
+        Ok(())
+    }
+
+    /// Returns how many of the `len` bytes from `addr` lie within its TAR wrap block.
+    pub(crate) fn wrap_block_len(&self, addr: u64, len: usize) -> usize {
+        let wrap = self.capabilities.tar_wrap as u64;
+        ((wrap - (addr & (wrap - 1))) as usize).min(len)
+    }
+
+    /// Sets TRNMODE and the byte lanes compared by the pushed transfers in CTRL/STAT.
+    fn set_transfer_mode(&self, debug_port: &mut impl DAPAccess, mode: u32, lanes: u32) -> Result<(), AccessPortError> {
+        let ctrl_stat = debug_port.read_register(DEBUG_PORT, DP_CTRL_STAT).map_err(|e| DebugPortError::new(DP_CTRL_STAT, e))?;
+        let ctrl_stat = transfer_mode(ctrl_stat, mode, lanes);
+        debug_port.write_register(DEBUG_PORT, DP_CTRL_STAT, ctrl_stat).map_err(|e| DebugPortError::new(DP_CTRL_STAT, e).into())
     }
 
-    pub fn write<S: ToMemoryReadSize>(&self, debug_port: &mut impl DAPAccess, addr: u32, data: S) -> Result<(), AccessPortError> {
-        if (addr & S::to_alignment_mask()) == 0 {
-            self.write_reg(debug_port, MEM_AP_CSW, CSW_VALUE | S::to_memory_read_size())?;
-            self.write_reg(debug_port, MEM_AP_TAR, addr)?;
-            self.write_reg(debug_port, MEM_AP_DRW, S::to_input(&data))?;
+    /// Writes `values` to DRW in a pushed transfer mode and returns CTRL/STAT as it was afterwards.
+    ///
+    /// Each block TAR auto-increments within goes out as one batch.
+    /// The sticky flags are cleared and the normal transfer mode is restored in any case.
+    fn pushed_transfer<D: DAPAccess>(
+        &self,
+        debug_port: &mut D,
+        mode: u32,
+        lanes: u32,
+        csw: u32,
+        addr: u64,
+        values: &[u32]
+    ) -> Result<u32, AccessPortError> {
+        self.set_transfer_mode(debug_port, mode, lanes)?;
+        let result = self.pushed_writes(debug_port, csw, addr, values);
+        let ctrl_stat = self.clear_sticky_errors(debug_port);
+        let restored = self.set_transfer_mode(debug_port, TRNNORMAL, 0);
+        result?;
+        let ctrl_stat = ctrl_stat?;
+        restored?;
+        if ctrl_stat & (CTRLSTAT_STICKYERR | CTRLSTAT_WDATAERR) != 0 {
+            return Err(self.fault(debug_port, ctrl_stat));
+        }
+        Ok(ctrl_stat)
+    }
+
+    fn pushed_writes<D: DAPAccess>(&self, debug_port: &mut D, csw: u32, addr: u64, values: &[u32]) -> Result<(), AccessPortError> {
+        let mut offset = 0;
+        while offset < values.len() {
+            let (tar, len) = self.pushed_chunk(csw, addr, offset, values.len());
+            let chunk = &values[offset..offset + len];
+
+            let mut transfers = vec![Transfer::write(self.access_port, MEM_AP_CSW, csw)];
+            transfers.extend(self.tar_transfers(tar)?);
+            transfers.extend(chunk.iter().map(|value| Transfer::write(self.access_port, MEM_AP_DRW, *value)));
+            match debug_port.batch(&mut transfers) {
+                Ok(()) => (),
+                // A set sticky flag refuses the remaining transfers, CTRL/STAT tells which one it is.
+                Err(ref e) if e.kind() == TransferErrorKind::Fault => return Ok(()),
+                Err(e) => return Err(self.recover(debug_port, e)),
+            }
+            offset += chunk.len();
+        }
+        Ok(())
+    }
+
+    /// Returns the TAR and the number of values from `offset` on which one pushed batch
+    /// writes, the ones up to the end of the TAR wrap block.
+    pub(crate) fn pushed_chunk(&self, csw: u32, addr: u64, offset: usize, count: usize) -> (u64, usize) {
+        let step = if csw & CSW_ADDRINC == CSW_NADDRINC { 0 } else { 1 << (csw & CSW_SIZE) };
+        let tar = addr + (offset as u32 * step) as u64;
+        let wrap = self.capabilities.tar_wrap;
+        let len = (wrap - (tar as u32 & (wrap - 1))).checked_div(step).map_or(count, |len| len as usize);
+        (tar, len.min(count - offset))
+    }
+
+    /// Checks a finished write of `data` to `addr` according to the verify policy.
+    fn verify_written<S: ToMemoryReadSize>(&self, debug_port: &mut impl DAPAccess, addr: u64, data: &[S]) -> Result<(), AccessPortError> {
+        if self.verify_policy == VerifyPolicy::Off {
+            return Ok(());
+        }
+        let mismatches = self.compare_block(debug_port, addr, data)?;
+        if mismatches.is_empty() {
             Ok(())
         } else {
-            Err(AccessPortError::MemoryNotAligned)
+            Err(AccessPortError::VerifyFailed { ap: self.access_port, mismatches })
         }
     }
 
-    pub fn write_block<S: ToMemoryReadSize>(&self, debug_port: &mut impl DAPAccess, addr: u32, data: &[S]) -> Result<(), AccessPortError> {
-        if (addr & S::to_alignment_mask()) == 0 {
-            let len = data.len() as u32;
-            let unit_size = std::mem::size_of::<S>() as u32;
-            self.write_reg(debug_port, MEM_AP_CSW, CSW_VALUE | S::to_memory_read_size())?;
-            for offset in 0..len {
-                let addr = addr + offset * unit_size;
-                self.write_reg(debug_port, MEM_AP_TAR, addr)?;
-                self.write_reg(debug_port, MEM_AP_DRW, S::to_input(&data[offset as usize]))?;
+    /// Compares the memory at `addr` with `data` and returns the bytes which differ.
+    ///
+    /// With `VerifyPolicy::PushedVerify` the memory is only read back if pushed verify finds
+    /// a difference, otherwise it is always read back.
+    pub fn compare_block<S: ToMemoryReadSize>(&self, debug_port: &mut impl DAPAccess, addr: u64, data: &[S]) -> Result<Vec<Mismatch>, AccessPortError> {
+        let pushed = self.verify_policy == VerifyPolicy::PushedVerify && (S::SIZE >= 4 || self.capabilities.supports_size(S::CSW_SIZE));
+        if pushed && self.verify_block(debug_port, addr, data)? {
+            return Ok(Vec::new());
+        }
+        self.read_back(debug_port, addr, &self.memory_bytes(data))
+    }
+
+    /// Compares the memory at `addr` with `data` like `compare_block`, `addr` does not have to be aligned.
+    ///
+    /// Pushed verify is used for whole words, or bytes if the MEM-AP supports byte accesses.
+    pub fn compare_bytes(&self, debug_port: &mut impl DAPAccess, addr: u64, data: &[u8]) -> Result<Vec<Mismatch>, AccessPortError> {
+        if self.verify_policy == VerifyPolicy::PushedVerify {
+            let equal = if addr & 0x3 == 0 && data.len() & 0x3 == 0 {
+                let words = data.chunks(4).map(|bytes| self.bytes_value(bytes)).collect::<Vec<_>>();
+                Some(self.verify_block(debug_port, addr, &words)?)
+            } else if self.capabilities.supports_size(CSW_SIZE8) {
+                Some(self.verify_block(debug_port, addr, data)?)
+            } else {
+                None
+            };
+            if equal == Some(true) {
+                return Ok(Vec::new());
             }
-            Ok(())
+        }
+        self.read_back(debug_port, addr, data)
+    }
+
+    /// Reads the memory at `addr` and returns where it differs from `expected`.
+    fn read_back(&self, debug_port: &mut impl DAPAccess, addr: u64, expected: &[u8]) -> Result<Vec<Mismatch>, AccessPortError> {
+        let mut actual = vec![0; expected.len()];
+        self.read_bytes(debug_port, addr, &mut actual)?;
+        Ok(mismatches(addr, expected, &actual))
+    }
+
+    /// Checks that the memory at `addr` holds `data` without reading it back, using pushed verify.
+    ///
+    /// Returns `false` if any of the values differ.
+    pub fn verify_block<S: ToMemoryReadSize>(&self, debug_port: &mut impl DAPAccess, addr: u64, data: &[S]) -> Result<bool, AccessPortError> {
+        let (csw, values) = self.verify_values(addr, data)?;
+        self.with_retries(debug_port, |debug_port| {
+            let ctrl_stat = self.pushed_transfer(debug_port, TRNVERIFY, 0xF, csw, addr, &values)?;
+            Ok(ctrl_stat & CTRLSTAT_STICKYCMP == 0)
+        })
+    }
+
+    /// Returns the CSW and the DRW values with which pushed verify compares `data` at `addr`.
+    pub(crate) fn verify_values<S: ToMemoryReadSize>(&self, addr: u64, data: &[S]) -> Result<(u32, Vec<u32>), AccessPortError> {
+        if (addr & (S::SIZE as u64 - 1)) != 0 {
+            return Err(AccessPortError::MemoryNotAligned { ap: self.access_port, address: addr, size: S::SIZE });
+        }
+        // Narrow values can not be compared as part of their word, the others would be compared too.
+        if S::SIZE < 4 && !self.capabilities.supports_size(S::CSW_SIZE) {
+            return Err(AccessPortError::UnsupportedAccessSize { ap: self.access_port, size: S::SIZE });
+        }
+        let mut values = vec![0; data.len() * S::WORD_COUNT];
+        for (i, (value, words)) in data.iter().zip(values.chunks_mut(S::WORD_COUNT)).enumerate() {
+            value.to_words(words);
+            self.word_order(words);
+            words[0] <<= self.lane_shift(addr + (i * S::SIZE) as u64, S::SIZE);
+        }
+        // Wide values are compared word by word, so this works without the large data extension.
+        let size = if S::WORD_COUNT > 1 { CSW_SIZE32 } else { S::CSW_SIZE };
+        Ok((self.csw(size), values))
+    }
+
+    /// Polls the word at `addr` until the bits selected by `mask` equal those of `value`.
+    ///
+    /// Masks which select whole bytes are polled with pushed compare in batches, any other
+    /// mask falls back to reading the word. `timeout` is measured by the clock, see `Clock`.
+    pub fn wait_for_value(
+        &self,
+        debug_port: &mut impl DAPAccess,
+        addr: u64,
+        value: u32,
+        mask: u32,
+        timeout: Duration
+    ) -> Result<(), AccessPortError> {
+        if (addr & value.alignment_mask()) != 0 {
+            return Err(AccessPortError::MemoryNotAligned { ap: self.access_port, address: addr, size: 4 });
+        }
+        let start = self.clock.map(|clock| (clock.now)());
+        match byte_lanes(mask) {
+            Some(lanes) => {
+                let csw = self.poll_csw();
+                let values = [value; POLL_BATCH_SIZE];
+                loop {
+                    let ctrl_stat = self.pushed_transfer(debug_port, TRNCOMPARE, lanes, csw, addr, &values)?;
+                    if ctrl_stat & CTRLSTAT_STICKYCMP != 0 {
+                        return Ok(());
+                    }
+                    if self.expired(start, timeout) {
+                        return Err(AccessPortError::Timeout { ap: self.access_port, address: addr });
+                    }
+                }
+            },
+            None => loop {
+                if self.read::<u32>(debug_port, addr)? & mask == value & mask {
+                    return Ok(());
+                }
+                if self.expired(start, timeout) {
+                    return Err(AccessPortError::Timeout { ap: self.access_port, address: addr });
+                }
+            },
+        }
+    }
+
+    /// Opens a window on the 16 byte block holding `addr` for word accesses through BD0 to BD3.
+    pub fn window<'a, D: DAPAccess>(&'a self, debug_port: &'a mut D, addr: u64) -> Result<MemoryWindow<'a, D>, AccessPortError> {
+        self.write_reg(debug_port, MEM_AP_CSW, self.csw(CSW_SIZE32))?;
+        let mut block = None;
+        MemoryWindow::select(self, debug_port, &mut block, addr)?;
+        Ok(MemoryWindow {
+            interface: self,
+            debug_port,
+            block,
+        })
+    }
+
+    /// Returns the accesses to the memory behind this MEM-AP through an async DAP.
+    pub fn asynchronous<'a, A: AsyncDAPAccess>(&'a self, debug_port: &'a mut A) -> AsyncMemory<'a, A> {
+        AsyncMemory::new(self, debug_port)
+    }
+
+    /// Returns the fill, compare, search and CRC-32 utilities for the memory behind this MEM-AP.
+    pub fn tools<'a, D: DAPAccess>(&'a self, debug_port: &'a mut D) -> MemoryTools<'a, D> {
+        MemoryTools::new(self, debug_port)
+    }
+
+    /// Reads a `T` at `addr`, decoded in the byte order of the target.
+    pub fn read_struct<T: TargetStruct>(&self, debug_port: &mut impl DAPAccess, addr: u64) -> Result<T, AccessPortError> {
+        let mut bytes = vec![0; T::SIZE];
+        self.read_bytes(debug_port, addr, &mut bytes)?;
+        Ok(T::decode(&bytes, self.endian()))
+    }
+
+    /// Writes `data` to `addr`, encoded in the byte order of the target. Padding is written as zeros.
+    pub fn write_struct<T: TargetStruct>(&self, debug_port: &mut impl DAPAccess, addr: u64, data: &T) -> Result<(), AccessPortError> {
+        let mut bytes = vec![0; T::SIZE];
+        data.encode(&mut bytes, self.endian());
+        self.write_bytes(debug_port, addr, &bytes)
+    }
+
+    /// Returns the byte order of the memory, as found by `probe` or `detect_endianness`.
+    pub fn endian(&self) -> Endian {
+        if self.capabilities.big_endian { Endian::Big } else { Endian::Little }
+    }
+
+    /// Returns a `std::io` cursor over the memory in `range`.
+    #[cfg(feature = "std")]
+    pub fn cursor<'a, D: DAPAccess>(&'a self, debug_port: &'a mut D, range: core::ops::Range<u64>) -> TargetMemoryCursor<'a, D> {
+        TargetMemoryCursor::new(self, debug_port, range)
+    }
+}
+
+/// Accesses memory through the banked data registers BD0 to BD3.
+///
+/// Each banked register reaches one word of the 16 byte block TAR points to, so TAR is
+/// only written when an access leaves the block. Registers which sit close together, like
+/// DHCSR, DCRSR, DCRDR and DEMCR, are accessed without rewriting it. The window borrows
+/// the DAP, so nothing else can move TAR while it is open.
+pub struct MemoryWindow<'a, D: DAPAccess> {
+    interface: &'a MemoryInterface,
+    debug_port: &'a mut D,
+    /// The block TAR points to, `None` if it is not known.
+    block: Option<u64>,
+}
+
+impl<'a, D: DAPAccess> MemoryWindow<'a, D> {
+    /// Returns the address of the block TAR currently points to.
+    pub fn block(&self) -> Option<u64> {
+        self.block
+    }
+
+    /// Reads the word at `addr`, TAR is moved if `addr` is outside of the current block.
+    pub fn read(&mut self, addr: u64) -> Result<u32, AccessPortError> {
+        let MemoryWindow { interface, debug_port, block } = self;
+        interface.with_retries(*debug_port, |debug_port| {
+            let register = Self::select(interface, debug_port, block, addr)?;
+            interface.read_reg(debug_port, register).inspect_err(|_| *block = None)
+        })
+    }
+
+    /// Writes the word at `addr`, TAR is moved if `addr` is outside of the current block.
+    pub fn write(&mut self, addr: u64, data: u32) -> Result<(), AccessPortError> {
+        let MemoryWindow { interface, debug_port, block } = self;
+        interface.with_retries(*debug_port, |debug_port| {
+            let register = Self::select(interface, debug_port, block, addr)?;
+            interface.write_reg(debug_port, register, data).inspect_err(|_| *block = None)
+        })
+    }
+
+    /// Points TAR at the block holding `addr` and returns the banked register which reaches it.
+    fn select(interface: &MemoryInterface, debug_port: &mut D, block: &mut Option<u64>, addr: u64) -> Result<u32, AccessPortError> {
+        if (addr & 0x3) != 0 {
+            return Err(AccessPortError::MemoryNotAligned { ap: interface.access_port, address: addr, size: 4 });
+        }
+        if *block != Some(addr & !0xF) {
+            *block = None;
+            interface.write_tar(debug_port, addr & !0xF)?;
+            *block = Some(addr & !0xF);
+        }
+        Ok(MEM_AP_BD0 + (addr & 0xC) as u32)
+    }
+}
+
+/// Splits `len` bytes from `addr` into the fewest naturally aligned accesses.
+///
+/// Returns the offset and length of each access. Bytes and halfwords are only used for
+/// the unaligned edges, everything in between is a single run of whole words.
+pub(crate) fn split_bytes(addr: u64, len: usize) -> Vec<(usize, usize)> {
+    let mut accesses = vec![];
+    let mut offset = 0;
+    while offset < len {
+        let addr = addr + offset as u64;
+        let remaining = len - offset;
+        let size = if addr & 0x1 != 0 || remaining < 2 {
+            1
+        } else if addr & 0x2 != 0 || remaining < 4 {
+            2
         } else {
-            Err(AccessPortError::MemoryNotAligned)
+            remaining & !0x3
+        };
+        accesses.push((offset, size));
+        offset += size;
+    }
+    accesses
+}
+
+/// Returns whether values of type `S` are accessed as part of the word around them with accesses of CSW.Size `size`.
+pub(crate) fn in_word<S: ToMemoryReadSize>(size: u32) -> bool {
+    S::SIZE < 4 && size == CSW_SIZE32
+}
+
+/// Returns where the bytes read from `addr` differ from `expected`.
+pub(crate) fn mismatches(addr: u64, expected: &[u8], actual: &[u8]) -> Vec<Mismatch> {
+    expected.iter().zip(actual).enumerate()
+        .filter(|(_, (expected, actual))| expected != actual)
+        .map(|(i, (expected, actual))| Mismatch { address: addr + i as u64, expected: *expected, actual: *actual })
+        .collect()
+}
+
+/// Returns the ABORT value which clears the sticky flags set in `ctrl_stat`.
+pub(crate) fn sticky_abort(ctrl_stat: u32) -> u32 {
+    let mut abort = 0;
+    if ctrl_stat & CTRLSTAT_STICKYERR != 0 {
+        abort |= ABORT_STKERRCLR;
+    }
+    if ctrl_stat & CTRLSTAT_STICKYCMP != 0 {
+        abort |= ABORT_STKCMPCLR;
+    }
+    if ctrl_stat & CTRLSTAT_STICKYORUN != 0 {
+        abort |= ABORT_ORUNERRCLR;
+    }
+    if ctrl_stat & CTRLSTAT_WDATAERR != 0 {
+        abort |= ABORT_WDERRCLR;
+    }
+    abort
+}
+
+/// Returns `ctrl_stat` with TRNMODE set to `mode` and MASKLANE to `lanes`.
+pub(crate) fn transfer_mode(ctrl_stat: u32, mode: u32, lanes: u32) -> u32 {
+    // Writing ones to the sticky flags would clear them on a SW-DP.
+    let keep = !(CTRLSTAT_STICKY_FLAGS | CTRLSTAT_TRNMODE | MASKLANE);
+    (ctrl_stat & keep) | mode | ((lanes << 8) & MASKLANE)
+}
+
+/// Returns the MASKLANE bits for `mask` if it selects whole bytes only.
+pub(crate) fn byte_lanes(mask: u32) -> Option<u32> {
+    let mut lanes = 0;
+    for lane in 0..4 {
+        match (mask >> (lane * 8)) & 0xFF {
+            0xFF => lanes |= 1 << lane,
+            0x00 => (),
+            _ => return None,
         }
     }
+    Some(lanes)
 }
 
 #[cfg(test)]
 mod test {
-    use super::MemoryInterface;
-    use crate::dap_access::MockDAP;
+    use super::{split_bytes, AccessAttributes, Clock, MemApCapabilities, MemoryInterface, RetryPolicy, VerifyPolicy};
+    use crate::access_port::consts::*;
+    use crate::access_port::{AccessPortError, Mismatch};
+    use crate::dap_access::consts::*;
+    use crate::dap_access::{DAPAccess, MockDAP, TransferError, TransferErrorKind, DEBUG_PORT};
+    use crate::probes::adiv5::AdiV5;
+    use crate::probes::sim::SimulatedDap;
+    #[cfg(feature = "std")]
+    use crate::recording::RecordingDAP;
+    use proptest::prelude::*;
+    use std::error::Error;
+    use std::fmt;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Duration;
+
+    #[derive(Debug)]
+    enum FlakyError {
+        Mock,
+        Wait,
+    }
+
+    impl fmt::Display for FlakyError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(match self {
+                FlakyError::Mock => "mock error",
+                FlakyError::Wait => "WAIT",
+            })
+        }
+    }
+
+    impl std::error::Error for FlakyError {}
+
+    impl TransferError for FlakyError {
+        fn kind(&self) -> TransferErrorKind {
+            match self {
+                FlakyError::Mock => TransferErrorKind::Other,
+                FlakyError::Wait => TransferErrorKind::Wait,
+            }
+        }
+    }
+
+    /// Answers WAIT to the first few AP transfers and logs the DP writes.
+    struct FlakyDAP {
+        mock: MockDAP,
+        waits: usize,
+        dp_writes: Vec<(u32, u32)>,
+    }
+
+    impl FlakyDAP {
+        fn new(waits: usize) -> Self {
+            Self {
+                mock: MockDAP::new(),
+                waits,
+                dp_writes: vec![],
+            }
+        }
+
+        fn wait(&mut self) -> Result<(), FlakyError> {
+            if self.waits > 0 {
+                self.waits -= 1;
+                return Err(FlakyError::Wait);
+            }
+            Ok(())
+        }
+    }
+
+    impl DAPAccess for FlakyDAP {
+        type Error = FlakyError;
+
+        fn read_register(&mut self, port: u16, addr: u32) -> Result<u32, Self::Error> {
+            self.wait()?;
+            self.mock.read_register(port, addr).map_err(|_| FlakyError::Mock)
+        }
+
+        fn write_register(&mut self, port: u16, addr: u32, value: u32) -> Result<(), Self::Error> {
+            if port == DEBUG_PORT {
+                self.dp_writes.push((addr, value));
+                return Ok(());
+            }
+            self.wait()?;
+            self.mock.write_register(port, addr, value).map_err(|_| FlakyError::Mock)
+        }
+    }
+
+    fn no_delay(wait_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            wait_retries,
+            delay: Duration::from_millis(0),
+            ..RetryPolicy::default()
+        }
+    }
 
     #[test]
     fn read_u32() {
@@ -247,7 +1562,7 @@ mod test {
     fn write_u32() {
         let mut mock = MockDAP::new();
         let mi = MemoryInterface::new(0x0);
-        debug_assert!(mi.write(&mut mock, 0, 0xDEADBEEF as u32).is_ok());
+        debug_assert!(mi.write(&mut mock, 0, 0xDEADBEEF_u32).is_ok());
         debug_assert_eq!(mock.data[0..4], [0xEF, 0xBE, 0xAD, 0xDE]);
     }
 
@@ -255,8 +1570,8 @@ mod test {
     fn write_u16() {
         let mut mock = MockDAP::new();
         let mi = MemoryInterface::new(0x0);
-        debug_assert!(mi.write(&mut mock, 0, 0xBEEF as u16).is_ok());
-        debug_assert!(mi.write(&mut mock, 2, 0xDEAD as u16).is_ok());
+        debug_assert!(mi.write(&mut mock, 0, 0xBEEF_u16).is_ok());
+        debug_assert!(mi.write(&mut mock, 2, 0xDEAD_u16).is_ok());
         debug_assert_eq!(mock.data[0..4], [0xEF, 0xBE, 0xAD, 0xDE]);
     }
 
@@ -264,10 +1579,10 @@ mod test {
     fn write_u8() {
         let mut mock = MockDAP::new();
         let mi = MemoryInterface::new(0x0);
-        debug_assert!(mi.write(&mut mock, 0, 0xEF as u8).is_ok());
-        debug_assert!(mi.write(&mut mock, 1, 0xBE as u8).is_ok());
-        debug_assert!(mi.write(&mut mock, 2, 0xAD as u8).is_ok());
-        debug_assert!(mi.write(&mut mock, 3, 0xDE as u8).is_ok());
+        debug_assert!(mi.write(&mut mock, 0, 0xEF_u8).is_ok());
+        debug_assert!(mi.write(&mut mock, 1, 0xBE_u8).is_ok());
+        debug_assert!(mi.write(&mut mock, 2, 0xAD_u8).is_ok());
+        debug_assert!(mi.write(&mut mock, 3, 0xDE_u8).is_ok());
         debug_assert_eq!(mock.data[0..4], [0xEF, 0xBE, 0xAD, 0xDE]);
     }
 
@@ -283,7 +1598,7 @@ mod test {
         mock.data[6] = 0xBA;
         mock.data[7] = 0xAB;
         let mi = MemoryInterface::new(0x0);
-        let mut data = [0 as u32; 2];
+        let mut data = [0_u32; 2];
         let read = mi.read_block(&mut mock, 0, &mut data);
         debug_assert!(read.is_ok());
         debug_assert_eq!(data, [0xDEADBEEF, 0xABBABABE]);
@@ -301,7 +1616,7 @@ mod test {
         mock.data[6] = 0xBA;
         mock.data[7] = 0xAB;
         let mi = MemoryInterface::new(0x0);
-        let mut data = [0 as u16; 4];
+        let mut data = [0_u16; 4];
         let read = mi.read_block(&mut mock, 0, &mut data);
         debug_assert!(read.is_ok());
         debug_assert_eq!(data, [0xBEEF, 0xDEAD, 0xBABE, 0xABBA]);
@@ -319,7 +1634,7 @@ mod test {
         mock.data[8] = 0xBA;
         mock.data[9] = 0xAB;
         let mi = MemoryInterface::new(0x0);
-        let mut data = [0 as u16; 4];
+        let mut data = [0_u16; 4];
         let read = mi.read_block(&mut mock, 2, &mut data);
         debug_assert!(read.is_ok());
         debug_assert_eq!(data, [0xBEEF, 0xDEAD, 0xBABE, 0xABBA]);
@@ -337,7 +1652,7 @@ mod test {
         mock.data[6] = 0xBA;
         mock.data[7] = 0xAB;
         let mi = MemoryInterface::new(0x0);
-        let mut data = [0 as u8; 8];
+        let mut data = [0_u8; 8];
         let read = mi.read_block(&mut mock, 0, &mut data);
         debug_assert!(read.is_ok());
         debug_assert_eq!(data, [0xEF, 0xBE, 0xAD, 0xDE, 0xBE, 0xBA, 0xBA ,0xAB]);
@@ -355,7 +1670,7 @@ mod test {
         mock.data[7] = 0xBA;
         mock.data[8] = 0xAB;
         let mi = MemoryInterface::new(0x0);
-        let mut data = [0 as u8; 8];
+        let mut data = [0_u8; 8];
         let read = mi.read_block(&mut mock, 1, &mut data);
         debug_assert!(read.is_ok());
         debug_assert_eq!(data, [0xEF, 0xBE, 0xAD, 0xDE, 0xBE, 0xBA, 0xBA ,0xAB]);
@@ -373,7 +1688,7 @@ mod test {
         mock.data[9] = 0xBA;
         mock.data[10] = 0xAB;
         let mi = MemoryInterface::new(0x0);
-        let mut data = [0 as u8; 8];
+        let mut data = [0_u8; 8];
         let read = mi.read_block(&mut mock, 3, &mut data);
         debug_assert!(read.is_ok());
         debug_assert_eq!(data, [0xEF, 0xBE, 0xAD, 0xDE, 0xBE, 0xBA, 0xBA ,0xAB]);
@@ -402,4 +1717,523 @@ mod test {
         debug_assert!(mi.write_block(&mut mock, 0, &([0xEF, 0xBE, 0xAD, 0xDE, 0xBE, 0xBA, 0xBA ,0xAB] as [u8; 8])).is_ok());
         debug_assert_eq!(mock.data[0..8], [0xEF, 0xBE, 0xAD, 0xDE, 0xBE, 0xBA, 0xBA ,0xAB]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn wait_retries() {
+        let mut dap = FlakyDAP::new(2);
+        dap.mock.data[0..4].copy_from_slice(&[0xEF, 0xBE, 0xAD, 0xDE]);
+        let mut mi = MemoryInterface::new(0x0);
+        mi.set_retry_policy(no_delay(2));
+        assert_eq!(mi.read::<u32>(&mut dap, 0), Ok(0xDEADBEEF));
+        // Every WAIT timeout aborts the stalled transfer.
+        assert_eq!(dap.dp_writes, vec![(DP_ABORT, ABORT_DAPABORT); 2]);
+    }
+
+    #[test]
+    fn wait_timeout() {
+        let mut dap = FlakyDAP::new(3);
+        let mut mi = MemoryInterface::new(0x0);
+        mi.set_retry_policy(no_delay(2));
+        match mi.read::<u32>(&mut dap, 0) {
+            Err(error @ AccessPortError::WaitTimeout { ap: 0, .. }) => {
+                assert_eq!(error.to_string(), "AP 0: the target kept answering WAIT");
+                assert_eq!(error.source().map(|source| source.to_string()), Some("WAIT".to_string()));
+            },
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn fault_clears_sticky_errors() {
+        let mut dap = AdiV5::new(SimulatedDap::new(0x100));
+        let mi = MemoryInterface::new(0x0);
+        let mut data = [0_u32; 4];
+        match mi.read_block_simple(&mut dap, 0xF8, &mut data) {
+            Err(AccessPortError::Fault { ap: 0, address, ctrl_stat }) => {
+                assert_eq!(address, 0x100);
+                assert_eq!(ctrl_stat & CTRLSTAT_STICKYERR, CTRLSTAT_STICKYERR);
+            },
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(!dap.raw().sticky_error());
+        mi.write(&mut dap, 0x10, 0xDEADBEEF_u32).unwrap();
+        assert_eq!(mi.read::<u32>(&mut dap, 0x10), Ok(0xDEADBEEF));
+    }
+
+    #[test]
+    fn check_sticky_errors() {
+        let mut dap = AdiV5::new(SimulatedDap::new(0x100));
+        let mi = MemoryInterface::new(0x0);
+        assert_eq!(mi.check_sticky_errors(&mut dap), Ok(()));
+        dap.raw().set_write_data_error();
+        match mi.check_sticky_errors(&mut dap) {
+            Err(AccessPortError::StickyError { ap: 0, ctrl_stat }) => assert_eq!(ctrl_stat & CTRLSTAT_WDATAERR, CTRLSTAT_WDATAERR),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(mi.check_sticky_errors(&mut dap), Ok(()));
+    }
+
+    #[test]
+    fn verify_block() {
+        let mut dap = AdiV5::new(SimulatedDap::new(0x800));
+        let mi = MemoryInterface::new(0x0);
+        // Crosses the 1 KiB boundary at 0x400.
+        let data: Vec<u32> = (0..16).map(|i| 0x1000_0000 + i).collect();
+        mi.write_block(&mut dap, 0x3E0, &data).unwrap();
+        assert_eq!(mi.verify_block(&mut dap, 0x3E0, &data), Ok(true));
+
+        dap.raw().memory[0x404] ^= 0x1;
+        assert_eq!(mi.verify_block(&mut dap, 0x3E0, &data), Ok(false));
+        // The transfer mode is back to normal and the compare flag is cleared.
+        assert_eq!(dap.read_register(DEBUG_PORT, DP_CTRL_STAT).unwrap() & (CTRLSTAT_TRNMODE | CTRLSTAT_STICKYCMP), 0);
+        assert_eq!(mi.read::<u32>(&mut dap, 0x3E0), Ok(0x1000_0000));
+    }
+
+    #[test]
+    fn verify_block_fault() {
+        let mut dap = AdiV5::new(SimulatedDap::new(0x100));
+        let mi = MemoryInterface::new(0x0);
+        match mi.verify_block(&mut dap, 0xF8, &[0_u32; 4]) {
+            Err(AccessPortError::Fault { address, .. }) => assert_eq!(address, 0x100),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn wait_for_value() {
+        let mut dap = AdiV5::new(SimulatedDap::new(0x100));
+        let mi = MemoryInterface::new(0x0);
+        let timeout = Duration::from_millis(20);
+        mi.write(&mut dap, 0x40, 0x1234_5678_u32).unwrap();
+        assert_eq!(mi.wait_for_value(&mut dap, 0x40, 0x1234_5678, 0xFFFF_FFFF, timeout), Ok(()));
+        assert_eq!(mi.wait_for_value(&mut dap, 0x40, 0xAA34_56BB, 0x00FF_FF00, timeout), Ok(()));
+        assert_eq!(mi.wait_for_value(&mut dap, 0x40, 0x0000_0008, 0x0000_000F, timeout), Ok(()));
+        assert_eq!(mi.wait_for_value(&mut dap, 0x40, 0x1234_5600, 0xFFFF_FF00, timeout), Ok(()));
+
+        assert_eq!(mi.wait_for_value(&mut dap, 0x40, 0x0000_0001, 0x0000_00FF, timeout), Err(AccessPortError::Timeout { ap: 0, address: 0x40 }));
+        assert_eq!(mi.wait_for_value(&mut dap, 0x40, 0x0000_0001, 0x0000_0001, timeout), Err(AccessPortError::Timeout { ap: 0, address: 0x40 }));
+        assert_eq!(dap.read_register(DEBUG_PORT, DP_CTRL_STAT).unwrap() & CTRLSTAT_TRNMODE, 0);
+    }
+
+    #[test]
+    fn clock() {
+        static TICKS: AtomicU64 = AtomicU64::new(0);
+        static SLEPT: AtomicU64 = AtomicU64::new(0);
+        let mut dap = AdiV5::new(SimulatedDap::new(0x100));
+        let mut mi = MemoryInterface::new(0x0);
+        let timeout = Duration::from_secs(3600);
+        // Without a clock the timeout expires after the first poll.
+        mi.set_clock(None);
+        assert_eq!(mi.wait_for_value(&mut dap, 0x40, 0x1, 0x1, timeout), Err(AccessPortError::Timeout { ap: 0, address: 0x40 }));
+
+        // Every reading of this clock advances it by a millisecond.
+        mi.set_clock(Some(Clock {
+            now: || Duration::from_millis(TICKS.fetch_add(1, Ordering::SeqCst)),
+            sleep: |delay| {
+                SLEPT.fetch_add(delay.as_millis() as u64, Ordering::SeqCst);
+            },
+        }));
+        let timeout = Duration::from_millis(5);
+        assert_eq!(mi.wait_for_value(&mut dap, 0x40, 0x1, 0x1, timeout), Err(AccessPortError::Timeout { ap: 0, address: 0x40 }));
+        assert_eq!(TICKS.load(Ordering::SeqCst), 6);
+
+        let mut dap = FlakyDAP::new(2);
+        mi.set_retry_policy(RetryPolicy { delay: Duration::from_millis(7), ..no_delay(2) });
+        assert_eq!(mi.read::<u32>(&mut dap, 0), Ok(0));
+        assert_eq!(SLEPT.load(Ordering::SeqCst), 14);
+    }
+
+    #[test]
+    fn large_address() {
+        let mut sim = SimulatedDap::new(0x100);
+        sim.set_cfg(CFG_LA);
+        sim.set_memory_base(0x1_2000_0000);
+        let mut dap = AdiV5::new(sim);
+        let mut mi = MemoryInterface::new(0x0);
+        assert_eq!(mi.write(&mut dap, 0x1_2000_0010, 0xDEADBEEF_u32), Err(AccessPortError::AddressTooLarge { ap: 0, address: 0x1_2000_0010 }));
+        assert!(mi.probe(&mut dap).unwrap().large_address);
+
+        mi.write_block(&mut dap, 0x1_2000_0010, &[1_u32, 2, 3]).unwrap();
+        let mut data = [0_u32; 3];
+        mi.read_block(&mut dap, 0x1_2000_0010, &mut data).unwrap();
+        assert_eq!(data, [1, 2, 3]);
+        assert_eq!(mi.read::<u32>(&mut dap, 0x1_2000_0014), Ok(2));
+        match mi.read::<u32>(&mut dap, 0x2000_0010) {
+            Err(AccessPortError::Fault { address, .. }) => assert_eq!(address, 0x2000_0010),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn base_address() {
+        let mut sim = SimulatedDap::new(0x100);
+        sim.set_base(0x0000_0008_E00F_F003);
+        let mut dap = AdiV5::new(sim);
+        let mut mi = MemoryInterface::new(0x0);
+        assert_eq!(mi.base_address(&mut dap), Ok(Some(0xE00F_F000)));
+        dap.raw().set_cfg(CFG_LA);
+        assert!(mi.probe(&mut dap).unwrap().large_address);
+        assert_eq!(mi.base_address(&mut dap), Ok(Some(0x8_E00F_F000)));
+        dap.raw().set_base(0x0000_0008_E00F_F002);
+        assert_eq!(mi.base_address(&mut dap), Ok(None));
+    }
+
+    #[test]
+    fn signed_and_float() {
+        let mut dap = AdiV5::new(SimulatedDap::new(0x100));
+        let mi = MemoryInterface::new(0x0);
+        mi.write(&mut dap, 0x10, -2_i8).unwrap();
+        mi.write(&mut dap, 0x12, -3_i16).unwrap();
+        mi.write(&mut dap, 0x14, 1.5_f32).unwrap();
+        mi.write(&mut dap, 0x18, -4_i64).unwrap();
+        mi.write(&mut dap, 0x20, -0.25_f64).unwrap();
+        assert_eq!(dap.raw().memory[0x10..0x18], [0xFE, 0x00, 0xFD, 0xFF, 0x00, 0x00, 0xC0, 0x3F]);
+        assert_eq!(mi.read::<i8>(&mut dap, 0x10), Ok(-2));
+        assert_eq!(mi.read::<i16>(&mut dap, 0x12), Ok(-3));
+        assert_eq!(mi.read::<f32>(&mut dap, 0x14), Ok(1.5));
+        assert_eq!(mi.read::<i64>(&mut dap, 0x18), Ok(-4));
+        assert_eq!(mi.read::<f64>(&mut dap, 0x20), Ok(-0.25));
+
+        mi.write_block(&mut dap, 0x30, &[-1.0_f32, 2.0, 0.5]).unwrap();
+        let mut floats = [0.0_f32; 3];
+        mi.read_block(&mut dap, 0x30, &mut floats).unwrap();
+        assert_eq!(floats, [-1.0, 2.0, 0.5]);
+        let mut halfwords = [0_i16; 3];
+        mi.read_block(&mut dap, 0x12, &mut halfwords).unwrap();
+        assert_eq!(halfwords, [-3, 0, 0x3FC0]);
+        assert_eq!(mi.read::<f32>(&mut dap, 0x32), Err(AccessPortError::MemoryNotAligned { ap: 0, address: 0x32, size: 4 }));
+    }
+
+    #[test]
+    fn large_data() {
+        let mut dap = AdiV5::new(SimulatedDap::new(0x100));
+        let mut mi = MemoryInterface::new(0x0);
+        // Without the large data extension, wide values go through as word accesses.
+        mi.write(&mut dap, 0x30, 0x0123_4567_89AB_CDEF_u64).unwrap();
+        assert_eq!(dap.raw().memory[0x30..0x38], [0xEF, 0xCD, 0xAB, 0x89, 0x67, 0x45, 0x23, 0x01]);
+        assert_eq!(mi.read::<u64>(&mut dap, 0x30), Ok(0x0123_4567_89AB_CDEF));
+        assert!(!mi.probe(&mut dap).unwrap().large_data);
+        dap.raw().set_cfg(CFG_LD);
+        assert!(mi.probe(&mut dap).unwrap().large_data);
+
+        mi.write(&mut dap, 0x10, 0x0123_4567_89AB_CDEF_u64).unwrap();
+        assert_eq!(dap.raw().memory[0x10..0x18], [0xEF, 0xCD, 0xAB, 0x89, 0x67, 0x45, 0x23, 0x01]);
+        assert_eq!(mi.read::<u64>(&mut dap, 0x10), Ok(0x0123_4567_89AB_CDEF));
+        assert_eq!(mi.read::<u64>(&mut dap, 0x14), Err(AccessPortError::MemoryNotAligned { ap: 0, address: 0x14, size: 8 }));
+
+        let data = [0x0011_2233_4455_6677_8899_AABB_CCDD_EEFF_u128, 0xFFEE_DDCC_BBAA_9988_7766_5544_3322_1100];
+        mi.write_block(&mut dap, 0x20, &data).unwrap();
+        let mut read = [0_u128; 2];
+        mi.read_block(&mut dap, 0x20, &mut read).unwrap();
+        assert_eq!(read, data);
+        let mut words = [0_u64; 4];
+        mi.read_block(&mut dap, 0x20, &mut words).unwrap();
+        assert_eq!(words[1], 0x0011_2233_4455_6677);
+        assert_eq!(mi.verify_block(&mut dap, 0x20, &data), Ok(true));
+
+        match mi.read::<u128>(&mut dap, 0x100) {
+            Err(AccessPortError::Fault { address, .. }) => assert_eq!(address, 0x100),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn large_data_256() {
+        let mut dap = AdiV5::new(SimulatedDap::new(0x100));
+        let mut mi = MemoryInterface::new(0x0);
+        let value = [0x0302_0100_u32, 0x0706_0504, 0x0B0A_0908, 0x0F0E_0D0C, 0x1312_1110, 0x1716_1514, 0x1B1A_1918, 0x1F1E_1D1C];
+        // As word accesses without the large data extension.
+        mi.write(&mut dap, 0x20, value).unwrap();
+        assert_eq!(mi.read::<[u32; 8]>(&mut dap, 0x20), Ok(value));
+        dap.raw().set_cfg(CFG_LD);
+        mi.probe(&mut dap).unwrap();
+        assert!(mi.capabilities().supports_size(CSW_SIZE256));
+
+        mi.write_block(&mut dap, 0x40, &[value, [0xFFFF_FFFF; 8]]).unwrap();
+        assert_eq!(dap.raw().memory[0x40..0x60], (0..0x20).collect::<Vec<u8>>()[..]);
+        assert_eq!(dap.raw().memory[0x60..0x80], [0xFF; 0x20]);
+        let mut read = [[0_u32; 8]; 2];
+        mi.read_block(&mut dap, 0x40, &mut read).unwrap();
+        assert_eq!(read, [value, [0xFFFF_FFFF; 8]]);
+        assert_eq!(dap.read_register(0, MEM_AP_CSW).unwrap() & CSW_SIZE, CSW_SIZE256);
+        assert_eq!(mi.read::<u128>(&mut dap, 0x50), Ok(0x1F1E_1D1C_1B1A_1918_1716_1514_1312_1110));
+        assert_eq!(mi.read::<[u32; 8]>(&mut dap, 0x50), Err(AccessPortError::MemoryNotAligned { ap: 0, address: 0x50, size: 32 }));
+    }
+
+    #[test]
+    fn verify_policy() {
+        let mut sim = SimulatedDap::new(0x100);
+        sim.set_read_only(0x16..0x18);
+        let mut dap = AdiV5::new(sim);
+        let mut mi = MemoryInterface::new(0x0);
+        mi.write_block(&mut dap, 0x10, &[1_u32, 2, 3]).unwrap();
+
+        for policy in [VerifyPolicy::ReadBack, VerifyPolicy::PushedVerify] {
+            mi.set_verify_policy(policy);
+            mi.write_block(&mut dap, 0x20, &[1_u32, 2, 3]).unwrap();
+            mi.write_bytes(&mut dap, 0x2D, &[4, 5, 6]).unwrap();
+            assert_eq!(mi.write_block(&mut dap, 0x10, &[0x1122_3344_u32, 0x5566_7788]), Err(AccessPortError::VerifyFailed {
+                ap: 0,
+                mismatches: vec![
+                    Mismatch { address: 0x16, expected: 0x66, actual: 0x00 },
+                    Mismatch { address: 0x17, expected: 0x55, actual: 0x00 },
+                ],
+            }));
+            assert_eq!(mi.write_bytes(&mut dap, 0x15, &[7, 8]), Err(AccessPortError::VerifyFailed {
+                ap: 0,
+                mismatches: vec![Mismatch { address: 0x16, expected: 8, actual: 0 }],
+            }));
+            assert_eq!(mi.write(&mut dap, 0x16, 0x99_u8), Err(AccessPortError::VerifyFailed {
+                ap: 0,
+                mismatches: vec![Mismatch { address: 0x16, expected: 0x99, actual: 0 }],
+            }));
+            assert!(!dap.raw().fault_pending());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn compare_block() {
+        let mut sim = SimulatedDap::new(0x100);
+        sim.memory[0x10..0x18].copy_from_slice(&[1, 0, 0, 0, 2, 0, 3, 0]);
+        let mut dap = RecordingDAP::new(AdiV5::new(sim), vec![]);
+        let mut mi = MemoryInterface::new(0x0);
+        mi.set_verify_policy(VerifyPolicy::PushedVerify);
+        assert_eq!(mi.compare_block(&mut dap, 0x10, &[1_u32, 0x0003_0002]), Ok(vec![]));
+        // A match is found without reading the memory back.
+        let (mut dap, log) = dap.into_inner().unwrap();
+        assert!(!String::from_utf8(log).unwrap().contains("R 0000 0000000C"));
+        assert_eq!(mi.compare_block(&mut dap, 0x12, &[0_u16, 2, 4]), Ok(vec![
+            Mismatch { address: 0x16, expected: 4, actual: 3 },
+        ]));
+        mi.set_verify_policy(VerifyPolicy::ReadBack);
+        assert_eq!(mi.compare_bytes(&mut dap, 0x11, &[0, 0, 1]), Ok(vec![
+            Mismatch { address: 0x13, expected: 1, actual: 0 },
+        ]));
+    }
+
+    #[test]
+    fn big_endian() {
+        let mut dap = MockDAP::new();
+        dap.set_big_endian(true);
+        let mut mi = MemoryInterface::new(0x0);
+        assert_eq!(mi.detect_endianness(&mut dap), Ok(true));
+
+        mi.write(&mut dap, 0x10, 0xDEAD_BEEF_u32).unwrap();
+        assert_eq!(dap.data[0x10..0x14], [0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(mi.read::<u16>(&mut dap, 0x12).unwrap(), 0xBEEF);
+        assert_eq!(mi.read::<u8>(&mut dap, 0x11).unwrap(), 0xAD);
+        mi.write(&mut dap, 0x12, 0x1234_u16).unwrap();
+        mi.write(&mut dap, 0x10, 0x56_u8).unwrap();
+        assert_eq!(dap.data[0x10..0x14], [0x56, 0xAD, 0x12, 0x34]);
+
+        let mut halfwords = [0_u16; 3];
+        mi.read_block(&mut dap, 0x10, &mut halfwords).unwrap();
+        assert_eq!(halfwords, [0x56AD, 0x1234, 0x0000]);
+        let mut bytes = [0; 6];
+        mi.read_bytes(&mut dap, 0x11, &mut bytes).unwrap();
+        assert_eq!(bytes, [0xAD, 0x12, 0x34, 0, 0, 0]);
+        mi.write_bytes(&mut dap, 0x13, &[1, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(dap.data[0x10..0x1A], [0x56, 0xAD, 0x12, 1, 2, 3, 4, 5, 6, 0]);
+        assert_eq!(mi.read::<u32>(&mut dap, 0x14).unwrap(), 0x0203_0405);
+    }
+
+    #[test]
+    fn big_endian_large_data() {
+        let mut sim = SimulatedDap::new(0x100);
+        sim.set_cfg(CFG_BE | CFG_LD);
+        let mut dap = AdiV5::new(sim);
+        let mut mi = MemoryInterface::new(0x0);
+        assert!(mi.probe(&mut dap).unwrap().big_endian);
+
+        mi.write(&mut dap, 0x10, 0x0123_4567_89AB_CDEF_u64).unwrap();
+        assert_eq!(dap.raw().memory[0x10..0x18], [0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF]);
+        assert_eq!(mi.read::<u64>(&mut dap, 0x10), Ok(0x0123_4567_89AB_CDEF));
+        assert_eq!(mi.read::<u32>(&mut dap, 0x14), Ok(0x89AB_CDEF));
+        assert_eq!(mi.verify_block(&mut dap, 0x10, &[0x0123_4567_89AB_CDEF_u64]), Ok(true));
+        assert_eq!(mi.verify_block(&mut dap, 0x10, &[0x4567_u16, 0x0123]), Ok(false));
+        assert_eq!(mi.verify_block(&mut dap, 0x10, &[0x0123_u16, 0x4567]), Ok(true));
+    }
+
+    #[test]
+    fn probe() {
+        let mut dap = AdiV5::new(SimulatedDap::new(0x1000));
+        let mut mi = MemoryInterface::new(0x0);
+        // DbgSwEnable is kept, the Prot bits are replaced by the access attributes.
+        dap.write_register(0, MEM_AP_CSW, 0x8000_0000 | CSW_HPROT | CSW_SIZE16).unwrap();
+        let capabilities = mi.probe(&mut dap).unwrap();
+        assert_eq!(capabilities, MemApCapabilities {
+            sizes: 0b111,
+            hnonsec_writable: true,
+            csw: 0x8000_0000 | CSW_HPROT,
+            ..MemApCapabilities::default()
+        });
+        assert_eq!(dap.read_register(0, MEM_AP_CSW).unwrap(), 0x8000_0000 | CSW_HPROT | CSW_DBGSTAT | CSW_SIZE16);
+        assert_eq!(mi.probe_tar_wrap(&mut dap, 0x0), Ok(0x400));
+
+        mi.write(&mut dap, 0x10, 0xDEADBEEF_u32).unwrap();
+        assert_eq!(dap.read_register(0, MEM_AP_CSW).unwrap(), 0x8000_0000 | CSW_VALUE | CSW_SIZE32);
+
+        dap.raw().set_cfg(CFG_LD);
+        assert_eq!(mi.probe(&mut dap).unwrap().sizes, 0b111111);
+
+        mi.set_capabilities(MemApCapabilities {
+            sizes: 1 << CSW_SIZE32,
+            ..*mi.capabilities()
+        });
+        // Narrow values are taken from and merged into the word around them.
+        assert_eq!(mi.read::<u8>(&mut dap, 0x11), Ok(0xBE));
+        mi.write(&mut dap, 0x12, 0x1234_u16).unwrap();
+        assert_eq!(mi.read::<u32>(&mut dap, 0x10), Ok(0x1234_BEEF));
+        mi.set_capabilities(MemApCapabilities {
+            sizes: 0,
+            ..*mi.capabilities()
+        });
+        assert_eq!(mi.read::<u8>(&mut dap, 0x10), Err(AccessPortError::UnsupportedAccessSize { ap: 0, size: 1 }));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn window() {
+        let mut dap = RecordingDAP::new(AdiV5::new(SimulatedDap::new(0x100)), vec![]);
+        let mi = MemoryInterface::new(0x0);
+        {
+            let mut window = mi.window(&mut dap, 0x44).unwrap();
+            assert_eq!(window.block(), Some(0x40));
+            window.write(0x48, 0x1234_5678).unwrap();
+            window.write(0x4C, 0x0000_0001).unwrap();
+            assert_eq!(window.read(0x48), Ok(0x1234_5678));
+            assert_eq!(window.read(0x4A), Err(AccessPortError::MemoryNotAligned { ap: 0, address: 0x4A, size: 4 }));
+            window.write(0x50, 0xDEADBEEF).unwrap();
+            assert_eq!(window.block(), Some(0x50));
+            match window.read(0x100) {
+                Err(AccessPortError::Fault { address, .. }) => assert_eq!(address, 0x100),
+                other => panic!("unexpected result: {:?}", other),
+            }
+            assert_eq!(window.block(), None);
+        }
+        assert_eq!(mi.read::<u32>(&mut dap, 0x4C), Ok(1));
+        assert_eq!(mi.read::<u32>(&mut dap, 0x50), Ok(0xDEADBEEF));
+        // One TAR write per block the window visited and one per read.
+        let log = String::from_utf8(dap.into_inner().unwrap().1).unwrap();
+        assert_eq!(log.lines().filter(|l| l.starts_with("W 0000 00000004")).count(), 5);
+    }
+
+    #[test]
+    fn access_attributes() {
+        let mut dap = AdiV5::new(SimulatedDap::new(0x100));
+        let mut mi = MemoryInterface::new(0x0);
+        let non_secure = AccessAttributes {
+            non_secure: true,
+            privileged: false,
+            ..AccessAttributes::default()
+        };
+        mi.with_attributes(non_secure).write(&mut dap, 0x10, 0x1234_u16).unwrap();
+        assert_eq!(dap.read_register(0, MEM_AP_CSW).unwrap(), CSW_HNONSEC | CSW_MSTRDBG | CSW_HPROT_DATA | CSW_DBGSTAT | CSW_SADDRINC | CSW_SIZE16);
+        mi.write(&mut dap, 0x10, 0x1234_u16).unwrap();
+        assert_eq!(dap.read_register(0, MEM_AP_CSW).unwrap(), CSW_VALUE | CSW_SIZE16);
+
+        let axi = MemApCapabilities {
+            ap_type: AP_TYPE_AXI,
+            csw: 0,
+            ..MemApCapabilities::default()
+        };
+        mi.set_capabilities(axi);
+        mi.set_attributes(AccessAttributes {
+            non_secure: true,
+            axi_cache: 0xF,
+            axi_domain: Some(0x2),
+            ..AccessAttributes::default()
+        });
+        assert_eq!(mi.read::<u32>(&mut dap, 0x10), Ok(0x1234));
+        assert_eq!(
+            dap.read_register(0, MEM_AP_CSW).unwrap(),
+            CSW_AXI_PRIVILEGED | CSW_AXI_NONSECURE | CSW_AXI_CACHE_MASK | 0x4000 | CSW_AXI_ACE_ENABLE | CSW_DBGSTAT | CSW_SADDRINC | CSW_SIZE32
+        );
+    }
+
+    #[test]
+    fn split_bytes_edges() {
+        assert_eq!(split_bytes(0x1, 10), vec![(0, 1), (1, 2), (3, 4), (7, 2), (9, 1)]);
+        assert_eq!(split_bytes(0x3, 2), vec![(0, 1), (1, 1)]);
+        assert_eq!(split_bytes(0x4, 0x400), vec![(0, 0x400)]);
+        assert_eq!(split_bytes(0x2, 0), vec![]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn read_bytes_transfers() {
+        let mut sim = SimulatedDap::new(0x100);
+        sim.memory.iter_mut().enumerate().for_each(|(i, byte)| *byte = i as u8);
+        let mut dap = RecordingDAP::new(AdiV5::new(sim), vec![]);
+        let mi = MemoryInterface::new(0x0);
+        let mut data = [0; 14];
+        mi.read_bytes(&mut dap, 0x1, &mut data).unwrap();
+        assert_eq!(data, [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14]);
+        let log = String::from_utf8(dap.into_inner().unwrap().1).unwrap();
+        assert_eq!(log.lines().filter(|l| l.starts_with("R 0000 0000000C")).count(), 6);
+    }
+
+    #[test]
+    fn write_bytes_without_narrow_accesses() {
+        let mut dap = AdiV5::new(SimulatedDap::new(0x100));
+        let mut mi = MemoryInterface::new(0x0);
+        mi.set_capabilities(MemApCapabilities {
+            sizes: 1 << CSW_SIZE32,
+            ..MemApCapabilities::default()
+        });
+        mi.write_bytes(&mut dap, 0x10, &[0xFF; 8]).unwrap();
+        mi.write_bytes(&mut dap, 0x11, &[1, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(dap.raw().memory[0x10..0x18], [0xFF, 1, 2, 3, 4, 5, 6, 0xFF]);
+        let mut data = [0; 3];
+        mi.read_bytes(&mut dap, 0x13, &mut data).unwrap();
+        assert_eq!(data, [3, 4, 5]);
+    }
+
+    #[test]
+    fn read_block_unaligned() {
+        let mut sim = SimulatedDap::new(0x100);
+        sim.memory.iter_mut().enumerate().for_each(|(i, byte)| *byte = i as u8);
+        let mut dap = AdiV5::new(sim);
+        let mi = MemoryInterface::new(0x0);
+        let mut bytes = [0_u8; 6];
+        mi.read_block(&mut dap, 0x1, &mut bytes).unwrap();
+        assert_eq!(bytes, [1, 2, 3, 4, 5, 6]);
+        let mut bytes = [0_u8; 2];
+        mi.read_block(&mut dap, 0x5, &mut bytes).unwrap();
+        assert_eq!(bytes, [5, 6]);
+        let mut halfwords = [0_u16; 3];
+        mi.read_block(&mut dap, 0x2, &mut halfwords).unwrap();
+        assert_eq!(halfwords, [0x0302, 0x0504, 0x0706]);
+    }
+
+    proptest! {
+        #[test]
+        fn bytes_match_reference_model(
+            memory in prop::collection::vec(any::<u8>(), 0x500),
+            write_addr in 0_usize..0x500,
+            write_data in prop::collection::vec(any::<u8>(), 0..0x100),
+            read_addr in 0_usize..0x500,
+            read_len in 0_usize..0x100,
+            big_endian in any::<bool>(),
+        ) {
+            let mut sim = SimulatedDap::new(0x500);
+            sim.memory.copy_from_slice(&memory);
+            sim.set_cfg(if big_endian { CFG_BE } else { 0 });
+            let mut dap = AdiV5::new(sim);
+            let mut mi = MemoryInterface::new(0x0);
+            mi.detect_endianness(&mut dap).unwrap();
+            let mut model = memory;
+
+            let write_len = write_data.len().min(0x500 - write_addr);
+            mi.write_bytes(&mut dap, write_addr as u64, &write_data[..write_len]).unwrap();
+            model[write_addr..write_addr + write_len].copy_from_slice(&write_data[..write_len]);
+            prop_assert_eq!(&dap.raw().memory, &model);
+
+            let read_len = read_len.min(0x500 - read_addr);
+            let mut data = vec![0; read_len];
+            mi.read_bytes(&mut dap, read_addr as u64, &mut data).unwrap();
+            prop_assert_eq!(&data[..], &model[read_addr..read_addr + read_len]);
+        }
+    }
+}
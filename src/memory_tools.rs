@@ -0,0 +1,303 @@
+use crate::access_port::{AccessPortError, Mismatch};
+use crate::dap_access::DAPAccess;
+use crate::memory_interface::{MemoryInterface, VerifyPolicy};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// How many bytes the host-side implementations read or write at once.
+const CHUNK_SIZE: usize = 0x1000;
+
+/// The CRC-32 of IEEE 802.3, as used by zlib and Ethernet.
+const CRC32_POLYNOMIAL: u32 = 0xEDB8_8320;
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ CRC32_POLYNOMIAL } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+/// Continues the CRC-32 `crc` over `data`. Start with 0.
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |crc, byte| CRC32_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+/// Runs the memory utilities on the target, e.g. as code loaded into its RAM and run by one of its cores.
+///
+/// The memory layer does not control cores, so implementations come from the caller. Each
+/// method returns `None` if it can not handle the request, which makes the host-side
+/// implementation run instead.
+pub trait TargetRoutines<D: DAPAccess> {
+    fn fill(&mut self, _debug_port: &mut D, _addr: u64, _len: usize, _pattern: &[u8]) -> Option<Result<(), AccessPortError>> {
+        None
+    }
+
+    fn compare(&mut self, _debug_port: &mut D, _addr: u64, _data: &[u8]) -> Option<Result<Vec<Mismatch>, AccessPortError>> {
+        None
+    }
+
+    fn find(&mut self, _debug_port: &mut D, _range: Range<u64>, _needle: &[u8]) -> Option<Result<Option<u64>, AccessPortError>> {
+        None
+    }
+
+    fn crc32(&mut self, _debug_port: &mut D, _range: Range<u64>) -> Option<Result<u32, AccessPortError>> {
+        None
+    }
+}
+
+/// Runs everything on the host.
+pub struct HostOnly;
+
+impl<D: DAPAccess> TargetRoutines<D> for HostOnly {}
+
+/// Fills, compares, searches and checksums target memory.
+///
+/// Each utility is first offered to the target routines and runs on the host if they
+/// decline it. The host-side implementations go through `read_bytes` and `write_bytes`,
+/// which batch word accesses per TAR wrap block.
+pub struct MemoryTools<'a, D: DAPAccess, R: TargetRoutines<D> = HostOnly> {
+    interface: &'a MemoryInterface,
+    debug_port: &'a mut D,
+    routines: R,
+}
+
+impl<'a, D: DAPAccess> MemoryTools<'a, D> {
+    pub fn new(interface: &'a MemoryInterface, debug_port: &'a mut D) -> Self {
+        Self {
+            interface,
+            debug_port,
+            routines: HostOnly,
+        }
+    }
+}
+
+impl<'a, D: DAPAccess, R: TargetRoutines<D>> MemoryTools<'a, D, R> {
+    /// Offers the utilities to `routines` before running them on the host.
+    pub fn with_routines<T: TargetRoutines<D>>(self, routines: T) -> MemoryTools<'a, D, T> {
+        MemoryTools {
+            interface: self.interface,
+            debug_port: self.debug_port,
+            routines,
+        }
+    }
+
+    pub fn routines(&mut self) -> &mut R {
+        &mut self.routines
+    }
+
+    /// Fills `len` bytes from `addr` with `pattern` repeated, starting with its first byte.
+    ///
+    /// Panics if `pattern` is empty.
+    pub fn fill(&mut self, addr: u64, len: usize, pattern: &[u8]) -> Result<(), AccessPortError> {
+        assert!(!pattern.is_empty(), "the fill pattern is empty");
+        if let Some(result) = self.routines.fill(self.debug_port, addr, len, pattern) {
+            return result;
+        }
+        // Whole patterns and whole words in each chunk, so the chunks after the first one
+        // start word aligned and at the same position in the pattern.
+        let unit = pattern.len() / gcd(pattern.len(), 4) * 4;
+        let chunk = CHUNK_SIZE.div_ceil(unit) * unit;
+        let buffer = pattern.iter().copied().cycle().take(chunk + pattern.len()).collect::<Vec<_>>();
+        let mut offset = 0;
+        while offset < len {
+            let size = (chunk - (addr as usize + offset) % 4).min(len - offset);
+            let start = offset % pattern.len();
+            self.interface.write_bytes(self.debug_port, addr + offset as u64, &buffer[start..start + size])?;
+            offset += size;
+        }
+        Ok(())
+    }
+
+    /// Compares the memory at `addr` with `data` and returns the bytes which differ.
+    ///
+    /// Pushed verify finds out whether anything differs unless the verify policy asks to
+    /// read back, only a difference is read back to report it.
+    pub fn compare(&mut self, addr: u64, data: &[u8]) -> Result<Vec<Mismatch>, AccessPortError> {
+        if let Some(result) = self.routines.compare(self.debug_port, addr, data) {
+            return result;
+        }
+        let mut interface = self.interface.clone();
+        if interface.verify_policy() == VerifyPolicy::Off {
+            interface.set_verify_policy(VerifyPolicy::PushedVerify);
+        }
+        let mut mismatches = Vec::new();
+        for (offset, data) in (0..).step_by(CHUNK_SIZE).zip(data.chunks(CHUNK_SIZE)) {
+            mismatches.extend(interface.compare_bytes(self.debug_port, addr + offset, data)?);
+        }
+        Ok(mismatches)
+    }
+
+    /// Returns the address of the first occurrence of `needle` which lies completely within `range`.
+    pub fn find(&mut self, range: Range<u64>, needle: &[u8]) -> Result<Option<u64>, AccessPortError> {
+        if let Some(result) = self.routines.find(self.debug_port, range.clone(), needle) {
+            return result;
+        }
+        if needle.is_empty() {
+            return Ok(Some(range.start).filter(|_| range.start <= range.end));
+        }
+        // Keeps the end of the previous chunk for matches across chunks.
+        let mut buffer = Vec::with_capacity(CHUNK_SIZE + needle.len());
+        let mut start = range.start;
+        let mut addr = range.start;
+        while addr < range.end {
+            let size = (CHUNK_SIZE as u64).min(range.end - addr) as usize;
+            let end = buffer.len();
+            buffer.resize(end + size, 0);
+            self.interface.read_bytes(self.debug_port, addr, &mut buffer[end..])?;
+            if let Some(position) = buffer.windows(needle.len()).position(|window| window == needle) {
+                return Ok(Some(start + position as u64));
+            }
+            let keep = buffer.len().min(needle.len() - 1);
+            start += (buffer.len() - keep) as u64;
+            buffer.drain(..buffer.len() - keep);
+            addr += size as u64;
+        }
+        Ok(None)
+    }
+
+    /// Returns the CRC-32 of the memory in `range`, as computed by `crc32_update`.
+    pub fn crc32(&mut self, range: Range<u64>) -> Result<u32, AccessPortError> {
+        if let Some(result) = self.routines.crc32(self.debug_port, range.clone()) {
+            return result;
+        }
+        let mut buffer = vec![0; CHUNK_SIZE];
+        let mut crc = 0;
+        let mut addr = range.start;
+        while addr < range.end {
+            let size = (CHUNK_SIZE as u64).min(range.end - addr) as usize;
+            self.interface.read_bytes(self.debug_port, addr, &mut buffer[..size])?;
+            crc = crc32_update(crc, &buffer[..size]);
+            addr += size as u64;
+        }
+        Ok(crc)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{crc32_update, MemoryTools, TargetRoutines};
+    #[cfg(feature = "std")]
+    use crate::access_port::consts::*;
+    use crate::access_port::AccessPortError;
+    #[cfg(feature = "std")]
+    use crate::access_port::Mismatch;
+    use crate::memory_interface::MemoryInterface;
+    use crate::probes::adiv5::AdiV5;
+    use crate::probes::sim::SimulatedDap;
+    #[cfg(feature = "std")]
+    use crate::recording::{Record, RecordingDAP};
+    use std::ops::Range;
+
+    #[test]
+    fn crc32() {
+        assert_eq!(crc32_update(0, b""), 0);
+        assert_eq!(crc32_update(0, b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32_update(crc32_update(0, b"1234"), b"56789"), 0xCBF4_3926);
+
+        let mut sim = SimulatedDap::new(0x3000);
+        sim.memory[0x1003..0x100C].copy_from_slice(b"123456789");
+        let mut dap = AdiV5::new(sim);
+        let mi = MemoryInterface::new(0x0);
+        let mut tools = MemoryTools::new(&mi, &mut dap);
+        assert_eq!(tools.crc32(0x1003..0x100C), Ok(0xCBF4_3926));
+        let expected = crc32_update(0, &vec![0; 0x1FF4]);
+        assert_eq!(tools.crc32(0x100C..0x3000), Ok(expected));
+    }
+
+    #[test]
+    fn fill() {
+        let mut dap = AdiV5::new(SimulatedDap::new(0x3000));
+        let mi = MemoryInterface::new(0x0);
+        mi.tools(&mut dap).fill(0x3, 0x2005, &[1, 2, 3]).unwrap();
+        let memory = &dap.raw().memory;
+        assert_eq!(memory[..0x8], [0, 0, 0, 1, 2, 3, 1, 2]);
+        assert!(memory[0x3..0x2008].iter().enumerate().all(|(i, byte)| *byte == [1, 2, 3][i % 3]));
+        assert_eq!(memory[0x2008], 0);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn fill_in_word_chunks() {
+        let mut dap = RecordingDAP::new(AdiV5::new(SimulatedDap::new(0x3000)), vec![]);
+        let mi = MemoryInterface::new(0x0);
+        mi.tools(&mut dap).fill(0x3, 0x2005, &[1, 2, 3]).unwrap();
+        let (dap, log) = dap.into_inner().unwrap();
+        assert!(dap.into_inner().memory[0x3..0x2008].iter().enumerate().all(|(i, byte)| *byte == [1, 2, 3][i % 3]));
+        // Only the first byte needs a narrow access.
+        let narrow = String::from_utf8(log).unwrap().lines()
+            .map(|line| line.parse::<Record>().unwrap())
+            .filter(|record| record.addr == MEM_AP_CSW && record.value & CSW_SIZE != CSW_SIZE32)
+            .count();
+        assert_eq!(narrow, 1);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn compare() {
+        let mut sim = SimulatedDap::new(0x100);
+        sim.memory[0x20..0x30].copy_from_slice(&[7; 0x10]);
+        let mut dap = RecordingDAP::new(AdiV5::new(sim), vec![]);
+        let mi = MemoryInterface::new(0x0);
+        let mut tools = MemoryTools::new(&mi, &mut dap);
+        assert_eq!(tools.compare(0x20, &[7; 0x10]), Ok(vec![]));
+        assert_eq!(tools.compare(0x1F, &[0, 7, 8]), Ok(vec![Mismatch { address: 0x21, expected: 8, actual: 7 }]));
+        // Only the mismatch is read back, the match is verified on the target.
+        let log = String::from_utf8(dap.into_inner().unwrap().1).unwrap();
+        assert_eq!(log.lines().filter(|l| l.starts_with("R 0000 0000000C")).count(), 2);
+    }
+
+    #[test]
+    fn find() {
+        let mut sim = SimulatedDap::new(0x3000);
+        sim.memory[0xFFE..0x1002].copy_from_slice(b"abcd");
+        sim.memory[0x2000..0x2004].copy_from_slice(b"abcd");
+        let mut dap = AdiV5::new(sim);
+        let mi = MemoryInterface::new(0x0);
+        let mut tools = MemoryTools::new(&mi, &mut dap);
+        assert_eq!(tools.find(0x0..0x3000, b"abcd"), Ok(Some(0xFFE)));
+        assert_eq!(tools.find(0xFFF..0x3000, b"abcd"), Ok(Some(0x2000)));
+        assert_eq!(tools.find(0xFFF..0x2003, b"abcd"), Ok(None));
+        assert_eq!(tools.find(0x10..0x20, b""), Ok(Some(0x10)));
+        assert_eq!(tools.find(0x0..0x3000, b"abce"), Ok(None));
+    }
+
+    /// Checksums on the target, but leaves large ranges to the host.
+    struct SmallCrc {
+        calls: usize,
+    }
+
+    impl<D: crate::dap_access::DAPAccess> TargetRoutines<D> for SmallCrc {
+        fn crc32(&mut self, _debug_port: &mut D, range: Range<u64>) -> Option<Result<u32, AccessPortError>> {
+            self.calls += 1;
+            if range.end - range.start <= 0x10 { Some(Ok(0x1234_5678)) } else { None }
+        }
+    }
+
+    #[test]
+    fn target_routines() {
+        let mut dap = AdiV5::new(SimulatedDap::new(0x100));
+        let mi = MemoryInterface::new(0x0);
+        let mut tools = MemoryTools::new(&mi, &mut dap).with_routines(SmallCrc { calls: 0 });
+        assert_eq!(tools.crc32(0x0..0x10), Ok(0x1234_5678));
+        assert_eq!(tools.crc32(0x0..0x20), Ok(crc32_update(0, &[0; 0x20])));
+        assert_eq!(tools.routines().calls, 2);
+        tools.fill(0x0, 0x4, &[1]).unwrap();
+        assert_eq!(dap.raw().memory[..0x5], [1, 1, 1, 1, 0]);
+    }
+}
@@ -0,0 +1,196 @@
+use crate::dap_access::consts::*;
+use crate::dap_access::{DAPAccess, Transfer, TransferError, TransferErrorKind, TransferKind, DEBUG_PORT};
+
+const DEFAULT_OVERRUN_RETRIES: usize = 8;
+
+/// Runs transfers back to back with overrun detection turned on.
+///
+/// With ORUNDETECT set in CTRL/STAT the DP refuses every transfer after one which was
+/// answered with WAIT and flags STICKYORUN, so a probe can stream transfers without
+/// waiting for each acknowledge. After an overrun, the transfers which were not done are
+/// retried once STICKYORUN is cleared. Other sticky errors are left for the caller.
+pub struct PipelinedDAP<D: DAPAccess> {
+    dap: D,
+    enabled: bool,
+    max_retries: usize,
+}
+
+impl<D: DAPAccess> PipelinedDAP<D> {
+    pub fn new(dap: D) -> Self {
+        Self {
+            dap,
+            enabled: false,
+            max_retries: DEFAULT_OVERRUN_RETRIES,
+        }
+    }
+
+    pub fn inner(&mut self) -> &mut D {
+        &mut self.dap
+    }
+
+    pub fn into_inner(self) -> D {
+        self.dap
+    }
+
+    /// Sets how often the lost transfers of a single batch are retried.
+    pub fn set_max_retries(&mut self, max_retries: usize) {
+        self.max_retries = max_retries;
+    }
+
+    /// Turns on ORUNDETECT. This happens on the first transfer and has to be repeated
+    /// with `reenable` after the DP was reset.
+    fn enable(&mut self) -> Result<(), D::Error> {
+        if !self.enabled {
+            let ctrl_stat = self.dap.read_register(DEBUG_PORT, DP_CTRL_STAT)?;
+            // Writing ones to the sticky flags would clear them on a SW-DP.
+            let sticky = CTRLSTAT_STICKYORUN | CTRLSTAT_STICKYCMP | CTRLSTAT_STICKYERR | CTRLSTAT_WDATAERR;
+            self.dap.write_register(DEBUG_PORT, DP_CTRL_STAT, (ctrl_stat & !sticky) | CTRLSTAT_ORUNDETECT)?;
+            self.enabled = true;
+        }
+        Ok(())
+    }
+
+    /// Makes the next transfer turn ORUNDETECT on again.
+    pub fn reenable(&mut self) {
+        self.enabled = false;
+    }
+
+    /// Runs `transfers` and retries the ones lost to an overrun.
+    fn run(&mut self, transfers: &mut [Transfer]) -> Result<(), D::Error> {
+        let mut start = 0;
+        let mut retries = 0;
+        loop {
+            let error = match self.dap.batch(&mut transfers[start..]) {
+                Ok(()) => return Ok(()),
+                Err(error) => error,
+            };
+            start += transfers[start..].iter().take_while(|t| t.done).count();
+            if retries == self.max_retries || !self.overrun(&error)? {
+                return Err(error);
+            }
+            log::debug!("overrun detected, retrying {} lost transfers", transfers.len() - start);
+            retries += 1;
+        }
+    }
+
+    /// Checks whether a failed batch was cut short by an overrun and clears it.
+    fn overrun(&mut self, error: &D::Error) -> Result<bool, D::Error> {
+        match error.kind() {
+            TransferErrorKind::Wait | TransferErrorKind::Fault => (),
+            _ => return Ok(false),
+        }
+        let ctrl_stat = self.dap.read_register(DEBUG_PORT, DP_CTRL_STAT)?;
+        let errors = CTRLSTAT_STICKYERR | CTRLSTAT_STICKYCMP | CTRLSTAT_WDATAERR;
+        if ctrl_stat & CTRLSTAT_STICKYORUN == 0 || ctrl_stat & errors != 0 {
+            return Ok(false);
+        }
+        self.dap.write_register(DEBUG_PORT, DP_ABORT, ABORT_ORUNERRCLR)?;
+        Ok(true)
+    }
+}
+
+impl<D: DAPAccess> DAPAccess for PipelinedDAP<D> {
+    type Error = D::Error;
+
+    fn read_register(&mut self, port: u16, addr: u32) -> Result<u32, Self::Error> {
+        let mut transfers = [Transfer::read(port, addr)];
+        self.batch(&mut transfers)?;
+        Ok(transfers[0].value)
+    }
+
+    fn write_register(&mut self, port: u16, addr: u32, value: u32) -> Result<(), Self::Error> {
+        self.batch(&mut [Transfer::write(port, addr, value)])
+    }
+
+    fn batch(&mut self, transfers: &mut [Transfer]) -> Result<(), Self::Error> {
+        self.enable()?;
+        let writes_ctrl_stat = |transfer: &Transfer| {
+            transfer.port == DEBUG_PORT && transfer.addr == DP_CTRL_STAT && transfer.kind == TransferKind::Write
+        };
+        if !transfers.iter().any(writes_ctrl_stat) {
+            return self.run(transfers);
+        }
+
+        // Keep overrun detection on when CTRL/STAT is written, without changing the
+        // values the caller asked for.
+        let mut sent = transfers.to_vec();
+        for transfer in sent.iter_mut().filter(|t| writes_ctrl_stat(t)) {
+            transfer.value |= CTRLSTAT_ORUNDETECT;
+        }
+        let result = self.run(&mut sent);
+        for (transfer, sent) in transfers.iter_mut().zip(sent) {
+            transfer.done = sent.done;
+            if transfer.kind == TransferKind::Read {
+                transfer.value = sent.value;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PipelinedDAP;
+    use crate::access_port::consts::*;
+    use crate::dap_access::consts::*;
+    use crate::dap_access::{DAPAccess, Transfer, DEBUG_PORT};
+    use crate::probes::adiv5::{AdiV5, DapError};
+    use crate::probes::sim::SimulatedDap;
+
+    fn write_words(dap: &mut impl DAPAccess, addr: u32, words: &[u32]) -> Vec<Transfer> {
+        let mut transfers = vec![
+            Transfer::write(0, MEM_AP_CSW, CSW_VALUE | CSW_SIZE32),
+            Transfer::write(0, MEM_AP_TAR, addr),
+        ];
+        transfers.extend(words.iter().map(|word| Transfer::write(0, MEM_AP_DRW, *word)));
+        transfers.push(Transfer::write(0, MEM_AP_TAR, addr));
+        transfers.extend(words.iter().map(|_| Transfer::read(0, MEM_AP_DRW)));
+        let _ = dap.batch(&mut transfers);
+        transfers
+    }
+
+    #[test]
+    fn retries_lost_transfers() {
+        let mut dap = PipelinedDAP::new(AdiV5::new(SimulatedDap::new(0x100)));
+        dap.inner().raw().inject_wait(4);
+        let transfers = write_words(&mut dap, 0x20, &[1, 2, 3, 4]);
+        assert!(transfers.iter().all(|t| t.done));
+        assert_eq!(transfers[7..].iter().map(|t| t.value).collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+
+        let mut dap = dap.into_inner();
+        assert_eq!(dap.read_register(DEBUG_PORT, DP_CTRL_STAT).unwrap() & (CTRLSTAT_ORUNDETECT | CTRLSTAT_STICKYORUN), CTRLSTAT_ORUNDETECT);
+        assert_eq!(dap.raw().memory[0x20..0x30], [1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0]);
+    }
+
+    #[test]
+    fn wait_without_overrun_detection() {
+        let mut dap = AdiV5::new(SimulatedDap::new(0x100));
+        dap.raw().inject_wait(4);
+        let transfers = write_words(&mut dap, 0x20, &[1, 2, 3, 4]);
+        assert_eq!(transfers.iter().filter(|t| t.done).count(), 4);
+        assert!(!dap.raw().fault_pending());
+    }
+
+    #[test]
+    fn faults_are_not_retried() {
+        let mut dap = PipelinedDAP::new(AdiV5::new(SimulatedDap::new(0x100)));
+        match dap.write_register(0, MEM_AP_TAR, 0x100).and_then(|_| dap.write_register(0, MEM_AP_DRW, 0)) {
+            Err(DapError::Fault) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(dap.into_inner().raw().sticky_error());
+    }
+
+    #[test]
+    fn keeps_overrun_detection_on() {
+        let mut dap = PipelinedDAP::new(AdiV5::new(SimulatedDap::new(0x100)));
+        let mut transfers = [
+            Transfer::write(DEBUG_PORT, DP_CTRL_STAT, CDBGPWRUPREQ | CSYSPWRUPREQ),
+            Transfer::read(DEBUG_PORT, DP_CTRL_STAT),
+        ];
+        dap.batch(&mut transfers).unwrap();
+        assert!(transfers.iter().all(|t| t.done));
+        assert_eq!(transfers[0].value, CDBGPWRUPREQ | CSYSPWRUPREQ);
+        assert_ne!(transfers[1].value & CTRLSTAT_ORUNDETECT, 0);
+    }
+}
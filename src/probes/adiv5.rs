@@ -0,0 +1,206 @@
+use crate::dap_access::consts::*;
+use crate::dap_access::{DAPAccess, TransferError, TransferErrorKind, DEBUG_PORT};
+use core::error::Error;
+use core::fmt;
+
+/// Selects whether a raw transfer targets the DP or the currently selected AP.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PortType {
+    DebugPort,
+    AccessPort,
+}
+
+#[derive(Debug)]
+pub enum DapError<E> {
+    /// The underlying probe transport failed.
+    Io(E),
+    /// The target answered WAIT until all retries were used up.
+    Wait,
+    /// The target answered FAULT.
+    Fault,
+    /// The target answered with an invalid acknowledge, usually because nothing is connected.
+    Protocol(u8),
+    /// The parity of the data read from the target did not match.
+    Parity,
+}
+
+impl<E: fmt::Display> fmt::Display for DapError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DapError::Io(e) => write!(f, "probe transport failed: {}", e),
+            DapError::Wait => f.write_str("the target kept answering WAIT"),
+            DapError::Fault => f.write_str("the target answered FAULT"),
+            DapError::Protocol(ack) => write!(f, "invalid acknowledge {:#05b}", ack),
+            DapError::Parity => f.write_str("parity error in read data"),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for DapError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DapError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl<E: Error + Send + Sync + 'static> TransferError for DapError<E> {
+    fn kind(&self) -> TransferErrorKind {
+        match self {
+            DapError::Io(_) => TransferErrorKind::Other,
+            DapError::Wait => TransferErrorKind::Wait,
+            DapError::Fault => TransferErrorKind::Fault,
+            DapError::Protocol(_) => TransferErrorKind::Protocol,
+            DapError::Parity => TransferErrorKind::Parity,
+        }
+    }
+}
+
+/// Raw access to the DP and AP registers as seen on the wire.
+///
+/// `addr` is one of the four register addresses `0x0`, `0x4`, `0x8` and `0xC`.
+/// AP transfers go to the AP and bank selected in DP SELECT. Implementors hide posted
+/// reads, so `raw_read` always returns the value of the register which was read.
+pub trait RawDapAccess {
+    type Error: TransferError;
+
+    fn raw_read(&mut self, port: PortType, addr: u8) -> Result<u32, Self::Error>;
+
+    fn raw_write(&mut self, port: PortType, addr: u8, value: u32) -> Result<(), Self::Error>;
+}
+
+/// Implements `DAPAccess` on top of raw register transfers by programming DP SELECT.
+pub struct AdiV5<R: RawDapAccess> {
+    raw: R,
+    select: Option<u32>,
+}
+
+impl<R: RawDapAccess> AdiV5<R> {
+    pub fn new(raw: R) -> Self {
+        Self {
+            raw,
+            select: None,
+        }
+    }
+
+    pub fn raw(&mut self) -> &mut R {
+        &mut self.raw
+    }
+
+    pub fn into_inner(self) -> R {
+        self.raw
+    }
+
+    /// Forgets the cached SELECT value so it gets written again on the next access.
+    ///
+    /// This has to be called whenever the DP might have lost its state, e.g. after a reset.
+    pub fn invalidate_select(&mut self) {
+        self.select = None;
+    }
+
+    /// Forgets SELECT after a failed transfer, the DP might have been reset in between.
+    fn failed(&mut self, error: R::Error) -> R::Error {
+        self.select = None;
+        error
+    }
+
+    fn select(&mut self, select: u32) -> Result<(), R::Error> {
+        if self.select != Some(select) {
+            // Invalidate first so a failed write leaves no stale value behind.
+            self.select = None;
+            self.raw.raw_write(PortType::DebugPort, DP_SELECT as u8, select)?;
+            self.select = Some(select);
+        }
+        Ok(())
+    }
+
+    fn select_for(&self, port: u16, addr: u32) -> Option<u32> {
+        let current = self.select.unwrap_or(0);
+        if port == DEBUG_PORT {
+            // Only CTRL/STAT is banked, everything else ignores DPBANKSEL.
+            let bank = (addr >> 4) & SELECT_DPBANKSEL_MASK;
+            if addr & 0xC == DP_CTRL_STAT && (current & SELECT_DPBANKSEL_MASK) != bank {
+                Some((current & !SELECT_DPBANKSEL_MASK) | bank)
+            } else {
+                None
+            }
+        } else {
+            Some(((port as u32) << SELECT_APSEL_SHIFT) | (addr & SELECT_APBANKSEL_MASK) | (current & SELECT_DPBANKSEL_MASK))
+        }
+    }
+}
+
+impl<R: RawDapAccess> DAPAccess for AdiV5<R> {
+    type Error = R::Error;
+
+    fn read_register(&mut self, port: u16, addr: u32) -> Result<u32, Self::Error> {
+        if let Some(select) = self.select_for(port, addr) {
+            self.select(select)?;
+        }
+        let port_type = if port == DEBUG_PORT { PortType::DebugPort } else { PortType::AccessPort };
+        self.raw.raw_read(port_type, (addr & 0xC) as u8).map_err(|e| self.failed(e))
+    }
+
+    fn write_register(&mut self, port: u16, addr: u32, value: u32) -> Result<(), Self::Error> {
+        if port == DEBUG_PORT && addr == DP_SELECT {
+            self.select = None;
+        } else if let Some(select) = self.select_for(port, addr) {
+            self.select(select)?;
+        }
+        let port_type = if port == DEBUG_PORT { PortType::DebugPort } else { PortType::AccessPort };
+        self.raw.raw_write(port_type, (addr & 0xC) as u8, value).map_err(|e| self.failed(e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AdiV5, DapError, PortType, RawDapAccess};
+    use crate::dap_access::{DAPAccess, DEBUG_PORT};
+    use std::convert::Infallible;
+
+    #[derive(Default)]
+    struct LoggingDap {
+        log: Vec<(PortType, bool, u8, u32)>,
+    }
+
+    impl RawDapAccess for LoggingDap {
+        type Error = DapError<Infallible>;
+
+        fn raw_read(&mut self, port: PortType, addr: u8) -> Result<u32, Self::Error> {
+            self.log.push((port, true, addr, 0));
+            Ok(0)
+        }
+
+        fn raw_write(&mut self, port: PortType, addr: u8, value: u32) -> Result<(), Self::Error> {
+            self.log.push((port, false, addr, value));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn select_is_written_only_on_change() {
+        let mut dap = AdiV5::new(LoggingDap::default());
+        dap.write_register(1, 0x04, 0x2000_0000).unwrap();
+        dap.read_register(1, 0x0C).unwrap();
+        dap.read_register(1, 0xFC).unwrap();
+        dap.read_register(DEBUG_PORT, 0x04).unwrap();
+        assert_eq!(dap.into_inner().log, vec![
+            (PortType::DebugPort, false, 0x8, 0x0100_0000),
+            (PortType::AccessPort, false, 0x4, 0x2000_0000),
+            (PortType::AccessPort, true, 0xC, 0),
+            (PortType::DebugPort, false, 0x8, 0x0100_00F0),
+            (PortType::AccessPort, true, 0xC, 0),
+            (PortType::DebugPort, true, 0x4, 0),
+        ]);
+    }
+
+    #[test]
+    fn invalidate_select_forces_rewrite() {
+        let mut dap = AdiV5::new(LoggingDap::default());
+        dap.read_register(0, 0x00).unwrap();
+        dap.invalidate_select();
+        dap.read_register(0, 0x00).unwrap();
+        assert_eq!(dap.into_inner().log.iter().filter(|(_, _, addr, _)| *addr == 0x8).count(), 2);
+    }
+}
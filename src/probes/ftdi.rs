@@ -0,0 +1,451 @@
+use crate::probes::jtag::JtagIo;
+use crate::probes::swd::SwdIo;
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub mod commands {
+    // Data shifting commands, all LSB first. Data is written on the falling and
+    // read on the rising edge of the clock.
+    pub const WRITE_BYTES: u8 = 0x19;
+    pub const WRITE_BITS: u8 = 0x1B;
+    pub const READ_BYTES: u8 = 0x28;
+    pub const READ_BITS: u8 = 0x2A;
+    pub const READ_WRITE_BYTES: u8 = 0x39;
+    pub const READ_WRITE_BITS: u8 = 0x3B;
+    pub const WRITE_TMS_BITS: u8 = 0x4B;
+    pub const READ_WRITE_TMS_BITS: u8 = 0x6B;
+
+    // Configuration commands.
+    pub const SET_BITS_LOW: u8 = 0x80;
+    pub const LOOPBACK_DISABLE: u8 = 0x85;
+    pub const SET_CLOCK_DIVISOR: u8 = 0x86;
+    pub const SEND_IMMEDIATE: u8 = 0x87;
+    pub const DISABLE_CLOCK_DIVIDE_BY_5: u8 = 0x8A;
+    pub const DISABLE_3_PHASE_CLOCKING: u8 = 0x8D;
+    pub const DISABLE_ADAPTIVE_CLOCKING: u8 = 0x97;
+}
+
+pub mod pins {
+    // Low byte pins of the MPSSE.
+    pub const TCK: u8 = 0x01;
+    pub const TDI: u8 = 0x02;
+    pub const TDO: u8 = 0x04;
+    pub const TMS: u8 = 0x08;
+
+    // For SWD, TDI and TDO are tied together to form SWDIO.
+    pub const SWCLK: u8 = TCK;
+    pub const SWDIO_OUT: u8 = TDI;
+    pub const SWDIO_IN: u8 = TDO;
+
+    // The upper four bits are free for adapter specific signals.
+    pub const GPIO_MASK: u8 = 0xF0;
+}
+
+/// The MPSSE clock of a FT2232H with the divide-by-5 prescaler disabled.
+const MPSSE_BASE_CLOCK: u32 = 60_000_000;
+
+/// The most TMS bits a single TMS command can carry.
+const MAX_TMS_BITS: usize = 7;
+
+/// The raw byte stream to and from an FTDI chip in MPSSE mode.
+pub trait FtdiTransport {
+    type Error: core::error::Error + Send + Sync + 'static;
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Fills all of `data` with bytes returned by the MPSSE.
+    fn read(&mut self, data: &mut [u8]) -> Result<(), Self::Error>;
+}
+
+/// Turns SWD and JTAG bit operations into MPSSE commands.
+///
+/// Commands which do not return data are buffered until the next read or an explicit `flush`.
+pub struct FtdiMpsse<T: FtdiTransport> {
+    transport: T,
+    buffer: Vec<u8>,
+    value: u8,
+    direction: u8,
+}
+
+/// Assembles up to 64 bits which were clocked in LSB first.
+///
+/// Full bytes arrive as they are. The bits of a trailing partial byte are shifted in
+/// from the top, so they sit in its upper `bits` bits.
+fn assemble(bytes: &[u8], bits: usize) -> u64 {
+    let full = bits / 8;
+    let mut value = bytes[..full]
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (i, byte)| acc | ((*byte as u64) << (i * 8)));
+    let rem = bits % 8;
+    if rem > 0 {
+        value |= ((bytes[full] >> (8 - rem)) as u64) << (full * 8);
+    }
+    value
+}
+
+impl<T: FtdiTransport> FtdiMpsse<T> {
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            buffer: vec![],
+            value: 0,
+            direction: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// Writes all buffered commands to the chip.
+    pub fn flush(&mut self) -> Result<(), T::Error> {
+        if !self.buffer.is_empty() {
+            self.transport.write(&self.buffer)?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+
+    /// Flushes the buffered commands and reads `len` bytes of replies.
+    fn flush_and_read(&mut self, len: usize) -> Result<Vec<u8>, T::Error> {
+        self.buffer.push(commands::SEND_IMMEDIATE);
+        self.flush()?;
+        let mut data = vec![0; len];
+        self.transport.read(&mut data)?;
+        Ok(data)
+    }
+
+    fn set_pins(&mut self) {
+        self.buffer.extend_from_slice(&[commands::SET_BITS_LOW, self.value, self.direction]);
+    }
+
+    fn init(&mut self, value: u8, direction: u8) -> Result<(), T::Error> {
+        self.buffer.extend_from_slice(&[
+            commands::LOOPBACK_DISABLE,
+            commands::DISABLE_CLOCK_DIVIDE_BY_5,
+            commands::DISABLE_ADAPTIVE_CLOCKING,
+            commands::DISABLE_3_PHASE_CLOCKING,
+        ]);
+        self.value = (self.value & pins::GPIO_MASK) | value;
+        self.direction = (self.direction & pins::GPIO_MASK) | direction;
+        self.set_pins();
+        self.flush()
+    }
+
+    /// Configures the pins for SWD with SWCLK low and SWDIO driven by the probe.
+    pub fn init_swd(&mut self) -> Result<(), T::Error> {
+        self.init(0, pins::SWCLK | pins::SWDIO_OUT)
+    }
+
+    /// Configures the pins for JTAG with TCK low and TMS high.
+    pub fn init_jtag(&mut self) -> Result<(), T::Error> {
+        self.init(pins::TMS, pins::TCK | pins::TDI | pins::TMS)
+    }
+
+    /// Sets the adapter specific signals on the upper four pins of the low byte.
+    pub fn set_gpio(&mut self, value: u8, direction: u8) {
+        self.value = (self.value & !pins::GPIO_MASK) | (value & pins::GPIO_MASK);
+        self.direction = (self.direction & !pins::GPIO_MASK) | (direction & pins::GPIO_MASK);
+        self.set_pins();
+    }
+
+    /// Sets the clock to the fastest frequency not above `frequency` Hz.
+    ///
+    /// Returns the frequency that was actually selected.
+    pub fn set_frequency(&mut self, frequency: u32) -> Result<u32, T::Error> {
+        let divisor = (MPSSE_BASE_CLOCK / 2).div_ceil(frequency.max(1)).saturating_sub(1).min(0xFFFF);
+        let bytes = (divisor as u16).to_le_bytes();
+        self.buffer.extend_from_slice(&[commands::SET_CLOCK_DIVISOR, bytes[0], bytes[1]]);
+        self.flush()?;
+        Ok(MPSSE_BASE_CLOCK / 2 / (divisor + 1))
+    }
+
+    /// Queues a command which clocks `bits` bits of `data`, returning the number of reply bytes.
+    fn queue_shift(&mut self, bytes_command: u8, bits_command: u8, data: &[u8], bits: usize) -> usize {
+        let full = bits / 8;
+        let rem = bits % 8;
+        let write = bytes_command & 0x10 != 0;
+        if full > 0 {
+            let len = ((full - 1) as u16).to_le_bytes();
+            self.buffer.extend_from_slice(&[bytes_command, len[0], len[1]]);
+            if write {
+                self.buffer.extend_from_slice(&data[..full]);
+            }
+        }
+        if rem > 0 {
+            self.buffer.extend_from_slice(&[bits_command, (rem - 1) as u8]);
+            if write {
+                self.buffer.push(data[full]);
+            }
+        }
+        full + (rem > 0) as usize
+    }
+}
+
+impl<T: FtdiTransport> SwdIo for FtdiMpsse<T> {
+    type Error = T::Error;
+
+    fn drive(&mut self) -> Result<(), Self::Error> {
+        self.direction |= pins::SWDIO_OUT;
+        self.set_pins();
+        Ok(())
+    }
+
+    fn release(&mut self) -> Result<(), Self::Error> {
+        self.direction &= !pins::SWDIO_OUT;
+        self.set_pins();
+        Ok(())
+    }
+
+    fn write_bits(&mut self, value: u64, len: u8) -> Result<(), Self::Error> {
+        let data = value.to_le_bytes();
+        self.queue_shift(commands::WRITE_BYTES, commands::WRITE_BITS, &data, len as usize);
+        Ok(())
+    }
+
+    fn read_bits(&mut self, len: u8) -> Result<u64, Self::Error> {
+        let replies = self.queue_shift(commands::READ_BYTES, commands::READ_BITS, &[], len as usize);
+        let data = self.flush_and_read(replies)?;
+        Ok(assemble(&data, len as usize))
+    }
+}
+
+impl<T: FtdiTransport> JtagIo for FtdiMpsse<T> {
+    type Error = T::Error;
+
+    fn shift(&mut self, tms: &[bool], tdi: &[bool]) -> Result<Vec<bool>, Self::Error> {
+        // The data commands do not drive TMS, the pin keeps the level the last TMS command
+        // left it at. So only runs with TMS low go through the data commands, once TMS is
+        // low already. Everything else goes through the TMS command, which holds TDI, and
+        // a TMS command leading into such a run ends with TMS low.
+        let mut runs = vec![];
+        let mut replies = vec![];
+        let mut start = 0;
+        while start < tms.len() {
+            let mut end = start + 1;
+            if !tms[start] && self.value & pins::TMS == 0 {
+                while end < tms.len() && end - start < 64 && !tms[end] {
+                    end += 1;
+                }
+                let value = (start..end).fold(0u64, |acc, i| acc | ((tdi[i] as u64) << (i - start)));
+                let data = value.to_le_bytes();
+                replies.push(self.queue_shift(commands::READ_WRITE_BYTES, commands::READ_WRITE_BITS, &data, end - start));
+            } else {
+                while end < tms.len() && end - start < MAX_TMS_BITS && tdi[end] == tdi[start] && (tms[end - 1] || tms[end]) {
+                    end += 1;
+                }
+                let pattern = (start..end).fold(0u8, |acc, i| acc | ((tms[i] as u8) << (i - start)));
+                self.buffer.extend_from_slice(&[commands::READ_WRITE_TMS_BITS, (end - start - 1) as u8, pattern | ((tdi[start] as u8) << 7)]);
+                replies.push(1);
+                self.value = if tms[end - 1] { self.value | pins::TMS } else { self.value & !pins::TMS };
+            }
+            runs.push((start, end));
+            start = end;
+        }
+
+        let data = self.flush_and_read(replies.iter().sum())?;
+        let mut tdo = Vec::with_capacity(tms.len());
+        let mut offset = 0;
+        for (&(start, end), &len) in runs.iter().zip(replies.iter()) {
+            let bits = end - start;
+            let value = assemble(&data[offset..offset + len], bits);
+            tdo.extend((0..bits).map(|i| (value >> i) & 0x1 == 1));
+            offset += len;
+        }
+        Ok(tdo)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FtdiMpsse, FtdiTransport};
+    use crate::dap_access::consts::*;
+    use crate::probes::adiv5::{DapError, PortType, RawDapAccess};
+    use crate::probes::jtag::Jtag;
+    use crate::probes::swd::Swd;
+    use std::collections::VecDeque;
+    use std::io;
+
+    /// Records everything written to the chip and replays canned replies.
+    #[derive(Default)]
+    struct RecordedTransport {
+        written: Vec<u8>,
+        replies: VecDeque<u8>,
+    }
+
+    impl RecordedTransport {
+        fn with_replies(replies: &[u8]) -> Self {
+            Self {
+                written: vec![],
+                replies: replies.iter().cloned().collect(),
+            }
+        }
+    }
+
+    impl FtdiTransport for RecordedTransport {
+        type Error = io::Error;
+
+        fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            self.written.extend_from_slice(data);
+            Ok(())
+        }
+
+        fn read(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
+            for byte in data.iter_mut() {
+                *byte = self.replies.pop_front().ok_or(io::ErrorKind::UnexpectedEof)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn init_and_frequency() {
+        let mut mpsse = FtdiMpsse::new(RecordedTransport::default());
+        mpsse.init_swd().unwrap();
+        assert_eq!(mpsse.set_frequency(1_000_000).unwrap(), 1_000_000);
+        assert_eq!(mpsse.set_frequency(7_000_000).unwrap(), 6_000_000);
+        assert_eq!(mpsse.into_inner().written, vec![
+            0x85, 0x8A, 0x97, 0x8D, 0x80, 0x00, 0x03,
+            0x86, 29, 0,
+            0x86, 4, 0,
+        ]);
+    }
+
+    /// The stream of a SWD DP IDCODE read, up to the final idle cycles.
+    const SWD_READ_IDCODE: [u8; 16] = [
+        // Request header
+        0x19, 0x00, 0x00, 0xA5,
+        // Release SWDIO, turnaround and ACK
+        0x80, 0x00, 0x01,
+        0x2A, 0x03, 0x87,
+        // Data, parity and turnaround
+        0x28, 0x03, 0x00,
+        0x2A, 0x01, 0x87,
+    ];
+
+    #[test]
+    fn swd_read_idcode() {
+        let transport = RecordedTransport::with_replies(&[0x20, 0x77, 0x14, 0xA0, 0x2B, 0x00]);
+        let mut mpsse = FtdiMpsse::new(transport);
+        mpsse.init_swd().unwrap();
+        let mut swd = Swd::new(mpsse);
+
+        assert_eq!(swd.raw_read(PortType::DebugPort, DP_IDCODE as u8).unwrap(), 0x2BA0_1477);
+
+        let mut mpsse = swd.into_inner();
+        mpsse.flush().unwrap();
+        let transport = mpsse.into_inner();
+        assert_eq!(transport.written[7..], [
+            &SWD_READ_IDCODE[..],
+            // Drive SWDIO again and idle
+            &[0x80, 0x00, 0x03, 0x19, 0x00, 0x00, 0x00],
+        ].concat()[..]);
+        assert!(transport.replies.is_empty());
+    }
+
+    #[test]
+    fn swd_parity_error() {
+        let transport = RecordedTransport::with_replies(&[0x20, 0x77, 0x14, 0xA0, 0x2B, 0x40]);
+        let mut mpsse = FtdiMpsse::new(transport);
+        mpsse.init_swd().unwrap();
+        let mut swd = Swd::new(mpsse);
+
+        match swd.raw_read(PortType::DebugPort, DP_IDCODE as u8) {
+            Err(DapError::Parity) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn swd_write_retries_on_wait() {
+        // WAIT, then OK followed by the turnaround cycle before the data phase.
+        let transport = RecordedTransport::with_replies(&[0x40, 0x00, 0x20, 0x00]);
+        let mut mpsse = FtdiMpsse::new(transport);
+        mpsse.init_swd().unwrap();
+        let mut swd = Swd::new(mpsse);
+
+        assert!(swd.raw_write(PortType::DebugPort, DP_SELECT as u8, 0x0100_00F0).is_ok());
+
+        let mut mpsse = swd.into_inner();
+        mpsse.flush().unwrap();
+        assert_eq!(mpsse.into_inner().written[7..], [
+            // WAIT: turnaround, drive and idle
+            0x19, 0x00, 0x00, 0xB1, 0x80, 0x00, 0x01, 0x2A, 0x03, 0x87,
+            0x2A, 0x00, 0x87, 0x80, 0x00, 0x03, 0x19, 0x00, 0x00, 0x00,
+            // OK: turnaround, drive, data with parity and idle
+            0x19, 0x00, 0x00, 0xB1, 0x80, 0x00, 0x01, 0x2A, 0x03, 0x87,
+            0x2A, 0x00, 0x87, 0x80, 0x00, 0x03,
+            0x19, 0x03, 0x00, 0xF0, 0x00, 0x00, 0x01, 0x1B, 0x00, 0x01,
+            0x19, 0x00, 0x00, 0x00,
+        ]);
+    }
+
+    #[test]
+    fn swd_fault() {
+        let transport = RecordedTransport::with_replies(&[0x80, 0x00]);
+        let mut mpsse = FtdiMpsse::new(transport);
+        mpsse.init_swd().unwrap();
+        let mut swd = Swd::new(mpsse);
+
+        match swd.raw_write(PortType::AccessPort, 0xC, 0) {
+            Err(DapError::Fault) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn jtag_read_idcode() {
+        let transport = RecordedTransport::with_replies(&[
+            // Reset
+            0x00,
+            // Select-DR and Capture-DR, then IDCODE bits 0 to 30 after the Capture-DR bit
+            0x00, 0xEE, 0x08, 0x40, 0x97,
+            // IDCODE bit 31 with Exit1-DR, Update-DR and Run-Test/Idle
+            0x00,
+        ]);
+        let mut mpsse = FtdiMpsse::new(transport);
+        mpsse.init_jtag().unwrap();
+        let mut jtag = Jtag::new(mpsse);
+
+        assert_eq!(jtag.connect().unwrap(), 0x4BA0_0477);
+
+        let transport = jtag.into_inner().into_inner();
+        assert_eq!(transport.written[7..], [
+            // Five times TMS high to Test-Logic-Reset, TMS low to Run-Test/Idle
+            0x6B, 0x05, 0x1F, 0x87,
+            // TMS high to Select-DR, low to Capture-DR and TMS stays low
+            0x6B, 0x01, 0x01,
+            // Shift-DR and the first 31 bits with TMS low
+            0x39, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00,
+            // Last bit with TMS high to Exit1-DR, then Update-DR and Run-Test/Idle
+            0x6B, 0x02, 0x03,
+            0x87,
+        ]);
+        assert!(transport.replies.is_empty());
+    }
+
+    #[test]
+    fn jtag_scan_ir() {
+        let transport = RecordedTransport::with_replies(&[0x00, 0x20, 0x00, 0x00]);
+        let mut mpsse = FtdiMpsse::new(transport);
+        mpsse.init_jtag().unwrap();
+        let mut jtag = Jtag::new(mpsse);
+
+        assert_eq!(jtag.scan_ir(0xA, 4).unwrap(), 0x1);
+
+        let transport = jtag.into_inner().into_inner();
+        assert_eq!(transport.written[7..], [
+            // Select-DR, Select-IR and Capture-IR, TMS stays low
+            0x6B, 0x02, 0x03,
+            // Shift-IR and IR bits 0 to 2
+            0x3B, 0x03, 0x04,
+            // IR bit 3 with TMS high to Exit1-IR, TDI changes with it
+            0x6B, 0x00, 0x81,
+            // Update-IR and Run-Test/Idle
+            0x6B, 0x01, 0x01,
+            0x87,
+        ]);
+        assert!(transport.replies.is_empty());
+    }
+}
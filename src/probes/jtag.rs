@@ -0,0 +1,175 @@
+use crate::dap_access::consts::*;
+use crate::probes::adiv5::{DapError, PortType, RawDapAccess};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// The number of times a transfer is repeated when the target answers WAIT.
+const DEFAULT_WAIT_RETRIES: usize = 100;
+
+// JTAG-DP instructions.
+pub const JTAG_IR_ABORT: u8 = 0x8;
+pub const JTAG_IR_DPACC: u8 = 0xA;
+pub const JTAG_IR_APACC: u8 = 0xB;
+pub const JTAG_IR_IDCODE: u8 = 0xE;
+pub const JTAG_IR_BYPASS: u8 = 0xF;
+
+/// The instruction register length of an ARM JTAG-DP.
+const JTAG_DP_IR_LENGTH: u8 = 4;
+
+/// The length of the DPACC and APACC data registers.
+const JTAG_DPACC_LENGTH: u8 = 35;
+
+// JTAG-DP acknowledge values.
+const ACK_OK_FAULT: u8 = 0b010;
+const ACK_WAIT: u8 = 0b001;
+
+/// Bit level access to the JTAG lines of a probe.
+pub trait JtagIo {
+    type Error: core::error::Error + Send + Sync + 'static;
+
+    /// Clocks TCK once for every pair of `tms` and `tdi` bits and returns the TDO bit
+    /// sampled in each cycle.
+    fn shift(&mut self, tms: &[bool], tdi: &[bool]) -> Result<Vec<bool>, Self::Error>;
+}
+
+/// Runs the JTAG-DP protocol on top of a bit level interface.
+///
+/// The DP has to be the only device in the scan chain. Between scans the TAP is
+/// kept in Run-Test/Idle.
+pub struct Jtag<I: JtagIo> {
+    io: I,
+    ir: Option<u8>,
+    wait_retries: usize,
+}
+
+impl<I: JtagIo> Jtag<I> {
+    pub fn new(io: I) -> Self {
+        Self {
+            io,
+            ir: None,
+            wait_retries: DEFAULT_WAIT_RETRIES,
+        }
+    }
+
+    pub fn io(&mut self) -> &mut I {
+        &mut self.io
+    }
+
+    pub fn into_inner(self) -> I {
+        self.io
+    }
+
+    /// Sets how often a transfer is repeated when the target answers WAIT.
+    pub fn set_wait_retries(&mut self, wait_retries: usize) {
+        self.wait_retries = wait_retries;
+    }
+
+    /// Moves the TAP to Test-Logic-Reset and then to Run-Test/Idle.
+    pub fn reset(&mut self) -> Result<(), DapError<I::Error>> {
+        self.io.shift(&[true, true, true, true, true, false], &[false; 6]).map_err(DapError::Io)?;
+        // The reset loads IDCODE into the instruction register.
+        self.ir = Some(JTAG_IR_IDCODE);
+        Ok(())
+    }
+
+    /// Resets the TAP and reads the IDCODE of the DP.
+    pub fn connect(&mut self) -> Result<u32, DapError<I::Error>> {
+        self.reset()?;
+        self.raw_read(PortType::DebugPort, DP_IDCODE as u8)
+    }
+
+    /// Shifts `len` bits of `value` through the TAP, starting and ending in Run-Test/Idle.
+    ///
+    /// `header` is the TMS sequence which leads from Run-Test/Idle to the shift state.
+    fn scan(&mut self, header: &[bool], value: u64, len: u8) -> Result<u64, DapError<I::Error>> {
+        let len = len as usize;
+        let mut tms = header.to_vec();
+        let mut tdi = vec![false; header.len()];
+        for i in 0..len {
+            // Leave the shift state with the last bit.
+            tms.push(i == len - 1);
+            tdi.push((value >> i) & 0x1 == 1);
+        }
+        // Update, then back to Run-Test/Idle.
+        tms.extend_from_slice(&[true, false]);
+        tdi.extend_from_slice(&[false, false]);
+
+        let tdo = self.io.shift(&tms, &tdi).map_err(DapError::Io)?;
+        Ok(tdo[header.len()..header.len() + len]
+            .iter()
+            .enumerate()
+            .fold(0, |acc, (i, bit)| acc | ((*bit as u64) << i)))
+    }
+
+    /// Shifts `value` through the instruction register.
+    pub fn scan_ir(&mut self, value: u8, len: u8) -> Result<u64, DapError<I::Error>> {
+        self.ir = None;
+        let captured = self.scan(&[true, true, false, false], value as u64, len)?;
+        self.ir = Some(value);
+        Ok(captured)
+    }
+
+    /// Shifts `value` through the currently selected data register.
+    pub fn scan_dr(&mut self, value: u64, len: u8) -> Result<u64, DapError<I::Error>> {
+        self.scan(&[true, false, false], value, len)
+    }
+
+    fn select_ir(&mut self, ir: u8) -> Result<(), DapError<I::Error>> {
+        if self.ir != Some(ir) {
+            self.scan_ir(ir, JTAG_DP_IR_LENGTH)?;
+        }
+        Ok(())
+    }
+
+    /// Runs one DPACC or APACC scan and returns the captured data.
+    ///
+    /// The captured data is the result of the previous read.
+    fn access(&mut self, ir: u8, read: bool, addr: u8, value: u32) -> Result<u32, DapError<I::Error>> {
+        self.select_ir(ir)?;
+        let request = ((value as u64) << 3) | ((((addr >> 2) & 0x3) as u64) << 1) | read as u64;
+        for _ in 0..=self.wait_retries {
+            let captured = self.scan_dr(request, JTAG_DPACC_LENGTH)?;
+            match (captured & 0x7) as u8 {
+                ACK_OK_FAULT => return Ok((captured >> 3) as u32),
+                ACK_WAIT => continue,
+                ack => return Err(DapError::Protocol(ack)),
+            }
+        }
+        Err(DapError::Wait)
+    }
+
+    /// Reads RDBUFF to complete the previous transfer and fetch its result.
+    fn complete(&mut self) -> Result<u32, DapError<I::Error>> {
+        self.access(JTAG_IR_DPACC, true, DP_RDBUFF as u8, 0)
+    }
+}
+
+impl<I: JtagIo> RawDapAccess for Jtag<I> {
+    type Error = DapError<I::Error>;
+
+    fn raw_read(&mut self, port: PortType, addr: u8) -> Result<u32, Self::Error> {
+        if port == PortType::DebugPort && addr as u32 == DP_IDCODE {
+            // IDCODE has its own instruction on JTAG.
+            self.select_ir(JTAG_IR_IDCODE)?;
+            return Ok(self.scan_dr(0, 32)? as u32);
+        }
+
+        let ir = match port {
+            PortType::DebugPort => JTAG_IR_DPACC,
+            PortType::AccessPort => JTAG_IR_APACC,
+        };
+        self.access(ir, true, addr, 0)?;
+        self.complete()
+    }
+
+    fn raw_write(&mut self, port: PortType, addr: u8, value: u32) -> Result<(), Self::Error> {
+        let ir = match port {
+            // ABORT has its own instruction on JTAG.
+            PortType::DebugPort if addr as u32 == DP_ABORT => JTAG_IR_ABORT,
+            PortType::DebugPort => JTAG_IR_DPACC,
+            PortType::AccessPort => JTAG_IR_APACC,
+        };
+        self.access(ir, false, addr, value)?;
+        self.complete().map(|_| ())
+    }
+}
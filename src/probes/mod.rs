@@ -0,0 +1,10 @@
+pub mod adiv5;
+pub mod ftdi;
+pub mod jtag;
+#[cfg(feature = "std")]
+pub mod network;
+#[cfg(feature = "std")]
+pub mod remote_bitbang;
+pub mod sim;
+pub mod stlink;
+pub mod swd;
@@ -0,0 +1,448 @@
+use crate::dap_access::{DAPAccess, Transfer, TransferError, TransferErrorKind, TransferKind};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+// Every frame starts with the length of its payload as a little-endian u32.
+// The first payload byte is one of these opcodes, all other fields are little-endian.
+pub mod opcodes {
+    /// `port: u16, addr: u32`, answered with `OK value: u32`.
+    pub const READ: u8 = 0x01;
+    /// `port: u16, addr: u32, value: u32`, answered with a bare `OK`.
+    pub const WRITE: u8 = 0x02;
+    /// `count: u32` followed by `kind: u8, port: u16, addr: u32, value: u32` per transfer,
+    /// answered with `OK count: u32` followed by one `value: u32` per transfer.
+    pub const BATCH: u8 = 0x03;
+
+    /// The request went through.
+    pub const OK: u8 = 0x80;
+    /// `done: u32` followed by one `value: u32` per finished transfer, the error kind
+    /// as `u8` and the error message.
+    pub const ERROR: u8 = 0x81;
+
+    // Error kinds inside an error response.
+    pub const ERROR_OTHER: u8 = 0x00;
+    pub const ERROR_WAIT: u8 = 0x01;
+    pub const ERROR_FAULT: u8 = 0x02;
+    pub const ERROR_PROTOCOL: u8 = 0x03;
+    pub const ERROR_PARITY: u8 = 0x04;
+
+    // Transfer kinds inside a batch.
+    pub const KIND_READ: u8 = 0x00;
+    pub const KIND_WRITE: u8 = 0x01;
+}
+
+/// The largest frame either side accepts.
+const MAX_FRAME_SIZE: usize = 1 << 20;
+
+#[derive(Debug)]
+pub enum NetworkError {
+    Io(io::Error),
+    /// The peer sent a frame which does not follow the protocol.
+    Protocol,
+    /// The DAP behind the server failed, the message is its error formatted with `Display`.
+    Remote {
+        kind: TransferErrorKind,
+        message: String,
+    },
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetworkError::Io(e) => write!(f, "connection failed: {}", e),
+            NetworkError::Protocol => f.write_str("the peer broke the protocol"),
+            NetworkError::Remote { kind, message } => write!(f, "remote DAP failed with {}: {}", kind, message),
+        }
+    }
+}
+
+impl Error for NetworkError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            NetworkError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl TransferError for NetworkError {
+    fn kind(&self) -> TransferErrorKind {
+        match self {
+            NetworkError::Remote { kind, .. } => *kind,
+            _ => TransferErrorKind::Other,
+        }
+    }
+}
+
+impl From<io::Error> for NetworkError {
+    fn from(error: io::Error) -> Self {
+        NetworkError::Io(error)
+    }
+}
+
+fn write_frame(stream: &mut impl Write, payload: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(payload.len() + 4);
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)?;
+    stream.flush()
+}
+
+/// Reads a single frame. Returns `None` if the peer closed the connection in between frames.
+fn read_frame(stream: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    match stream.read_exact(&mut len) {
+        Ok(()) => (),
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too large"));
+    }
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload)?;
+    Ok(Some(payload))
+}
+
+/// Decodes the kind and message at the end of an error response.
+fn remote_error(fields: &mut Fields) -> NetworkError {
+    let kind = match fields.u8() {
+        Some(opcodes::ERROR_WAIT) => TransferErrorKind::Wait,
+        Some(opcodes::ERROR_FAULT) => TransferErrorKind::Fault,
+        Some(opcodes::ERROR_PROTOCOL) => TransferErrorKind::Protocol,
+        Some(opcodes::ERROR_PARITY) => TransferErrorKind::Parity,
+        Some(_) => TransferErrorKind::Other,
+        None => return NetworkError::Protocol,
+    };
+    NetworkError::Remote {
+        kind,
+        message: String::from_utf8_lossy(fields.rest()).into_owned(),
+    }
+}
+
+/// Walks through the fields of a payload.
+struct Fields<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.data.len() < len {
+            return None;
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        self.take(self.data.len()).unwrap_or(&[])
+    }
+}
+
+fn encode_transfer(payload: &mut Vec<u8>, transfer: &Transfer) {
+    payload.push(match transfer.kind {
+        TransferKind::Read => opcodes::KIND_READ,
+        TransferKind::Write => opcodes::KIND_WRITE,
+    });
+    payload.extend_from_slice(&transfer.port.to_le_bytes());
+    payload.extend_from_slice(&transfer.addr.to_le_bytes());
+    payload.extend_from_slice(&transfer.value.to_le_bytes());
+}
+
+fn decode_transfer(fields: &mut Fields) -> Option<Transfer> {
+    let kind = fields.u8()?;
+    let port = fields.u16()?;
+    let addr = fields.u32()?;
+    let value = fields.u32()?;
+    match kind {
+        opcodes::KIND_READ => Some(Transfer::read(port, addr)),
+        opcodes::KIND_WRITE => Some(Transfer::write(port, addr, value)),
+        _ => None,
+    }
+}
+
+fn error_response(values: &[u32], error: impl TransferError) -> Vec<u8> {
+    let mut response = vec![opcodes::ERROR];
+    response.extend_from_slice(&(values.len() as u32).to_le_bytes());
+    for value in values {
+        response.extend_from_slice(&value.to_le_bytes());
+    }
+    response.push(match error.kind() {
+        TransferErrorKind::Wait => opcodes::ERROR_WAIT,
+        TransferErrorKind::Fault => opcodes::ERROR_FAULT,
+        TransferErrorKind::Protocol => opcodes::ERROR_PROTOCOL,
+        TransferErrorKind::Parity => opcodes::ERROR_PARITY,
+        TransferErrorKind::Other => opcodes::ERROR_OTHER,
+    });
+    response.extend_from_slice(error.to_string().as_bytes());
+    response
+}
+
+/// Runs a single request against the DAP and builds the response.
+fn handle_request<D: DAPAccess>(dap: &mut D, request: &[u8]) -> Option<Vec<u8>> {
+    let mut fields = Fields { data: request };
+    let response = match fields.u8()? {
+        opcodes::READ => {
+            let port = fields.u16()?;
+            let addr = fields.u32()?;
+            match dap.read_register(port, addr) {
+                Ok(value) => [&[opcodes::OK][..], &value.to_le_bytes()].concat(),
+                Err(e) => error_response(&[], e),
+            }
+        },
+        opcodes::WRITE => {
+            let port = fields.u16()?;
+            let addr = fields.u32()?;
+            let value = fields.u32()?;
+            match dap.write_register(port, addr, value) {
+                Ok(()) => vec![opcodes::OK],
+                Err(e) => error_response(&[], e),
+            }
+        },
+        opcodes::BATCH => {
+            let count = fields.u32()? as usize;
+            let mut transfers = (0..count).map(|_| decode_transfer(&mut fields)).collect::<Option<Vec<_>>>()?;
+            let result = dap.batch(&mut transfers);
+            let values: Vec<u32> = transfers.iter().take_while(|t| t.done).map(|t| t.value).collect();
+            match result {
+                Ok(()) => {
+                    let mut response = vec![opcodes::OK];
+                    response.extend_from_slice(&(values.len() as u32).to_le_bytes());
+                    for value in values {
+                        response.extend_from_slice(&value.to_le_bytes());
+                    }
+                    response
+                },
+                Err(e) => error_response(&values, e),
+            }
+        },
+        _ => return None,
+    };
+    if fields.data.is_empty() {
+        Some(response)
+    } else {
+        None
+    }
+}
+
+/// Serves a DAP to a single client until it disconnects.
+pub fn serve<S: Read + Write, D: DAPAccess>(mut stream: S, dap: &mut D) -> io::Result<()> {
+    while let Some(request) = read_frame(&mut stream)? {
+        match handle_request(dap, &request) {
+            Some(response) => write_frame(&mut stream, &response)?,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed request")),
+        }
+    }
+    Ok(())
+}
+
+/// Serves a DAP to one client after the other.
+pub fn listen<D: DAPAccess>(listener: TcpListener, dap: &mut D) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        stream.set_nodelay(true)?;
+        // A misbehaving client must not take the server down.
+        if let Err(e) = serve(stream, dap) {
+            log::warn!("client connection failed: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// A DAP on another machine, reached through a server started with `serve` or `listen`.
+pub struct RemoteDap<S: Read + Write> {
+    stream: S,
+}
+
+impl RemoteDap<TcpStream> {
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(stream))
+    }
+}
+
+impl<S: Read + Write> RemoteDap<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Sends a request and returns the fields of a successful response.
+    fn request(&mut self, payload: &[u8]) -> Result<Vec<u8>, NetworkError> {
+        write_frame(&mut self.stream, payload)?;
+        let response = read_frame(&mut self.stream)?.ok_or(NetworkError::Protocol)?;
+        match response.first() {
+            Some(&opcodes::OK) => Ok(response[1..].to_vec()),
+            Some(&opcodes::ERROR) => {
+                let mut fields = Fields { data: &response[1..] };
+                let done = fields.u32().ok_or(NetworkError::Protocol)? as usize;
+                fields.take(done * 4).ok_or(NetworkError::Protocol)?;
+                Err(remote_error(&mut fields))
+            },
+            _ => Err(NetworkError::Protocol),
+        }
+    }
+}
+
+impl<S: Read + Write> DAPAccess for RemoteDap<S> {
+    type Error = NetworkError;
+
+    fn read_register(&mut self, port: u16, addr: u32) -> Result<u32, Self::Error> {
+        let mut payload = vec![opcodes::READ];
+        payload.extend_from_slice(&port.to_le_bytes());
+        payload.extend_from_slice(&addr.to_le_bytes());
+        let response = self.request(&payload)?;
+        Fields { data: &response }.u32().ok_or(NetworkError::Protocol)
+    }
+
+    fn write_register(&mut self, port: u16, addr: u32, value: u32) -> Result<(), Self::Error> {
+        let mut payload = vec![opcodes::WRITE];
+        payload.extend_from_slice(&port.to_le_bytes());
+        payload.extend_from_slice(&addr.to_le_bytes());
+        payload.extend_from_slice(&value.to_le_bytes());
+        self.request(&payload).map(|_| ())
+    }
+
+    /// Sends the whole batch in a single frame.
+    fn batch(&mut self, transfers: &mut [Transfer]) -> Result<(), Self::Error> {
+        let mut payload = vec![opcodes::BATCH];
+        payload.extend_from_slice(&(transfers.len() as u32).to_le_bytes());
+        for transfer in transfers.iter() {
+            encode_transfer(&mut payload, transfer);
+        }
+
+        write_frame(&mut self.stream, &payload)?;
+        let response = read_frame(&mut self.stream)?.ok_or(NetworkError::Protocol)?;
+        let mut fields = Fields { data: &response };
+        let status = fields.u8().ok_or(NetworkError::Protocol)?;
+        let done = fields.u32().ok_or(NetworkError::Protocol)? as usize;
+        if done > transfers.len() {
+            return Err(NetworkError::Protocol);
+        }
+        for transfer in transfers[..done].iter_mut() {
+            let value = fields.u32().ok_or(NetworkError::Protocol)?;
+            if transfer.kind == TransferKind::Read {
+                transfer.value = value;
+            }
+            transfer.done = true;
+        }
+        match status {
+            opcodes::OK => Ok(()),
+            opcodes::ERROR => Err(remote_error(&mut fields)),
+            _ => Err(NetworkError::Protocol),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{serve, NetworkError, RemoteDap};
+    use crate::dap_access::{DAPAccess, MockDAP, Transfer, TransferErrorKind};
+    use crate::access_port::consts::*;
+    use crate::memory_interface::MemoryInterface;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    /// Serves a `MockDAP` on loopback and connects to it.
+    fn start_server() -> (RemoteDap<TcpStream>, thread::JoinHandle<MockDAP>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut mock = MockDAP::new();
+            let (stream, _) = listener.accept().unwrap();
+            serve(stream, &mut mock).unwrap();
+            mock
+        });
+        (RemoteDap::connect(addr).unwrap(), server)
+    }
+
+    #[test]
+    fn memory_access() {
+        let (mut remote, server) = start_server();
+        let mi = MemoryInterface::new(0);
+        mi.write(&mut remote, 0x10, 0xDEADBEEF_u32).unwrap();
+        assert_eq!(mi.read::<u32>(&mut remote, 0x10).unwrap(), 0xDEADBEEF);
+        drop(remote);
+
+        let mock = server.join().unwrap();
+        assert_eq!(mock.data[0x10..0x14], [0xEF, 0xBE, 0xAD, 0xDE]);
+    }
+
+    #[test]
+    fn batch() {
+        let (mut remote, server) = start_server();
+        let mut transfers = [
+            Transfer::write(0, MEM_AP_CSW, CSW_VALUE | CSW_SIZE32),
+            Transfer::write(0, MEM_AP_TAR, 0x20),
+            Transfer::write(0, MEM_AP_DRW, 0xABBABABE),
+            Transfer::read(0, MEM_AP_DRW),
+            Transfer::read(0, MEM_AP_TAR),
+        ];
+        remote.batch(&mut transfers).unwrap();
+        assert!(transfers.iter().all(|t| t.done));
+        assert_eq!(transfers[3].value, 0xABBABABE);
+        assert_eq!(transfers[4].value, 0x20);
+        drop(remote);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn batch_stops_at_error() {
+        let (mut remote, server) = start_server();
+        let mut transfers = [
+            Transfer::write(0, MEM_AP_TAR, 0x20),
+            Transfer::read(0, AP_IDR),
+            Transfer::read(0, MEM_AP_TAR),
+        ];
+        match remote.batch(&mut transfers) {
+            Err(NetworkError::Remote { kind, message }) => {
+                assert_eq!(kind, TransferErrorKind::Other);
+                assert_eq!(message, "unsupported register");
+            },
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(transfers[0].done);
+        assert!(!transfers[1].done);
+        assert!(!transfers[2].done);
+        drop(remote);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn remote_error() {
+        let (mut remote, server) = start_server();
+        match remote.read_register(0, AP_IDR) {
+            Err(NetworkError::Remote { kind, message }) => {
+                assert_eq!(kind, TransferErrorKind::Other);
+                assert_eq!(message, "unsupported register");
+            },
+            other => panic!("unexpected result: {:?}", other),
+        }
+        // The connection stays usable after an error.
+        assert!(remote.write_register(0, MEM_AP_TAR, 0).is_ok());
+        drop(remote);
+        server.join().unwrap();
+    }
+}
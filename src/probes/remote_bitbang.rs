@@ -0,0 +1,313 @@
+use crate::probes::jtag::JtagIo;
+use crate::probes::sim::SimulatedTarget;
+use crate::probes::swd::SwdIo;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+pub mod commands {
+    // JTAG pins are written as '0' + (TCK << 2 | TMS << 1 | TDI).
+    pub const JTAG_WRITE: u8 = b'0';
+    pub const JTAG_READ: u8 = b'R';
+
+    // Resets are written as 'r' + (TRST << 1 | SRST).
+    pub const RESET: u8 = b'r';
+
+    // SWD pins are written as 'd' + (SWCLK << 1 | SWDIO).
+    pub const SWD_WRITE: u8 = b'd';
+    pub const SWD_READ: u8 = b'c';
+    pub const SWDIO_DRIVE: u8 = b'O';
+    pub const SWDIO_RELEASE: u8 = b'o';
+
+    pub const BLINK_ON: u8 = b'B';
+    pub const BLINK_OFF: u8 = b'b';
+    pub const QUIT: u8 = b'Q';
+}
+
+#[derive(Debug)]
+pub enum RemoteBitbangError {
+    Io(io::Error),
+    /// The server answered a read with something other than '0' or '1'.
+    InvalidReply(u8),
+}
+
+impl fmt::Display for RemoteBitbangError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RemoteBitbangError::Io(e) => write!(f, "connection failed: {}", e),
+            RemoteBitbangError::InvalidReply(reply) => write!(f, "invalid reply {:#04X}", reply),
+        }
+    }
+}
+
+impl Error for RemoteBitbangError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RemoteBitbangError::Io(e) => Some(e),
+            RemoteBitbangError::InvalidReply(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for RemoteBitbangError {
+    fn from(error: io::Error) -> Self {
+        RemoteBitbangError::Io(error)
+    }
+}
+
+/// A client for OpenOCD's `remote_bitbang` protocol.
+///
+/// Pin changes are buffered until a read needs the server's answer or `flush` is called.
+pub struct RemoteBitbang<S: Read + Write> {
+    stream: S,
+    buffer: Vec<u8>,
+}
+
+impl RemoteBitbang<TcpStream> {
+    /// Connects to a `remote_bitbang` server.
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(stream))
+    }
+}
+
+impl<S: Read + Write> RemoteBitbang<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            buffer: vec![],
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Sends all buffered commands to the server.
+    pub fn flush(&mut self) -> Result<(), RemoteBitbangError> {
+        if !self.buffer.is_empty() {
+            self.stream.write_all(&self.buffer)?;
+            self.stream.flush()?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+
+    /// Flushes the buffer and collects the answers to `len` reads.
+    fn read_replies(&mut self, len: usize) -> Result<Vec<bool>, RemoteBitbangError> {
+        self.flush()?;
+        let mut replies = vec![0; len];
+        self.stream.read_exact(&mut replies)?;
+        replies
+            .into_iter()
+            .map(|reply| match reply {
+                b'0' => Ok(false),
+                b'1' => Ok(true),
+                reply => Err(RemoteBitbangError::InvalidReply(reply)),
+            })
+            .collect()
+    }
+
+    /// Sets the TRST and SRST lines. `true` asserts the reset.
+    pub fn reset(&mut self, trst: bool, srst: bool) -> Result<(), RemoteBitbangError> {
+        self.buffer.push(commands::RESET + ((trst as u8) << 1 | srst as u8));
+        self.flush()
+    }
+
+    /// Switches the activity LED of the server.
+    pub fn blink(&mut self, on: bool) -> Result<(), RemoteBitbangError> {
+        self.buffer.push(if on { commands::BLINK_ON } else { commands::BLINK_OFF });
+        self.flush()
+    }
+
+    /// Tells the server to close the connection.
+    pub fn quit(mut self) -> Result<(), RemoteBitbangError> {
+        self.buffer.push(commands::QUIT);
+        self.flush()
+    }
+
+    fn swd_write(&mut self, swclk: bool, swdio: bool) {
+        self.buffer.push(commands::SWD_WRITE + ((swclk as u8) << 1 | swdio as u8));
+    }
+
+    fn jtag_write(&mut self, tck: bool, tms: bool, tdi: bool) {
+        self.buffer.push(commands::JTAG_WRITE + ((tck as u8) << 2 | (tms as u8) << 1 | tdi as u8));
+    }
+}
+
+impl<S: Read + Write> SwdIo for RemoteBitbang<S> {
+    type Error = RemoteBitbangError;
+
+    fn drive(&mut self) -> Result<(), Self::Error> {
+        self.buffer.push(commands::SWDIO_DRIVE);
+        Ok(())
+    }
+
+    fn release(&mut self) -> Result<(), Self::Error> {
+        self.buffer.push(commands::SWDIO_RELEASE);
+        Ok(())
+    }
+
+    fn write_bits(&mut self, value: u64, len: u8) -> Result<(), Self::Error> {
+        for i in 0..len {
+            let bit = (value >> i) & 0x1 == 1;
+            self.swd_write(false, bit);
+            self.swd_write(true, bit);
+        }
+        Ok(())
+    }
+
+    fn read_bits(&mut self, len: u8) -> Result<u64, Self::Error> {
+        for _ in 0..len {
+            self.swd_write(false, false);
+            self.buffer.push(commands::SWD_READ);
+            self.swd_write(true, false);
+        }
+        let bits = self.read_replies(len as usize)?;
+        Ok(bits.iter().enumerate().fold(0, |acc, (i, bit)| acc | ((*bit as u64) << i)))
+    }
+}
+
+impl<S: Read + Write> JtagIo for RemoteBitbang<S> {
+    type Error = RemoteBitbangError;
+
+    fn shift(&mut self, tms: &[bool], tdi: &[bool]) -> Result<Vec<bool>, Self::Error> {
+        for (&tms, &tdi) in tms.iter().zip(tdi.iter()) {
+            self.jtag_write(false, tms, tdi);
+            self.buffer.push(commands::JTAG_READ);
+            self.jtag_write(true, tms, tdi);
+        }
+        self.read_replies(tms.len())
+    }
+}
+
+/// Serves a simulated target over the `remote_bitbang` protocol until the client quits
+/// or disconnects.
+pub fn serve<S: Read + Write>(mut stream: S, target: &mut SimulatedTarget) -> io::Result<()> {
+    let mut tck = false;
+    let mut swclk = false;
+    let mut swdio = false;
+    let mut buffer = [0; 4096];
+
+    loop {
+        let len = stream.read(&mut buffer)?;
+        if len == 0 {
+            return Ok(());
+        }
+
+        let mut replies = vec![];
+        for &command in &buffer[..len] {
+            match command {
+                b'0'..=b'7' => {
+                    let bits = command - commands::JTAG_WRITE;
+                    let (new_tck, tms, tdi) = (bits & 0x4 != 0, bits & 0x2 != 0, bits & 0x1 != 0);
+                    if new_tck && !tck {
+                        target.jtag_clock(tms, tdi);
+                    }
+                    tck = new_tck;
+                },
+                commands::JTAG_READ => replies.push(if target.jtag_tdo() { b'1' } else { b'0' }),
+                b'd'..=b'g' => {
+                    let bits = command - commands::SWD_WRITE;
+                    let new_swclk = bits & 0x2 != 0;
+                    swdio = bits & 0x1 != 0;
+                    if new_swclk && !swclk {
+                        target.swd_clock(swdio);
+                    }
+                    swclk = new_swclk;
+                },
+                commands::SWD_READ => {
+                    let bit = target.swd_output().unwrap_or(swdio);
+                    replies.push(if bit { b'1' } else { b'0' });
+                },
+                b'r'..=b'u' if (command - commands::RESET) & 0x2 != 0 => target.jtag_reset(),
+                commands::QUIT => {
+                    stream.write_all(&replies)?;
+                    return Ok(());
+                },
+                _ => (),
+            }
+        }
+        stream.write_all(&replies)?;
+        stream.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{serve, RemoteBitbang};
+    use crate::dap_access::consts::*;
+    use crate::dap_access::{DAPAccess, DEBUG_PORT};
+    use crate::memory_interface::MemoryInterface;
+    use crate::probes::adiv5::AdiV5;
+    use crate::probes::jtag::Jtag;
+    use crate::probes::sim::{SimulatedTarget, SIM_AP_IDR, SIM_JTAG_IDCODE, SIM_SWD_IDCODE};
+    use crate::probes::swd::Swd;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    /// Starts a server for a fresh simulated target and connects to it.
+    fn start_server() -> (RemoteBitbang<TcpStream>, thread::JoinHandle<SimulatedTarget>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut target = SimulatedTarget::new(1024);
+            let (stream, _) = listener.accept().unwrap();
+            serve(stream, &mut target).unwrap();
+            target
+        });
+        (RemoteBitbang::connect(addr).unwrap(), server)
+    }
+
+    #[test]
+    fn swd_memory_access() {
+        let (client, server) = start_server();
+        let mut swd = Swd::new(client);
+        assert_eq!(swd.connect().unwrap(), SIM_SWD_IDCODE);
+
+        let mut dap = AdiV5::new(swd);
+        dap.write_register(DEBUG_PORT, DP_CTRL_STAT, CDBGPWRUPREQ | CSYSPWRUPREQ).unwrap();
+        assert_eq!(dap.read_register(DEBUG_PORT, DP_CTRL_STAT).unwrap() & CDBGPWRUPACK, CDBGPWRUPACK);
+        assert_eq!(dap.read_register(0, 0xFC).unwrap(), SIM_AP_IDR);
+
+        let mi = MemoryInterface::new(0);
+        mi.write_block(&mut dap, 0x0, &[0xDEADBEEF_u32, 0xABBABABE]).unwrap();
+        let mut data = [0_u32; 2];
+        mi.read_block(&mut dap, 0x0, &mut data).unwrap();
+        assert_eq!(data, [0xDEADBEEF, 0xABBABABE]);
+
+        dap.into_inner().into_inner().quit().unwrap();
+        let target = server.join().unwrap();
+        assert_eq!(target.dap.memory[0x0..0x4], [0xEF, 0xBE, 0xAD, 0xDE]);
+    }
+
+    #[test]
+    fn jtag_memory_access() {
+        let (client, server) = start_server();
+        let mut jtag = Jtag::new(client);
+        assert_eq!(jtag.connect().unwrap(), SIM_JTAG_IDCODE);
+
+        let mut dap = AdiV5::new(jtag);
+        assert_eq!(dap.read_register(0, 0xFC).unwrap(), SIM_AP_IDR);
+
+        let mi = MemoryInterface::new(0);
+        mi.write(&mut dap, 0x20, 0xDEADBEEF_u32).unwrap();
+        assert_eq!(mi.read::<u32>(&mut dap, 0x20).unwrap(), 0xDEADBEEF);
+
+        dap.into_inner().into_inner().quit().unwrap();
+        let target = server.join().unwrap();
+        assert_eq!(target.dap.memory[0x20..0x24], [0xEF, 0xBE, 0xAD, 0xDE]);
+    }
+
+    #[test]
+    fn swd_needs_line_reset() {
+        let (client, server) = start_server();
+        let mut dap = AdiV5::new(Swd::new(client));
+        // Without the connect sequence the target does not answer.
+        assert!(dap.read_register(DEBUG_PORT, DP_IDCODE).is_err());
+        dap.into_inner().into_inner().quit().unwrap();
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,796 @@
+use crate::access_port::consts::*;
+use crate::dap_access::consts::*;
+use crate::probes::adiv5::{DapError, PortType, RawDapAccess};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::Infallible;
+use core::ops::Range;
+
+/// The DPIDR of the simulated SW-DP.
+pub const SIM_SWD_IDCODE: u32 = 0x2BA0_1477;
+
+/// The IDCODE of the simulated JTAG-DP.
+pub const SIM_JTAG_IDCODE: u32 = 0x4BA0_0477;
+
+/// The IDR of the simulated AHB-AP.
+pub const SIM_AP_IDR: u32 = 0x2477_0011;
+
+/// A software model of a DAP with a single AHB-AP in front of a RAM, by default at address 0.
+///
+/// Registers are accessed as seen on the wire, so AP reads are not posted here.
+/// Data is placed on the byte lanes of DRW like on a little-endian AHB, or like on a
+/// big-endian one if CFG.BE is set.
+pub struct SimulatedDap {
+    pub memory: Vec<u8>,
+    ctrl_stat: u32,
+    select: u32,
+    rdbuff: u32,
+    csw: u32,
+    tar: u32,
+    tar_upper: u32,
+    cfg: u32,
+    base: u64,
+    memory_base: u64,
+    /// The words of an access wider than 32 bits and the next one to go through DRW.
+    words: [u32; 8],
+    word: usize,
+    /// Number of AP transfers which go through before the next one answers WAIT.
+    wait_after: Option<usize>,
+    /// Offsets into the memory which ignore writes.
+    read_only: Range<usize>,
+}
+
+impl SimulatedDap {
+    pub fn new(memory_size: usize) -> Self {
+        Self {
+            memory: vec![0; memory_size],
+            ctrl_stat: 0,
+            select: 0,
+            rdbuff: 0,
+            csw: CSW_SIZE32,
+            tar: 0,
+            tar_upper: 0,
+            cfg: 0,
+            base: 0x0000_0002,
+            memory_base: 0,
+            words: [0; 8],
+            word: 0,
+            wait_after: None,
+            read_only: 0..0,
+        }
+    }
+
+    /// Returns the result of the last AP read.
+    pub fn rdbuff(&self) -> u32 {
+        self.rdbuff
+    }
+
+    /// Returns whether a sticky error is pending.
+    pub fn sticky_error(&self) -> bool {
+        self.ctrl_stat & CTRLSTAT_STICKYERR != 0
+    }
+
+    /// Returns whether AP transfers are refused because a sticky flag is set.
+    pub fn fault_pending(&self) -> bool {
+        self.ctrl_stat & (CTRLSTAT_STICKYERR | CTRLSTAT_STICKYCMP | CTRLSTAT_WDATAERR | CTRLSTAT_STICKYORUN) != 0
+    }
+
+    /// Lets the AP transfer after the next `after` ones answer WAIT once.
+    ///
+    /// Only the `RawDapAccess` implementation answers WAIT. With ORUNDETECT set in CTRL/STAT,
+    /// the WAIT sets STICKYORUN.
+    pub fn inject_wait(&mut self, after: usize) {
+        self.wait_after = Some(after);
+    }
+
+    /// Counts an AP transfer towards an injected WAIT and returns whether it has to WAIT.
+    fn busy(&mut self) -> bool {
+        match self.wait_after {
+            Some(0) => {
+                self.wait_after = None;
+                if self.ctrl_stat & CTRLSTAT_ORUNDETECT != 0 {
+                    self.ctrl_stat |= CTRLSTAT_STICKYORUN;
+                }
+                true
+            },
+            Some(after) => {
+                self.wait_after = Some(after - 1);
+                false
+            },
+            None => false,
+        }
+    }
+
+    /// Reads a DP or AP register.
+    pub fn read(&mut self, port: PortType, addr: u8) -> u32 {
+        let addr = addr as u32;
+        match port {
+            PortType::DebugPort => match addr {
+                DP_IDCODE => SIM_SWD_IDCODE,
+                DP_CTRL_STAT => self.ctrl_stat,
+                DP_RDBUFF => self.rdbuff,
+                _ => 0,
+            },
+            PortType::AccessPort => {
+                let value = self.read_ap((self.select & SELECT_APBANKSEL_MASK) | addr);
+                self.rdbuff = value;
+                value
+            },
+        }
+    }
+
+    /// Writes a DP or AP register.
+    pub fn write(&mut self, port: PortType, addr: u8, value: u32) {
+        let addr = addr as u32;
+        match port {
+            PortType::DebugPort => match addr {
+                DP_ABORT => {
+                    if value & ABORT_STKCMPCLR != 0 {
+                        self.ctrl_stat &= !CTRLSTAT_STICKYCMP;
+                    }
+                    if value & ABORT_STKERRCLR != 0 {
+                        self.ctrl_stat &= !CTRLSTAT_STICKYERR;
+                    }
+                    if value & ABORT_WDERRCLR != 0 {
+                        self.ctrl_stat &= !CTRLSTAT_WDATAERR;
+                    }
+                    if value & ABORT_ORUNERRCLR != 0 {
+                        self.ctrl_stat &= !CTRLSTAT_STICKYORUN;
+                    }
+                },
+                DP_CTRL_STAT => {
+                    // The sticky flags are write-one-to-clear, the power requests are acknowledged at once.
+                    let sticky = CTRLSTAT_STICKYORUN | CTRLSTAT_STICKYCMP | CTRLSTAT_STICKYERR | CTRLSTAT_WDATAERR;
+                    let requests = value & (CDBGPWRUPREQ | CSYSPWRUPREQ);
+                    self.ctrl_stat = (self.ctrl_stat & sticky & !value)
+                        | (value & !(sticky | CDBGPWRUPACK | CSYSPWRUPACK))
+                        | (requests << 1);
+                },
+                DP_SELECT => self.select = value,
+                _ => (),
+            },
+            PortType::AccessPort => self.write_ap((self.select & SELECT_APBANKSEL_MASK) | addr, value),
+        }
+    }
+
+    /// Sets the value of the MEM-AP CFG register. CFG.LA enables TAR upper and BASE upper,
+    /// CFG.LD enables accesses of 64 to 256 bits.
+    pub fn set_cfg(&mut self, cfg: u32) {
+        self.cfg = cfg;
+    }
+
+    /// Sets the raw value of BASE and BASE upper.
+    pub fn set_base(&mut self, base: u64) {
+        self.base = base;
+    }
+
+    /// Moves the RAM to `base`.
+    pub fn set_memory_base(&mut self, base: u64) {
+        self.memory_base = base;
+    }
+
+    /// Makes writes to the memory offsets in `range` go through without an error but
+    /// without effect, like writes to flash.
+    pub fn set_read_only(&mut self, range: Range<usize>) {
+        self.read_only = range;
+    }
+
+    /// Flags a failed transfer.
+    pub fn set_sticky_error(&mut self) {
+        self.ctrl_stat |= CTRLSTAT_STICKYERR;
+    }
+
+    /// Flags a write whose data phase was corrupted.
+    pub fn set_write_data_error(&mut self) {
+        self.ctrl_stat |= CTRLSTAT_WDATAERR;
+    }
+
+    fn selected_ap(&self) -> u32 {
+        self.select >> SELECT_APSEL_SHIFT
+    }
+
+    /// Returns the access size in bytes selected in CSW.
+    fn size(&self) -> Option<u32> {
+        match self.csw & CSW_SIZE {
+            CSW_SIZE8 => Some(1),
+            CSW_SIZE16 => Some(2),
+            CSW_SIZE32 => Some(4),
+            CSW_SIZE64 if self.large_data() => Some(8),
+            CSW_SIZE128 if self.large_data() => Some(16),
+            CSW_SIZE256 if self.large_data() => Some(32),
+            _ => None,
+        }
+    }
+
+    fn large_address(&self) -> bool {
+        self.cfg & CFG_LA != 0
+    }
+
+    fn large_data(&self) -> bool {
+        self.cfg & CFG_LD != 0
+    }
+
+    /// Returns the address in TAR, including TAR upper if it is implemented.
+    fn address(&self, tar: u32) -> u64 {
+        let upper = if self.large_address() { self.tar_upper } else { 0 };
+        ((upper as u64) << 32) | tar as u64
+    }
+
+    /// Checks that an access of `size` bytes at `addr` hits the memory.
+    fn check(&self, addr: u64, size: u32) -> bool {
+        addr % size as u64 == 0
+            && addr >= self.memory_base
+            && addr - self.memory_base + size as u64 <= self.memory.len() as u64
+    }
+
+    fn increment_tar(&mut self, size: u32) {
+        if self.csw & CSW_ADDRINC == CSW_SADDRINC {
+            // The auto-increment only covers the lowest 10 bits of TAR.
+            self.tar = (self.tar & !0x3FF) | (self.tar.wrapping_add(size) & 0x3FF);
+        }
+    }
+
+    fn read_memory(&mut self, addr: u64, size: u32) -> u32 {
+        if !self.check(addr, size) {
+            self.set_sticky_error();
+            return 0;
+        }
+        let offset = (addr - self.memory_base) as usize;
+        (0..size).fold(0, |acc, i| acc | (self.memory[offset + i as usize] as u32) << self.byte_shift(addr + i as u64))
+    }
+
+    fn write_memory(&mut self, addr: u64, size: u32, value: u32) {
+        if !self.check(addr, size) {
+            self.set_sticky_error();
+            return;
+        }
+        let offset = (addr - self.memory_base) as usize;
+        for i in 0..size {
+            if !self.read_only.contains(&(offset + i as usize)) {
+                self.memory[offset + i as usize] = (value >> self.byte_shift(addr + i as u64)) as u8;
+            }
+        }
+    }
+
+    /// Returns the position of the byte at `addr` in DRW.
+    fn byte_shift(&self, addr: u64) -> u32 {
+        let lane = (addr & 0x3) as u32;
+        if self.cfg & CFG_BE != 0 { (3 - lane) * 8 } else { lane * 8 }
+    }
+
+    /// Returns the next word of an access wider than 32 bits. The memory is read with the first word.
+    fn read_wide(&mut self, size: u32) -> u32 {
+        if self.word == 0 {
+            let addr = self.address(self.tar);
+            if !self.check(addr, size) {
+                self.set_sticky_error();
+                return 0;
+            }
+            for i in 0..size / 4 {
+                self.words[i as usize] = self.read_memory(addr + 4 * i as u64, 4);
+            }
+        }
+        let value = self.words[self.word];
+        self.next_word(size);
+        value
+    }
+
+    /// Collects the next word of an access wider than 32 bits. The memory is written with the last word.
+    fn write_wide(&mut self, size: u32, value: u32) {
+        self.words[self.word] = value;
+        if self.word + 1 == (size / 4) as usize {
+            let addr = self.address(self.tar);
+            if !self.check(addr, size) {
+                self.word = 0;
+                self.set_sticky_error();
+                return;
+            }
+            for i in 0..size / 4 {
+                self.write_memory(addr + 4 * i as u64, 4, self.words[i as usize]);
+            }
+        }
+        self.next_word(size);
+    }
+
+    /// Moves on to the next word of a wide access, TAR is incremented after the last one.
+    fn next_word(&mut self, size: u32) {
+        self.word += 1;
+        if self.word == (size / 4) as usize {
+            self.word = 0;
+            self.increment_tar(size);
+        }
+    }
+
+    /// Runs a pushed verify or pushed compare of `value` against the memory.
+    fn compare_memory(&mut self, addr: u64, size: u32, value: u32) {
+        let actual = self.read_memory(addr, size);
+        let size_mask = (0..size).fold(0, |acc, i| acc | 0xFF << self.byte_shift(addr + i as u64));
+        let lanes = (0..4).filter(|i| self.ctrl_stat & (1 << (8 + i)) != 0).fold(0, |acc, i| acc | (0xFF << (i * 8)));
+        let mask = size_mask & lanes;
+        let matches = actual & mask == value & mask;
+        let verify = self.ctrl_stat & CTRLSTAT_TRNMODE == TRNVERIFY;
+        if matches != verify {
+            self.ctrl_stat |= CTRLSTAT_STICKYCMP;
+        }
+    }
+
+    fn read_ap(&mut self, addr: u32) -> u32 {
+        if self.selected_ap() != 0 {
+            return 0;
+        }
+        match addr {
+            MEM_AP_CSW => self.csw | CSW_DBGSTAT,
+            MEM_AP_TAR => self.tar,
+            MEM_AP_TAR_UPPER if self.large_address() => self.tar_upper,
+            MEM_AP_DRW => match self.size() {
+                Some(size) if size > 4 => self.read_wide(size),
+                Some(size) => {
+                    // A failed transfer leaves TAR pointing at the faulting address.
+                    let value = self.read_memory(self.address(self.tar), size);
+                    if !self.sticky_error() {
+                        self.increment_tar(size);
+                    }
+                    value
+                },
+                None => {
+                    self.set_sticky_error();
+                    0
+                },
+            },
+            MEM_AP_BD0..=MEM_AP_BD3 => self.read_memory(self.address((self.tar & !0xF) | (addr & 0xC)), 4),
+            MEM_AP_CFG => self.cfg,
+            AP_BASE_UPPER if self.large_address() => (self.base >> 32) as u32,
+            AP_BASE => self.base as u32,
+            AP_IDR => SIM_AP_IDR,
+            _ => 0,
+        }
+    }
+
+    fn write_ap(&mut self, addr: u32, value: u32) {
+        if self.selected_ap() != 0 {
+            return;
+        }
+        match addr {
+            MEM_AP_CSW => {
+                let old = self.csw;
+                self.csw = value & !(CSW_DBGSTAT | CSW_TINPROG);
+                // Unsupported sizes and packed transfers leave the fields unchanged, like on
+                // an AP without them.
+                if self.size().is_none() {
+                    self.csw = (self.csw & !CSW_SIZE) | (old & CSW_SIZE);
+                }
+                if self.csw & CSW_ADDRINC == CSW_PADDRINC {
+                    self.csw = (self.csw & !CSW_ADDRINC) | (old & CSW_ADDRINC);
+                }
+                self.word = 0;
+            },
+            MEM_AP_TAR => {
+                self.tar = value;
+                self.word = 0;
+            },
+            MEM_AP_TAR_UPPER if self.large_address() => self.tar_upper = value,
+            MEM_AP_DRW => match self.size() {
+                Some(size) if size > 4 && self.ctrl_stat & CTRLSTAT_TRNMODE == TRNNORMAL => self.write_wide(size, value),
+                // Pushed transfers of large data are not modelled.
+                Some(size) if size > 4 => self.set_sticky_error(),
+                Some(size) if self.ctrl_stat & CTRLSTAT_TRNMODE != TRNNORMAL => {
+                    self.compare_memory(self.address(self.tar), size, value);
+                    if !self.sticky_error() {
+                        self.increment_tar(size);
+                    }
+                },
+                Some(size) => {
+                    self.write_memory(self.address(self.tar), size, value);
+                    if !self.sticky_error() {
+                        self.increment_tar(size);
+                    }
+                },
+                None => self.set_sticky_error(),
+            },
+            MEM_AP_BD0..=MEM_AP_BD3 => self.write_memory(self.address((self.tar & !0xF) | (addr & 0xC)), 4, value),
+            _ => (),
+        }
+    }
+}
+
+/// Lets the register model stand in for a probe. AP transfers fail while a sticky flag is
+/// set, including the one which set it.
+impl RawDapAccess for SimulatedDap {
+    type Error = DapError<Infallible>;
+
+    fn raw_read(&mut self, port: PortType, addr: u8) -> Result<u32, Self::Error> {
+        if port == PortType::AccessPort && self.fault_pending() {
+            return Err(DapError::Fault);
+        }
+        if port == PortType::AccessPort && self.busy() {
+            return Err(DapError::Wait);
+        }
+        let value = self.read(port, addr);
+        if port == PortType::AccessPort && self.fault_pending() {
+            return Err(DapError::Fault);
+        }
+        Ok(value)
+    }
+
+    fn raw_write(&mut self, port: PortType, addr: u8, value: u32) -> Result<(), Self::Error> {
+        if port == PortType::AccessPort && self.fault_pending() {
+            return Err(DapError::Fault);
+        }
+        if port == PortType::AccessPort && self.busy() {
+            return Err(DapError::Wait);
+        }
+        self.write(port, addr, value);
+        if port == PortType::AccessPort && self.fault_pending() {
+            return Err(DapError::Fault);
+        }
+        Ok(())
+    }
+}
+
+// SWD acknowledge values.
+const SWD_ACK_OK: u64 = 0b001;
+const SWD_ACK_FAULT: u64 = 0b100;
+
+#[derive(Debug)]
+enum SwdState {
+    /// Waiting for a line reset after a protocol error or power up.
+    Lockout,
+    /// Saw a line reset, waiting for the first idle cycle.
+    Reset,
+    Idle,
+    Request { request: u8, count: u8 },
+    /// Neither side drives the line for the given number of cycles.
+    Turnaround { count: u8, next: Box<SwdState> },
+    /// The target drives the given bits, LSB first.
+    Drive { bits: u64, count: u8, next: Box<SwdState> },
+    WriteData { request: u8, data: u64, count: u8 },
+}
+
+/// A SW-DP which is clocked bit by bit.
+pub struct SimulatedSwd {
+    state: SwdState,
+    ones: u32,
+}
+
+impl Default for SimulatedSwd {
+    fn default() -> Self {
+        Self {
+            state: SwdState::Lockout,
+            ones: 0,
+        }
+    }
+}
+
+impl SimulatedSwd {
+    /// Returns the bit the target drives in the current cycle, if any.
+    pub fn output(&self) -> Option<bool> {
+        match self.state {
+            SwdState::Drive { bits, .. } => Some(bits & 0x1 == 1),
+            _ => None,
+        }
+    }
+
+    /// Advances the target by one rising SWCLK edge, sampling `swdio`.
+    pub fn clock(&mut self, dap: &mut SimulatedDap, swdio: bool) {
+        let driving = self.output().is_some();
+        self.ones = if swdio && !driving { self.ones + 1 } else { 0 };
+
+        let state = core::mem::replace(&mut self.state, SwdState::Lockout);
+        self.state = match state {
+            // At least 50 cycles high reset the line from any state.
+            _ if self.ones >= 50 => SwdState::Reset,
+            SwdState::Lockout => SwdState::Lockout,
+            SwdState::Reset if swdio => SwdState::Reset,
+            SwdState::Reset | SwdState::Idle if swdio => SwdState::Request { request: 1, count: 1 },
+            SwdState::Reset | SwdState::Idle => SwdState::Idle,
+            SwdState::Request { request, count } => {
+                let request = request | ((swdio as u8) << count);
+                if count + 1 < 8 {
+                    SwdState::Request { request, count: count + 1 }
+                } else {
+                    self.respond(dap, request)
+                }
+            },
+            SwdState::Turnaround { count, next } => {
+                if count > 1 {
+                    SwdState::Turnaround { count: count - 1, next }
+                } else {
+                    *next
+                }
+            },
+            SwdState::Drive { bits, count, next } => {
+                if count > 1 {
+                    SwdState::Drive { bits: bits >> 1, count: count - 1, next }
+                } else {
+                    *next
+                }
+            },
+            SwdState::WriteData { request, data, count } => {
+                let data = data | ((swdio as u64) << count);
+                if count + 1 < 33 {
+                    SwdState::WriteData { request, data, count: count + 1 }
+                } else {
+                    let (port, _, addr) = Self::decode(request);
+                    let value = data as u32;
+                    if (value.count_ones() & 0x1) as u64 != data >> 32 {
+                        dap.set_write_data_error();
+                    } else {
+                        dap.write(port, addr, value);
+                    }
+                    SwdState::Idle
+                }
+            },
+        };
+    }
+
+    fn decode(request: u8) -> (PortType, bool, u8) {
+        let port = if request & 0x02 != 0 { PortType::AccessPort } else { PortType::DebugPort };
+        let read = request & 0x04 != 0;
+        let addr = (request >> 1) & 0xC;
+        (port, read, addr)
+    }
+
+    /// Answers a complete request header.
+    fn respond(&mut self, dap: &mut SimulatedDap, request: u8) -> SwdState {
+        let parity = (request >> 1) & 0xF;
+        let valid = request & 0x40 == 0
+            && request & 0x80 != 0
+            && parity.count_ones() & 0x1 == ((request >> 5) & 0x1) as u32;
+        if !valid {
+            return SwdState::Lockout;
+        }
+
+        let (port, read, addr) = Self::decode(request);
+        let ack_only = |ack| SwdState::Turnaround {
+            count: 1,
+            next: Box::new(SwdState::Drive {
+                bits: ack,
+                count: 3,
+                next: Box::new(SwdState::Turnaround { count: 1, next: Box::new(SwdState::Idle) }),
+            }),
+        };
+
+        if port == PortType::AccessPort && dap.fault_pending() {
+            return ack_only(SWD_ACK_FAULT);
+        }
+
+        if read {
+            // AP reads are posted and return the result of the previous AP read.
+            let value = match port {
+                PortType::DebugPort => dap.read(port, addr),
+                PortType::AccessPort => {
+                    let previous = dap.rdbuff();
+                    dap.read(port, addr);
+                    previous
+                },
+            };
+            let parity = (value.count_ones() & 0x1) as u64;
+            SwdState::Turnaround {
+                count: 1,
+                next: Box::new(SwdState::Drive {
+                    bits: SWD_ACK_OK | ((value as u64) << 3) | (parity << 35),
+                    count: 36,
+                    next: Box::new(SwdState::Turnaround { count: 1, next: Box::new(SwdState::Idle) }),
+                }),
+            }
+        } else {
+            SwdState::Turnaround {
+                count: 1,
+                next: Box::new(SwdState::Drive {
+                    bits: SWD_ACK_OK,
+                    count: 3,
+                    next: Box::new(SwdState::Turnaround {
+                        count: 1,
+                        next: Box::new(SwdState::WriteData { request, data: 0, count: 0 }),
+                    }),
+                }),
+            }
+        }
+    }
+}
+
+/// The states of a JTAG TAP controller.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TapState {
+    TestLogicReset,
+    RunTestIdle,
+    SelectDrScan,
+    CaptureDr,
+    ShiftDr,
+    Exit1Dr,
+    PauseDr,
+    Exit2Dr,
+    UpdateDr,
+    SelectIrScan,
+    CaptureIr,
+    ShiftIr,
+    Exit1Ir,
+    PauseIr,
+    Exit2Ir,
+    UpdateIr,
+}
+
+impl TapState {
+    /// Returns the state the TAP moves to on a rising TCK edge with the given TMS.
+    pub fn next(self, tms: bool) -> Self {
+        use TapState::*;
+        match (self, tms) {
+            (TestLogicReset, true) => TestLogicReset,
+            (TestLogicReset, false) => RunTestIdle,
+            (RunTestIdle, true) => SelectDrScan,
+            (RunTestIdle, false) => RunTestIdle,
+            (SelectDrScan, true) => SelectIrScan,
+            (SelectDrScan, false) => CaptureDr,
+            (CaptureDr, true) => Exit1Dr,
+            (CaptureDr, false) => ShiftDr,
+            (ShiftDr, true) => Exit1Dr,
+            (ShiftDr, false) => ShiftDr,
+            (Exit1Dr, true) => UpdateDr,
+            (Exit1Dr, false) => PauseDr,
+            (PauseDr, true) => Exit2Dr,
+            (PauseDr, false) => PauseDr,
+            (Exit2Dr, true) => UpdateDr,
+            (Exit2Dr, false) => ShiftDr,
+            (UpdateDr, true) => SelectDrScan,
+            (UpdateDr, false) => RunTestIdle,
+            (SelectIrScan, true) => TestLogicReset,
+            (SelectIrScan, false) => CaptureIr,
+            (CaptureIr, true) => Exit1Ir,
+            (CaptureIr, false) => ShiftIr,
+            (ShiftIr, true) => Exit1Ir,
+            (ShiftIr, false) => ShiftIr,
+            (Exit1Ir, true) => UpdateIr,
+            (Exit1Ir, false) => PauseIr,
+            (PauseIr, true) => Exit2Ir,
+            (PauseIr, false) => PauseIr,
+            (Exit2Ir, true) => UpdateIr,
+            (Exit2Ir, false) => ShiftIr,
+            (UpdateIr, true) => SelectDrScan,
+            (UpdateIr, false) => RunTestIdle,
+        }
+    }
+}
+
+// JTAG-DP instructions.
+const JTAG_IR_ABORT: u8 = 0x8;
+const JTAG_IR_DPACC: u8 = 0xA;
+const JTAG_IR_APACC: u8 = 0xB;
+const JTAG_IR_IDCODE: u8 = 0xE;
+
+/// The JTAG-DP acknowledge for an accepted transfer.
+const JTAG_ACK_OK: u64 = 0b010;
+
+/// A JTAG-DP which is clocked bit by bit.
+pub struct SimulatedJtag {
+    state: TapState,
+    ir: u8,
+    shift: u64,
+    len: u8,
+    result: u32,
+}
+
+impl Default for SimulatedJtag {
+    fn default() -> Self {
+        Self {
+            state: TapState::TestLogicReset,
+            ir: JTAG_IR_IDCODE,
+            shift: 0,
+            len: 0,
+            result: 0,
+        }
+    }
+}
+
+impl SimulatedJtag {
+    pub fn state(&self) -> TapState {
+        self.state
+    }
+
+    /// Returns the current level of TDO.
+    pub fn tdo(&self) -> bool {
+        self.shift & 0x1 == 1
+    }
+
+    /// Resets the TAP asynchronously, like TRST does.
+    pub fn reset(&mut self) {
+        self.state = TapState::TestLogicReset;
+        self.ir = JTAG_IR_IDCODE;
+    }
+
+    /// Advances the TAP by one rising TCK edge.
+    pub fn clock(&mut self, dap: &mut SimulatedDap, tms: bool, tdi: bool) {
+        match self.state {
+            TapState::CaptureIr => {
+                self.shift = 0b0001;
+                self.len = 4;
+            },
+            TapState::CaptureDr => {
+                let (shift, len) = match self.ir {
+                    JTAG_IR_IDCODE => (SIM_JTAG_IDCODE as u64, 32),
+                    JTAG_IR_DPACC | JTAG_IR_APACC | JTAG_IR_ABORT => (((self.result as u64) << 3) | JTAG_ACK_OK, 35),
+                    _ => (0, 1),
+                };
+                self.shift = shift;
+                self.len = len;
+            },
+            TapState::ShiftDr | TapState::ShiftIr => {
+                self.shift = (self.shift >> 1) | ((tdi as u64) << (self.len - 1));
+            },
+            _ => (),
+        }
+
+        self.state = self.state.next(tms);
+        match self.state {
+            TapState::TestLogicReset => self.ir = JTAG_IR_IDCODE,
+            TapState::UpdateIr => self.ir = self.shift as u8 & 0xF,
+            TapState::UpdateDr => self.update_dr(dap),
+            _ => (),
+        }
+    }
+
+    fn update_dr(&mut self, dap: &mut SimulatedDap) {
+        let read = self.shift & 0x1 == 1;
+        let addr = ((self.shift >> 1) & 0x3) as u8 * 4;
+        let value = (self.shift >> 3) as u32;
+        let port = match self.ir {
+            JTAG_IR_DPACC => PortType::DebugPort,
+            JTAG_IR_APACC => PortType::AccessPort,
+            JTAG_IR_ABORT => {
+                dap.write(PortType::DebugPort, DP_ABORT as u8, value);
+                return;
+            },
+            _ => return,
+        };
+
+        // Transfers to the AP are ignored while a sticky flag is set.
+        if port == PortType::AccessPort && dap.fault_pending() {
+            return;
+        }
+        if read {
+            self.result = dap.read(port, addr);
+        } else {
+            dap.write(port, addr, value);
+        }
+    }
+}
+
+/// A simulated target which can be driven over SWD or JTAG.
+pub struct SimulatedTarget {
+    pub dap: SimulatedDap,
+    swd: SimulatedSwd,
+    jtag: SimulatedJtag,
+}
+
+impl SimulatedTarget {
+    pub fn new(memory_size: usize) -> Self {
+        Self {
+            dap: SimulatedDap::new(memory_size),
+            swd: SimulatedSwd::default(),
+            jtag: SimulatedJtag::default(),
+        }
+    }
+
+    /// Advances the SW-DP by one rising SWCLK edge.
+    pub fn swd_clock(&mut self, swdio: bool) {
+        self.swd.clock(&mut self.dap, swdio);
+    }
+
+    /// Returns the bit the SW-DP drives, if any.
+    pub fn swd_output(&self) -> Option<bool> {
+        self.swd.output()
+    }
+
+    /// Advances the JTAG-DP by one rising TCK edge.
+    pub fn jtag_clock(&mut self, tms: bool, tdi: bool) {
+        self.jtag.clock(&mut self.dap, tms, tdi);
+    }
+
+    pub fn jtag_tdo(&self) -> bool {
+        self.jtag.tdo()
+    }
+
+    pub fn jtag_reset(&mut self) {
+        self.jtag.reset();
+    }
+}
@@ -0,0 +1,677 @@
+use crate::access_port::AccessPortNumber;
+use crate::dap_access::{DAPAccess, TransferError, TransferErrorKind};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+
+pub mod commands {
+    // Top level commands.
+    pub const GET_VERSION: u8 = 0xF1;
+    pub const JTAG_COMMAND: u8 = 0xF2;
+    pub const DFU_COMMAND: u8 = 0xF3;
+    pub const SWIM_COMMAND: u8 = 0xF4;
+    pub const GET_CURRENT_MODE: u8 = 0xF5;
+    pub const GET_TARGET_VOLTAGE: u8 = 0xF7;
+    pub const GET_VERSION_EXT: u8 = 0xFB;
+
+    // Modes returned by GET_CURRENT_MODE.
+    pub const DEV_DFU_MODE: u8 = 0x00;
+    pub const DEV_MASS_MODE: u8 = 0x01;
+    pub const DEV_JTAG_MODE: u8 = 0x02;
+    pub const DEV_SWIM_MODE: u8 = 0x03;
+
+    // Commands to exit other modes.
+    pub const DFU_EXIT: u8 = 0x07;
+    pub const SWIM_EXIT: u8 = 0x01;
+
+    // JTAG commands.
+    pub const JTAG_READMEM_32BIT: u8 = 0x07;
+    pub const JTAG_WRITEMEM_32BIT: u8 = 0x08;
+    pub const JTAG_READMEM_8BIT: u8 = 0x0C;
+    pub const JTAG_WRITEMEM_8BIT: u8 = 0x0D;
+    pub const JTAG_EXIT: u8 = 0x21;
+    pub const JTAG_ENTER2: u8 = 0x30;
+    pub const JTAG_GETLASTRWSTATUS2: u8 = 0x3E;
+    pub const SWD_SET_FREQ: u8 = 0x43;
+    pub const JTAG_READ_DAP_REG: u8 = 0x45;
+    pub const JTAG_WRITE_DAP_REG: u8 = 0x46;
+    pub const JTAG_INIT_AP: u8 = 0x4B;
+    pub const JTAG_CLOSE_AP_DBG: u8 = 0x4C;
+    pub const SET_COM_FREQ: u8 = 0x61;
+
+    // Parameters for JTAG_ENTER2.
+    pub const JTAG_ENTER_SWD: u8 = 0xA3;
+    pub const JTAG_ENTER_JTAG_NO_CORE_RESET: u8 = 0xA4;
+
+    // Parameters for JTAG_INIT_AP.
+    pub const JTAG_AP_NO_CORE: u8 = 0x00;
+
+    // Parameters for SET_COM_FREQ.
+    pub const JTAG_STLINK_SWD_COM: u8 = 0x00;
+    pub const JTAG_STLINK_JTAG_COM: u8 = 0x01;
+
+    // Status byte returned by the probe on success.
+    pub const JTAG_OK: u8 = 0x80;
+
+    // Status bytes for failed SWD transfers.
+    pub const SWD_AP_WAIT: u8 = 0x10;
+    pub const SWD_AP_FAULT: u8 = 0x11;
+    pub const SWD_AP_ERROR: u8 = 0x12;
+    pub const SWD_AP_PARITY_ERROR: u8 = 0x13;
+    pub const SWD_DP_WAIT: u8 = 0x14;
+    pub const SWD_DP_FAULT: u8 = 0x15;
+    pub const SWD_DP_ERROR: u8 = 0x16;
+    pub const SWD_DP_PARITY_ERROR: u8 = 0x17;
+    pub const SWD_AP_WDATA_ERROR: u8 = 0x18;
+    pub const SWD_AP_STICKY_ERROR: u8 = 0x19;
+    pub const SWD_AP_STICKYORUN_ERROR: u8 = 0x1A;
+}
+
+/// The first firmware version of the ST-Link/V2 which supports raw DAP register access.
+const MIN_JTAG_VERSION_DAP_ACCESS: u8 = 24;
+
+/// The largest chunk the ST-Link transfers with a single memory command.
+const MAX_MEMORY_TRANSFER_SIZE: usize = 1024;
+
+/// The largest chunk the ST-Link transfers with a single 8 bit memory command.
+const MAX_MEMORY_TRANSFER_SIZE_8BIT: usize = 64;
+
+/// SWD clock divisors of the ST-Link/V2 and the frequencies in kHz they result in.
+const SWD_FREQUENCY_DIVISORS: [(u32, u16); 12] = [
+    (4000, 0),
+    (1800, 1),
+    (1200, 2),
+    (950, 3),
+    (480, 7),
+    (240, 15),
+    (125, 31),
+    (100, 40),
+    (50, 79),
+    (25, 158),
+    (15, 265),
+    (5, 798),
+];
+
+/// The USB transport underneath an ST-Link.
+///
+/// Implementors send `cmd` to the probe, followed by `write_data` if it is not empty,
+/// and then fill `read_data` with the probe's response.
+pub trait StLinkUsb {
+    type Error: core::error::Error + Send + Sync + 'static;
+
+    fn write(&mut self, cmd: &[u8], write_data: &[u8], read_data: &mut [u8]) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WireProtocol {
+    Swd,
+    Jtag,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StLinkMode {
+    Dfu,
+    Mass,
+    Jtag,
+    Swim,
+    Unknown(u8),
+}
+
+impl From<u8> for StLinkMode {
+    fn from(mode: u8) -> Self {
+        match mode {
+            commands::DEV_DFU_MODE => StLinkMode::Dfu,
+            commands::DEV_MASS_MODE => StLinkMode::Mass,
+            commands::DEV_JTAG_MODE => StLinkMode::Jtag,
+            commands::DEV_SWIM_MODE => StLinkMode::Swim,
+            mode => StLinkMode::Unknown(mode),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum StLinkError<E> {
+    /// The USB transport failed.
+    Usb(E),
+    /// The probe answered with a status other than `JTAG_OK`.
+    CommandFailed(u8),
+    /// The firmware of the probe is too old for the requested operation.
+    FirmwareTooOld,
+    /// The requested address is not aligned to the transfer size.
+    MemoryNotAligned,
+    /// The probe reported a hardware version this driver does not know.
+    UnknownHardwareVersion(u8),
+}
+
+impl<E: fmt::Display> fmt::Display for StLinkError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StLinkError::Usb(e) => write!(f, "USB transfer failed: {}", e),
+            StLinkError::CommandFailed(status) => write!(f, "command failed with status {:#04X}", status),
+            StLinkError::FirmwareTooOld => f.write_str("the probe firmware is too old"),
+            StLinkError::MemoryNotAligned => f.write_str("the address is not aligned to the transfer size"),
+            StLinkError::UnknownHardwareVersion(version) => write!(f, "unknown hardware version {}", version),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for StLinkError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StLinkError::Usb(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl<E: Error + Send + Sync + 'static> TransferError for StLinkError<E> {
+    fn kind(&self) -> TransferErrorKind {
+        match *self {
+            StLinkError::CommandFailed(commands::SWD_AP_WAIT) | StLinkError::CommandFailed(commands::SWD_DP_WAIT) => TransferErrorKind::Wait,
+            StLinkError::CommandFailed(commands::SWD_AP_FAULT)
+            | StLinkError::CommandFailed(commands::SWD_DP_FAULT)
+            | StLinkError::CommandFailed(commands::SWD_AP_WDATA_ERROR)
+            | StLinkError::CommandFailed(commands::SWD_AP_STICKY_ERROR)
+            | StLinkError::CommandFailed(commands::SWD_AP_STICKYORUN_ERROR) => TransferErrorKind::Fault,
+            StLinkError::CommandFailed(commands::SWD_AP_ERROR) | StLinkError::CommandFailed(commands::SWD_DP_ERROR) => TransferErrorKind::Protocol,
+            StLinkError::CommandFailed(commands::SWD_AP_PARITY_ERROR)
+            | StLinkError::CommandFailed(commands::SWD_DP_PARITY_ERROR) => TransferErrorKind::Parity,
+            _ => TransferErrorKind::Other,
+        }
+    }
+}
+
+/// An ST-Link/V2 or ST-Link/V3 debug probe.
+pub struct StLink<U: StLinkUsb> {
+    usb: U,
+    hw_version: u8,
+    jtag_version: u8,
+}
+
+impl<U: StLinkUsb> StLink<U> {
+    /// Creates a new ST-Link on top of `usb` and reads its firmware version.
+    pub fn new(usb: U) -> Result<Self, StLinkError<U::Error>> {
+        let mut stlink = Self {
+            usb,
+            hw_version: 0,
+            jtag_version: 0,
+        };
+        stlink.read_version()?;
+        Ok(stlink)
+    }
+
+    /// Returns the hardware version of the probe, e.g. 2 for an ST-Link/V2.
+    pub fn hw_version(&self) -> u8 {
+        self.hw_version
+    }
+
+    /// Returns the version of the JTAG/SWD firmware of the probe.
+    pub fn jtag_version(&self) -> u8 {
+        self.jtag_version
+    }
+
+    /// Consumes the ST-Link and hands back its USB transport.
+    pub fn into_inner(self) -> U {
+        self.usb
+    }
+
+    fn transfer(&mut self, cmd: &[u8], write_data: &[u8], read_data: &mut [u8]) -> Result<(), StLinkError<U::Error>> {
+        self.usb.write(cmd, write_data, read_data).map_err(StLinkError::Usb)
+    }
+
+    /// Checks the status byte at the start of a response.
+    fn check_status(status: u8) -> Result<(), StLinkError<U::Error>> {
+        if status == commands::JTAG_OK {
+            Ok(())
+        } else {
+            Err(StLinkError::CommandFailed(status))
+        }
+    }
+
+    fn read_version(&mut self) -> Result<(), StLinkError<U::Error>> {
+        let mut buf = [0; 6];
+        self.transfer(&[commands::GET_VERSION], &[], &mut buf)?;
+
+        let version = u16::from_be_bytes([buf[0], buf[1]]);
+        self.hw_version = (version >> 12) as u8 & 0x0F;
+        self.jtag_version = (version >> 6) as u8 & 0x3F;
+
+        match self.hw_version {
+            2 => Ok(()),
+            // The ST-Link/V3 reports its real versions through the extended command only.
+            3 => {
+                let mut buf = [0; 12];
+                self.transfer(&[commands::GET_VERSION_EXT], &[], &mut buf)?;
+                self.jtag_version = buf[2];
+                Ok(())
+            },
+            version => Err(StLinkError::UnknownHardwareVersion(version)),
+        }
+    }
+
+    /// Reads the mode the probe currently runs in.
+    pub fn get_current_mode(&mut self) -> Result<StLinkMode, StLinkError<U::Error>> {
+        let mut buf = [0; 2];
+        self.transfer(&[commands::GET_CURRENT_MODE], &[], &mut buf)?;
+        Ok(buf[0].into())
+    }
+
+    /// Reads the target voltage in volts.
+    pub fn get_target_voltage(&mut self) -> Result<f32, StLinkError<U::Error>> {
+        let mut buf = [0; 8];
+        self.transfer(&[commands::GET_TARGET_VOLTAGE], &[], &mut buf)?;
+
+        let a0 = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f32;
+        let a1 = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]) as f32;
+        if a0 == 0.0 {
+            Ok(0.0)
+        } else {
+            Ok(2.0 * a1 * 1.2 / a0)
+        }
+    }
+
+    /// Leaves DFU or SWIM mode so the probe can enter the debug mode.
+    fn leave_current_mode(&mut self) -> Result<(), StLinkError<U::Error>> {
+        match self.get_current_mode()? {
+            StLinkMode::Dfu => self.transfer(&[commands::DFU_COMMAND, commands::DFU_EXIT], &[], &mut []),
+            StLinkMode::Swim => self.transfer(&[commands::SWIM_COMMAND, commands::SWIM_EXIT], &[], &mut []),
+            StLinkMode::Jtag => self.transfer(&[commands::JTAG_COMMAND, commands::JTAG_EXIT], &[], &mut []),
+            _ => Ok(()),
+        }
+    }
+
+    /// Puts the probe into debug mode using the given wire protocol.
+    pub fn attach(&mut self, protocol: WireProtocol) -> Result<(), StLinkError<U::Error>> {
+        self.leave_current_mode()?;
+
+        let param = match protocol {
+            WireProtocol::Swd => commands::JTAG_ENTER_SWD,
+            WireProtocol::Jtag => commands::JTAG_ENTER_JTAG_NO_CORE_RESET,
+        };
+        let mut buf = [0; 2];
+        self.transfer(&[commands::JTAG_COMMAND, commands::JTAG_ENTER2, param, 0], &[], &mut buf)?;
+        Self::check_status(buf[0])
+    }
+
+    /// Leaves debug mode.
+    pub fn detach(&mut self) -> Result<(), StLinkError<U::Error>> {
+        self.transfer(&[commands::JTAG_COMMAND, commands::JTAG_EXIT], &[], &mut [])
+    }
+
+    /// Sets the SWD clock to the fastest supported frequency not above `frequency_khz`.
+    ///
+    /// Returns the frequency that was actually selected.
+    pub fn set_swd_frequency(&mut self, frequency_khz: u32) -> Result<u32, StLinkError<U::Error>> {
+        if self.hw_version >= 3 {
+            let mut cmd = vec![commands::JTAG_COMMAND, commands::SET_COM_FREQ, commands::JTAG_STLINK_SWD_COM, 0];
+            cmd.extend_from_slice(&frequency_khz.to_le_bytes());
+            let mut buf = [0; 8];
+            self.transfer(&cmd, &[], &mut buf)?;
+            Self::check_status(buf[0])?;
+            Ok(u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]))
+        } else {
+            let (frequency, divisor) = SWD_FREQUENCY_DIVISORS
+                .iter()
+                .find(|(f, _)| *f <= frequency_khz)
+                .unwrap_or(&SWD_FREQUENCY_DIVISORS[SWD_FREQUENCY_DIVISORS.len() - 1]);
+            let divisor = divisor.to_le_bytes();
+            let mut buf = [0; 2];
+            self.transfer(&[commands::JTAG_COMMAND, commands::SWD_SET_FREQ, divisor[0], divisor[1]], &[], &mut buf)?;
+            Self::check_status(buf[0])?;
+            Ok(*frequency)
+        }
+    }
+
+    /// Opens an access port for memory transfers.
+    ///
+    /// Newer firmwares refuse memory transfers on an AP which was not opened first.
+    pub fn open_ap(&mut self, access_port: AccessPortNumber) -> Result<(), StLinkError<U::Error>> {
+        let mut buf = [0; 2];
+        self.transfer(&[commands::JTAG_COMMAND, commands::JTAG_INIT_AP, access_port as u8, commands::JTAG_AP_NO_CORE], &[], &mut buf)?;
+        Self::check_status(buf[0])
+    }
+
+    /// Closes an access port which was opened with `open_ap`.
+    pub fn close_ap(&mut self, access_port: AccessPortNumber) -> Result<(), StLinkError<U::Error>> {
+        let mut buf = [0; 2];
+        self.transfer(&[commands::JTAG_COMMAND, commands::JTAG_CLOSE_AP_DBG, access_port as u8], &[], &mut buf)?;
+        Self::check_status(buf[0])
+    }
+
+    fn check_dap_access_support(&self) -> Result<(), StLinkError<U::Error>> {
+        if self.hw_version < 3 && self.jtag_version < MIN_JTAG_VERSION_DAP_ACCESS {
+            Err(StLinkError::FirmwareTooOld)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Reads the status of the last memory transfer.
+    fn get_last_rw_status(&mut self) -> Result<(), StLinkError<U::Error>> {
+        let mut buf = [0; 12];
+        self.transfer(&[commands::JTAG_COMMAND, commands::JTAG_GETLASTRWSTATUS2], &[], &mut buf)?;
+        Self::check_status(buf[0])
+    }
+
+    fn memory_command(command: u8, access_port: AccessPortNumber, addr: u32, len: usize) -> [u8; 9] {
+        let addr = addr.to_le_bytes();
+        let len = (len as u16).to_le_bytes();
+        [
+            commands::JTAG_COMMAND,
+            command,
+            addr[0], addr[1], addr[2], addr[3],
+            len[0], len[1],
+            access_port as u8,
+        ]
+    }
+
+    /// Splits a transfer into chunks of at most `max_size` bytes which do not cross
+    /// the 1 KiB boundary the MEM-AP TAR auto-increment wraps at.
+    fn chunks(addr: u32, len: usize, max_size: usize) -> Vec<(u32, usize)> {
+        let mut chunks = vec![];
+        let mut addr = addr;
+        let mut remaining = len;
+        while remaining > 0 {
+            let to_boundary = MAX_MEMORY_TRANSFER_SIZE - (addr as usize % MAX_MEMORY_TRANSFER_SIZE);
+            let size = remaining.min(max_size).min(to_boundary);
+            chunks.push((addr, size));
+            addr += size as u32;
+            remaining -= size;
+        }
+        chunks
+    }
+
+    /// Reads a block of 32 bit words with the ST-Link memory commands.
+    pub fn read_mem32(&mut self, access_port: AccessPortNumber, addr: u32, data: &mut [u32]) -> Result<(), StLinkError<U::Error>> {
+        if addr & 0x3 != 0 {
+            return Err(StLinkError::MemoryNotAligned);
+        }
+
+        let mut offset = 0;
+        for (addr, size) in Self::chunks(addr, data.len() * 4, MAX_MEMORY_TRANSFER_SIZE) {
+            let mut buf = vec![0; size];
+            let cmd = Self::memory_command(commands::JTAG_READMEM_32BIT, access_port, addr, size);
+            self.transfer(&cmd, &[], &mut buf)?;
+            self.get_last_rw_status()?;
+            for word in buf.chunks(4) {
+                data[offset] = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+                offset += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a block of 32 bit words with the ST-Link memory commands.
+    pub fn write_mem32(&mut self, access_port: AccessPortNumber, addr: u32, data: &[u32]) -> Result<(), StLinkError<U::Error>> {
+        if addr & 0x3 != 0 {
+            return Err(StLinkError::MemoryNotAligned);
+        }
+
+        let bytes: Vec<u8> = data.iter().flat_map(|word| word.to_le_bytes().to_vec()).collect();
+        let mut offset = 0;
+        for (addr, size) in Self::chunks(addr, bytes.len(), MAX_MEMORY_TRANSFER_SIZE) {
+            let cmd = Self::memory_command(commands::JTAG_WRITEMEM_32BIT, access_port, addr, size);
+            self.transfer(&cmd, &bytes[offset..offset + size], &mut [])?;
+            self.get_last_rw_status()?;
+            offset += size;
+        }
+        Ok(())
+    }
+
+    /// Reads a block of bytes with the ST-Link memory commands.
+    pub fn read_mem8(&mut self, access_port: AccessPortNumber, addr: u32, data: &mut [u8]) -> Result<(), StLinkError<U::Error>> {
+        let mut offset = 0;
+        for (addr, size) in Self::chunks(addr, data.len(), MAX_MEMORY_TRANSFER_SIZE_8BIT) {
+            let cmd = Self::memory_command(commands::JTAG_READMEM_8BIT, access_port, addr, size);
+            // A single byte read still returns two bytes.
+            let mut buf = vec![0; size.max(2)];
+            self.transfer(&cmd, &[], &mut buf)?;
+            self.get_last_rw_status()?;
+            data[offset..offset + size].copy_from_slice(&buf[..size]);
+            offset += size;
+        }
+        Ok(())
+    }
+
+    /// Writes a block of bytes with the ST-Link memory commands.
+    pub fn write_mem8(&mut self, access_port: AccessPortNumber, addr: u32, data: &[u8]) -> Result<(), StLinkError<U::Error>> {
+        let mut offset = 0;
+        for (addr, size) in Self::chunks(addr, data.len(), MAX_MEMORY_TRANSFER_SIZE_8BIT) {
+            let cmd = Self::memory_command(commands::JTAG_WRITEMEM_8BIT, access_port, addr, size);
+            self.transfer(&cmd, &data[offset..offset + size], &mut [])?;
+            self.get_last_rw_status()?;
+            offset += size;
+        }
+        Ok(())
+    }
+}
+
+impl<U: StLinkUsb> DAPAccess for StLink<U> {
+    type Error = StLinkError<U::Error>;
+
+    /// Reads a DP or AP register through the ST-Link.
+    ///
+    /// The DP is addressed with `DEBUG_PORT`, which is also what the ST-Link expects.
+    fn read_register(&mut self, port: u16, addr: u32) -> Result<u32, Self::Error> {
+        self.check_dap_access_support()?;
+
+        let port = port.to_le_bytes();
+        let addr = (addr as u16).to_le_bytes();
+        let cmd = [
+            commands::JTAG_COMMAND,
+            commands::JTAG_READ_DAP_REG,
+            port[0], port[1],
+            addr[0], addr[1],
+        ];
+        let mut buf = [0; 8];
+        self.transfer(&cmd, &[], &mut buf)?;
+        Self::check_status(buf[0])?;
+        Ok(u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]))
+    }
+
+    /// Writes a DP or AP register through the ST-Link.
+    fn write_register(&mut self, port: u16, addr: u32, value: u32) -> Result<(), Self::Error> {
+        self.check_dap_access_support()?;
+
+        let port = port.to_le_bytes();
+        let addr = (addr as u16).to_le_bytes();
+        let value = value.to_le_bytes();
+        let cmd = [
+            commands::JTAG_COMMAND,
+            commands::JTAG_WRITE_DAP_REG,
+            port[0], port[1],
+            addr[0], addr[1],
+            value[0], value[1], value[2], value[3],
+        ];
+        let mut buf = [0; 2];
+        self.transfer(&cmd, &[], &mut buf)?;
+        Self::check_status(buf[0])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{commands, StLink, StLinkError, StLinkMode, StLinkUsb, WireProtocol};
+    use crate::dap_access::{DAPAccess, DEBUG_PORT};
+    use std::collections::VecDeque;
+    use std::convert::Infallible;
+
+    /// A single expected USB exchange.
+    struct Exchange {
+        cmd: Vec<u8>,
+        write_data: Vec<u8>,
+        response: Vec<u8>,
+    }
+
+    /// A stand-in for the USB transport which checks every command against a script
+    /// and answers with the scripted response.
+    #[derive(Default)]
+    struct ScriptedUsb {
+        script: VecDeque<Exchange>,
+    }
+
+    impl ScriptedUsb {
+        fn expect(mut self, cmd: &[u8], write_data: &[u8], response: &[u8]) -> Self {
+            self.script.push_back(Exchange {
+                cmd: cmd.to_vec(),
+                write_data: write_data.to_vec(),
+                response: response.to_vec(),
+            });
+            self
+        }
+
+        /// Scripts the version handshake of an ST-Link/V2 with firmware V2J29.
+        fn v2() -> Self {
+            let version: u16 = (2 << 12) | (29 << 6);
+            let version = version.to_be_bytes();
+            Self::default().expect(&[commands::GET_VERSION], &[], &[version[0], version[1], 0x83, 0x04, 0x48, 0x37])
+        }
+    }
+
+    impl StLinkUsb for ScriptedUsb {
+        type Error = Infallible;
+
+        fn write(&mut self, cmd: &[u8], write_data: &[u8], read_data: &mut [u8]) -> Result<(), Self::Error> {
+            let exchange = self.script.pop_front().expect("unexpected USB transfer");
+            assert_eq!(cmd, &exchange.cmd[..]);
+            assert_eq!(write_data, &exchange.write_data[..]);
+            assert_eq!(read_data.len(), exchange.response.len());
+            read_data.copy_from_slice(&exchange.response);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn version_v2() {
+        let stlink = StLink::new(ScriptedUsb::v2()).unwrap();
+        assert_eq!(stlink.hw_version(), 2);
+        assert_eq!(stlink.jtag_version(), 29);
+        assert!(stlink.into_inner().script.is_empty());
+    }
+
+    #[test]
+    fn version_v3() {
+        let version: u16 = 3 << 12;
+        let version = version.to_be_bytes();
+        let usb = ScriptedUsb::default()
+            .expect(&[commands::GET_VERSION], &[], &[version[0], version[1], 0x83, 0x04, 0x4F, 0x37])
+            .expect(&[commands::GET_VERSION_EXT], &[], &[3, 0, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let stlink = StLink::new(usb).unwrap();
+        assert_eq!(stlink.hw_version(), 3);
+        assert_eq!(stlink.jtag_version(), 7);
+    }
+
+    #[test]
+    fn attach_from_dfu_mode() {
+        let usb = ScriptedUsb::v2()
+            .expect(&[commands::GET_CURRENT_MODE], &[], &[commands::DEV_DFU_MODE, 0])
+            .expect(&[commands::DFU_COMMAND, commands::DFU_EXIT], &[], &[])
+            .expect(&[commands::JTAG_COMMAND, commands::JTAG_ENTER2, commands::JTAG_ENTER_SWD, 0], &[], &[commands::JTAG_OK, 0]);
+        let mut stlink = StLink::new(usb).unwrap();
+        assert!(stlink.attach(WireProtocol::Swd).is_ok());
+        assert!(stlink.into_inner().script.is_empty());
+    }
+
+    #[test]
+    fn current_mode() {
+        let usb = ScriptedUsb::v2()
+            .expect(&[commands::GET_CURRENT_MODE], &[], &[commands::DEV_JTAG_MODE, 0]);
+        let mut stlink = StLink::new(usb).unwrap();
+        assert_eq!(stlink.get_current_mode().unwrap(), StLinkMode::Jtag);
+    }
+
+    #[test]
+    fn read_dp_register() {
+        let usb = ScriptedUsb::v2()
+            .expect(&[commands::JTAG_COMMAND, commands::JTAG_READ_DAP_REG, 0xFF, 0xFF, 0x00, 0x00], &[], &[commands::JTAG_OK, 0, 0, 0, 0x77, 0x14, 0xA0, 0x2B]);
+        let mut stlink = StLink::new(usb).unwrap();
+        assert_eq!(stlink.read_register(DEBUG_PORT, 0x0).unwrap(), 0x2BA0_1477);
+    }
+
+    #[test]
+    fn write_ap_register() {
+        let usb = ScriptedUsb::v2()
+            .expect(&[commands::JTAG_COMMAND, commands::JTAG_WRITE_DAP_REG, 0x01, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x20], &[], &[commands::JTAG_OK, 0]);
+        let mut stlink = StLink::new(usb).unwrap();
+        assert!(stlink.write_register(1, 0x4, 0x2000_0000).is_ok());
+    }
+
+    #[test]
+    fn register_access_failure() {
+        let usb = ScriptedUsb::v2()
+            .expect(&[commands::JTAG_COMMAND, commands::JTAG_READ_DAP_REG, 0x00, 0x00, 0x0C, 0x00], &[], &[0x11, 0, 0, 0, 0, 0, 0, 0]);
+        let mut stlink = StLink::new(usb).unwrap();
+        match stlink.read_register(0, 0xC) {
+            Err(StLinkError::CommandFailed(0x11)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn register_access_needs_new_firmware() {
+        let version: u16 = (2 << 12) | (17 << 6);
+        let version = version.to_be_bytes();
+        let usb = ScriptedUsb::default()
+            .expect(&[commands::GET_VERSION], &[], &[version[0], version[1], 0x83, 0x04, 0x48, 0x37]);
+        let mut stlink = StLink::new(usb).unwrap();
+        match stlink.read_register(0, 0xC) {
+            Err(StLinkError::FirmwareTooOld) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn read_mem32() {
+        let usb = ScriptedUsb::v2()
+            .expect(&[commands::JTAG_COMMAND, commands::JTAG_READMEM_32BIT, 0x00, 0x00, 0x00, 0x20, 8, 0, 0], &[], &[0xEF, 0xBE, 0xAD, 0xDE, 0xBE, 0xBA, 0xBA, 0xAB])
+            .expect(&[commands::JTAG_COMMAND, commands::JTAG_GETLASTRWSTATUS2], &[], &[commands::JTAG_OK, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let mut stlink = StLink::new(usb).unwrap();
+        let mut data = [0; 2];
+        assert!(stlink.read_mem32(0, 0x2000_0000, &mut data).is_ok());
+        assert_eq!(data, [0xDEADBEEF, 0xABBABABE]);
+    }
+
+    #[test]
+    fn write_mem32_splits_at_1k_boundary() {
+        let usb = ScriptedUsb::v2()
+            .expect(&[commands::JTAG_COMMAND, commands::JTAG_WRITEMEM_32BIT, 0xFC, 0x03, 0x00, 0x20, 4, 0, 0], &[0xEF, 0xBE, 0xAD, 0xDE], &[])
+            .expect(&[commands::JTAG_COMMAND, commands::JTAG_GETLASTRWSTATUS2], &[], &[commands::JTAG_OK, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+            .expect(&[commands::JTAG_COMMAND, commands::JTAG_WRITEMEM_32BIT, 0x00, 0x04, 0x00, 0x20, 4, 0, 0], &[0xBE, 0xBA, 0xBA, 0xAB], &[])
+            .expect(&[commands::JTAG_COMMAND, commands::JTAG_GETLASTRWSTATUS2], &[], &[commands::JTAG_OK, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let mut stlink = StLink::new(usb).unwrap();
+        assert!(stlink.write_mem32(0, 0x2000_03FC, &[0xDEADBEEF, 0xABBABABE]).is_ok());
+        assert!(stlink.into_inner().script.is_empty());
+    }
+
+    #[test]
+    fn read_mem32_unaligned() {
+        let mut stlink = StLink::new(ScriptedUsb::v2()).unwrap();
+        let mut data = [0; 1];
+        match stlink.read_mem32(0, 0x2000_0002, &mut data) {
+            Err(StLinkError::MemoryNotAligned) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn read_mem8_reports_fault() {
+        let usb = ScriptedUsb::v2()
+            .expect(&[commands::JTAG_COMMAND, commands::JTAG_READMEM_8BIT, 0x01, 0x00, 0x00, 0x20, 3, 0, 0], &[], &[0xEF, 0xBE, 0xAD])
+            .expect(&[commands::JTAG_COMMAND, commands::JTAG_GETLASTRWSTATUS2], &[], &[0x11, 0, 0, 0, 0x01, 0x00, 0x00, 0x20, 0, 0, 0, 0]);
+        let mut stlink = StLink::new(usb).unwrap();
+        let mut data = [0; 3];
+        match stlink.read_mem8(0, 0x2000_0001, &mut data) {
+            Err(StLinkError::CommandFailed(0x11)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn set_swd_frequency_v2() {
+        let usb = ScriptedUsb::v2()
+            .expect(&[commands::JTAG_COMMAND, commands::SWD_SET_FREQ, 3, 0], &[], &[commands::JTAG_OK, 0]);
+        let mut stlink = StLink::new(usb).unwrap();
+        assert_eq!(stlink.set_swd_frequency(1000).unwrap(), 950);
+    }
+}
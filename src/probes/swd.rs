@@ -0,0 +1,167 @@
+use crate::dap_access::consts::*;
+use crate::probes::adiv5::{DapError, PortType, RawDapAccess};
+
+/// The number of times a transfer is repeated when the target answers WAIT.
+const DEFAULT_WAIT_RETRIES: usize = 100;
+
+/// The number of idle cycles clocked after each transfer.
+const IDLE_CYCLES: u8 = 8;
+
+/// The JTAG-to-SWD select sequence, sent LSB first.
+const JTAG_TO_SWD_SEQUENCE: u64 = 0xE79E;
+
+// SWD acknowledge values.
+const ACK_OK: u8 = 0b001;
+const ACK_WAIT: u8 = 0b010;
+const ACK_FAULT: u8 = 0b100;
+
+/// Bit level access to the SWD lines of a probe.
+///
+/// All bits are transferred LSB first, one bit per SWCLK cycle.
+pub trait SwdIo {
+    type Error: core::error::Error + Send + Sync + 'static;
+
+    /// Lets the probe drive SWDIO.
+    fn drive(&mut self) -> Result<(), Self::Error>;
+
+    /// Releases SWDIO so the target can drive it.
+    fn release(&mut self) -> Result<(), Self::Error>;
+
+    /// Clocks out the `len` lowest bits of `value`.
+    fn write_bits(&mut self, value: u64, len: u8) -> Result<(), Self::Error>;
+
+    /// Clocks in `len` bits.
+    fn read_bits(&mut self, len: u8) -> Result<u64, Self::Error>;
+}
+
+/// Builds the 8 bit request header of a transfer.
+fn request(port: PortType, read: bool, addr: u8) -> u8 {
+    let ap = (port == PortType::AccessPort) as u8;
+    let read = read as u8;
+    let a2 = (addr >> 2) & 0x1;
+    let a3 = (addr >> 3) & 0x1;
+    let parity = (ap + read + a2 + a3) & 0x1;
+    // start | APnDP | RnW | A[2:3] | parity | stop | park
+    0x01 | (ap << 1) | (read << 2) | (a2 << 3) | (a3 << 4) | (parity << 5) | 0x80
+}
+
+/// Runs the SWD wire protocol on top of a bit level interface.
+pub struct Swd<I: SwdIo> {
+    io: I,
+    wait_retries: usize,
+}
+
+impl<I: SwdIo> Swd<I> {
+    pub fn new(io: I) -> Self {
+        Self {
+            io,
+            wait_retries: DEFAULT_WAIT_RETRIES,
+        }
+    }
+
+    pub fn io(&mut self) -> &mut I {
+        &mut self.io
+    }
+
+    pub fn into_inner(self) -> I {
+        self.io
+    }
+
+    /// Sets how often a transfer is repeated when the target answers WAIT.
+    pub fn set_wait_retries(&mut self, wait_retries: usize) {
+        self.wait_retries = wait_retries;
+    }
+
+    /// Clocks at least 50 cycles with SWDIO high followed by a few idle cycles.
+    pub fn line_reset(&mut self) -> Result<(), DapError<I::Error>> {
+        self.io.drive().map_err(DapError::Io)?;
+        self.io.write_bits(u64::MAX >> 8, 56).map_err(DapError::Io)?;
+        self.io.write_bits(0, IDLE_CYCLES).map_err(DapError::Io)
+    }
+
+    /// Switches a SWJ-DP from JTAG to SWD and reads its IDCODE.
+    pub fn connect(&mut self) -> Result<u32, DapError<I::Error>> {
+        self.io.drive().map_err(DapError::Io)?;
+        self.io.write_bits(u64::MAX >> 8, 56).map_err(DapError::Io)?;
+        self.io.write_bits(JTAG_TO_SWD_SEQUENCE, 16).map_err(DapError::Io)?;
+        self.line_reset()?;
+        self.raw_read(PortType::DebugPort, DP_IDCODE as u8)
+    }
+
+    fn transfer(&mut self, port: PortType, read: bool, addr: u8, value: u32) -> Result<u32, DapError<I::Error>> {
+        let request = request(port, read, addr);
+        for _ in 0..=self.wait_retries {
+            self.io.write_bits(request as u64, 8).map_err(DapError::Io)?;
+            self.io.release().map_err(DapError::Io)?;
+            // One turnaround cycle followed by the three ACK bits.
+            let ack = ((self.io.read_bits(4).map_err(DapError::Io)? >> 1) & 0x7) as u8;
+
+            match ack {
+                ACK_OK if read => {
+                    // Data, parity and the turnaround cycle back to the host.
+                    let data = self.io.read_bits(34).map_err(DapError::Io)?;
+                    self.io.drive().map_err(DapError::Io)?;
+                    self.io.write_bits(0, IDLE_CYCLES).map_err(DapError::Io)?;
+
+                    let value = data as u32;
+                    let parity = ((data >> 32) & 0x1) as u32;
+                    if value.count_ones() & 0x1 != parity {
+                        return Err(DapError::Parity);
+                    }
+                    return Ok(value);
+                },
+                ACK_OK => {
+                    self.io.read_bits(1).map_err(DapError::Io)?;
+                    self.io.drive().map_err(DapError::Io)?;
+                    let parity = (value.count_ones() & 0x1) as u64;
+                    self.io.write_bits(value as u64 | (parity << 32), 33).map_err(DapError::Io)?;
+                    self.io.write_bits(0, IDLE_CYCLES).map_err(DapError::Io)?;
+                    return Ok(0);
+                },
+                _ => {
+                    self.io.read_bits(1).map_err(DapError::Io)?;
+                    self.io.drive().map_err(DapError::Io)?;
+                    self.io.write_bits(0, IDLE_CYCLES).map_err(DapError::Io)?;
+                    match ack {
+                        ACK_WAIT => continue,
+                        ACK_FAULT => return Err(DapError::Fault),
+                        ack => return Err(DapError::Protocol(ack)),
+                    }
+                },
+            }
+        }
+        Err(DapError::Wait)
+    }
+}
+
+impl<I: SwdIo> RawDapAccess for Swd<I> {
+    type Error = DapError<I::Error>;
+
+    fn raw_read(&mut self, port: PortType, addr: u8) -> Result<u32, Self::Error> {
+        let value = self.transfer(port, true, addr, 0)?;
+        match port {
+            PortType::DebugPort => Ok(value),
+            // AP reads are posted, the result is fetched from RDBUFF.
+            PortType::AccessPort => self.transfer(PortType::DebugPort, true, DP_RDBUFF as u8, 0),
+        }
+    }
+
+    fn raw_write(&mut self, port: PortType, addr: u8, value: u32) -> Result<(), Self::Error> {
+        self.transfer(port, false, addr, value).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::request;
+    use crate::probes::adiv5::PortType;
+
+    #[test]
+    fn request_headers() {
+        // Well known request bytes as seen on a logic analyzer.
+        assert_eq!(request(PortType::DebugPort, true, 0x0), 0xA5);
+        assert_eq!(request(PortType::DebugPort, false, 0x8), 0xB1);
+        assert_eq!(request(PortType::AccessPort, true, 0xC), 0x9F);
+        assert_eq!(request(PortType::DebugPort, true, 0xC), 0xBD);
+    }
+}
@@ -0,0 +1,450 @@
+use crate::dap_access::{DAPAccess, Transfer, TransferError, TransferErrorKind, TransferKind};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// A single recorded register transaction.
+///
+/// Recordings are text files with one transaction per line, e.g.
+/// `R 0000 000000FC 24770011 OK` or `W FFFF 00000008 00000000 ERR FAULT Fault`.
+/// Lines starting with `#` are comments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub kind: TransferKind,
+    pub port: u16,
+    pub addr: u32,
+    /// The value written, or the value read if the read went through.
+    pub value: u32,
+    pub error: Option<RecordedError>,
+}
+
+/// An error the DAP reported during the recording.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedError {
+    pub kind: TransferErrorKind,
+    /// The error formatted with `Debug`.
+    pub message: String,
+}
+
+/// The names of the error kinds in recordings.
+const ERROR_KINDS: [(TransferErrorKind, &str); 5] = [
+    (TransferErrorKind::Wait, "WAIT"),
+    (TransferErrorKind::Fault, "FAULT"),
+    (TransferErrorKind::Protocol, "PROTOCOL"),
+    (TransferErrorKind::Parity, "PARITY"),
+    (TransferErrorKind::Other, "OTHER"),
+];
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            TransferKind::Read => 'R',
+            TransferKind::Write => 'W',
+        };
+        write!(f, "{} {:04X} {:08X} {:08X} ", kind, self.port, self.addr, self.value)?;
+        match self.error {
+            None => write!(f, "OK"),
+            Some(ref error) => {
+                let (_, name) = ERROR_KINDS.iter().find(|(kind, _)| *kind == error.kind).unwrap();
+                write!(f, "ERR {} {}", name, error.message)
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for Record {
+    type Err = ();
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut fields = line.splitn(5, ' ');
+        let kind = match fields.next() {
+            Some("R") => TransferKind::Read,
+            Some("W") => TransferKind::Write,
+            _ => return Err(()),
+        };
+        let port = u16::from_str_radix(fields.next().ok_or(())?, 16).map_err(|_| ())?;
+        let addr = u32::from_str_radix(fields.next().ok_or(())?, 16).map_err(|_| ())?;
+        let value = u32::from_str_radix(fields.next().ok_or(())?, 16).map_err(|_| ())?;
+        let status = fields.next().ok_or(())?;
+        let error = if status == "OK" {
+            None
+        } else if let Some(error) = status.strip_prefix("ERR ") {
+            // Recordings without the kind of the error replay it as `Other`.
+            let (kind, message) = error
+                .split_once(' ')
+                .and_then(|(name, message)| {
+                    ERROR_KINDS.iter().find(|(_, n)| *n == name).map(|(kind, _)| (*kind, message))
+                })
+                .unwrap_or((TransferErrorKind::Other, error));
+            Some(RecordedError {
+                kind,
+                message: message.to_string(),
+            })
+        } else {
+            return Err(());
+        };
+        Ok(Self {
+            kind,
+            port,
+            addr,
+            value,
+            error,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum RecordingError<E> {
+    Dap(E),
+    /// The transaction went through but could not be logged.
+    Io(io::Error),
+}
+
+impl<E: fmt::Display> fmt::Display for RecordingError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordingError::Dap(e) => e.fmt(f),
+            RecordingError::Io(e) => write!(f, "could not log the transaction: {}", e),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for RecordingError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RecordingError::Dap(e) => e.source(),
+            RecordingError::Io(e) => Some(e),
+        }
+    }
+}
+
+impl<E: TransferError> TransferError for RecordingError<E> {
+    fn kind(&self) -> TransferErrorKind {
+        match self {
+            RecordingError::Dap(e) => e.kind(),
+            RecordingError::Io(_) => TransferErrorKind::Other,
+        }
+    }
+}
+
+/// Logs every register transaction of the wrapped DAP.
+pub struct RecordingDAP<D: DAPAccess, W: Write> {
+    dap: D,
+    log: W,
+}
+
+impl<D: DAPAccess> RecordingDAP<D, BufWriter<File>> {
+    /// Records into a newly created file at `path`.
+    pub fn create(dap: D, path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(dap, BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<D: DAPAccess, W: Write> RecordingDAP<D, W> {
+    pub fn new(dap: D, log: W) -> Self {
+        Self {
+            dap,
+            log,
+        }
+    }
+
+    /// Flushes the log and returns the DAP and the log.
+    pub fn into_inner(mut self) -> io::Result<(D, W)> {
+        self.log.flush()?;
+        Ok((self.dap, self.log))
+    }
+
+    fn record(&mut self, kind: TransferKind, port: u16, addr: u32, value: u32, error: Option<&D::Error>) -> io::Result<()> {
+        let record = Record {
+            kind,
+            port,
+            addr,
+            value,
+            error: error.map(|e| RecordedError {
+                kind: e.kind(),
+                message: format!("{:?}", e),
+            }),
+        };
+        writeln!(self.log, "{}", record)
+    }
+}
+
+impl<D: DAPAccess, W: Write> DAPAccess for RecordingDAP<D, W> {
+    type Error = RecordingError<D::Error>;
+
+    fn read_register(&mut self, port: u16, addr: u32) -> Result<u32, Self::Error> {
+        let result = self.dap.read_register(port, addr);
+        let (value, error) = match result {
+            Ok(value) => (value, None),
+            Err(ref e) => (0, Some(e)),
+        };
+        self.record(TransferKind::Read, port, addr, value, error).map_err(RecordingError::Io)?;
+        result.map_err(RecordingError::Dap)
+    }
+
+    fn write_register(&mut self, port: u16, addr: u32, value: u32) -> Result<(), Self::Error> {
+        let result = self.dap.write_register(port, addr, value);
+        self.record(TransferKind::Write, port, addr, value, result.as_ref().err()).map_err(RecordingError::Io)?;
+        result.map_err(RecordingError::Dap)
+    }
+
+    fn batch(&mut self, transfers: &mut [Transfer]) -> Result<(), Self::Error> {
+        let result = self.dap.batch(transfers);
+        for transfer in transfers.iter().take_while(|t| t.done) {
+            self.record(transfer.kind, transfer.port, transfer.addr, transfer.value, None).map_err(RecordingError::Io)?;
+        }
+        if let Err(ref e) = result {
+            if let Some(failed) = transfers.iter().find(|t| !t.done) {
+                let value = if failed.kind == TransferKind::Write { failed.value } else { 0 };
+                self.record(failed.kind, failed.port, failed.addr, value, Some(e)).map_err(RecordingError::Io)?;
+            }
+        }
+        result.map_err(RecordingError::Dap)
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    /// The transaction does not match the recording.
+    Divergence {
+        /// The position of the expected record in the recording.
+        index: usize,
+        expected: Record,
+        actual: Record,
+    },
+    /// The recording ran out of transactions.
+    EndOfRecording,
+    /// The DAP failed at this point of the recording.
+    Recorded(RecordedError),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Divergence { index, expected, actual } => {
+                write!(f, "diverged from record {}: expected `{}`, got `{}`", index, expected, actual)
+            },
+            ReplayError::EndOfRecording => f.write_str("the recording ran out of transactions"),
+            ReplayError::Recorded(error) => write!(f, "recorded {}: {}", error.kind, error.message),
+        }
+    }
+}
+
+impl Error for ReplayError {}
+
+impl TransferError for ReplayError {
+    /// Recorded errors keep their kind, so the replay recovers from them like the recorded session.
+    fn kind(&self) -> TransferErrorKind {
+        match self {
+            ReplayError::Recorded(error) => error.kind,
+            _ => TransferErrorKind::Other,
+        }
+    }
+}
+
+/// Serves a recorded session back and reports every deviation from it.
+pub struct ReplayDAP {
+    records: Vec<Record>,
+    position: usize,
+}
+
+impl ReplayDAP {
+    pub fn new(records: Vec<Record>) -> Self {
+        Self {
+            records,
+            position: 0,
+        }
+    }
+
+    /// Loads a recording made by `RecordingDAP`.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    pub fn from_reader(reader: impl BufRead) -> io::Result<Self> {
+        let mut records = vec![];
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let record = line.parse().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("invalid record in line {}", number + 1))
+            })?;
+            records.push(record);
+        }
+        Ok(Self::new(records))
+    }
+
+    /// Returns whether the whole recording was replayed.
+    pub fn is_finished(&self) -> bool {
+        self.position == self.records.len()
+    }
+
+    /// Returns the records which were not replayed yet.
+    pub fn remaining(&self) -> &[Record] {
+        &self.records[self.position..]
+    }
+
+    /// Checks the next record against the actual transaction and consumes it.
+    fn next(&mut self, actual: Record) -> Result<u32, ReplayError> {
+        let index = self.position;
+        let expected = self.records.get(index).ok_or(ReplayError::EndOfRecording)?;
+        let matches = expected.kind == actual.kind
+            && expected.port == actual.port
+            && expected.addr == actual.addr
+            && (actual.kind == TransferKind::Read || expected.value == actual.value);
+        if !matches {
+            return Err(ReplayError::Divergence {
+                index,
+                expected: expected.clone(),
+                actual,
+            });
+        }
+
+        self.position += 1;
+        match expected.error {
+            Some(ref error) => Err(ReplayError::Recorded(error.clone())),
+            None => Ok(expected.value),
+        }
+    }
+}
+
+impl DAPAccess for ReplayDAP {
+    type Error = ReplayError;
+
+    fn read_register(&mut self, port: u16, addr: u32) -> Result<u32, Self::Error> {
+        self.next(Record {
+            kind: TransferKind::Read,
+            port,
+            addr,
+            value: 0,
+            error: None,
+        })
+    }
+
+    fn write_register(&mut self, port: u16, addr: u32, value: u32) -> Result<(), Self::Error> {
+        self.next(Record {
+            kind: TransferKind::Write,
+            port,
+            addr,
+            value,
+            error: None,
+        }).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Record, RecordedError, RecordingDAP, ReplayDAP, ReplayError};
+    use crate::access_port::consts::*;
+    use crate::access_port::AccessPortError;
+    use crate::dap_access::{DAPAccess, MockDAP, TransferErrorKind, TransferKind};
+    use crate::memory_interface::MemoryInterface;
+    use crate::probes::adiv5::AdiV5;
+    use crate::probes::sim::SimulatedDap;
+
+    /// Runs a short session against a `MockDAP` and returns the recording.
+    fn record_session() -> Vec<u8> {
+        let mut mock = MockDAP::new();
+        mock.data[0x10..0x14].copy_from_slice(&[0xEF, 0xBE, 0xAD, 0xDE]);
+        let mut dap = RecordingDAP::new(mock, vec![]);
+        let mi = MemoryInterface::new(0);
+        assert_eq!(mi.read::<u32>(&mut dap, 0x10).unwrap(), 0xDEADBEEF);
+        mi.write(&mut dap, 0x20, 0xABBA_u16).unwrap();
+        assert!(dap.read_register(0, AP_IDR).is_err());
+        dap.into_inner().unwrap().1
+    }
+
+    #[test]
+    fn record_format() {
+        let log = String::from_utf8(record_session()).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines, vec![
+            "W 0000 00000000 23000052 OK",
+            "W 0000 00000004 00000010 OK",
+            "R 0000 0000000C DEADBEEF OK",
+            "W 0000 00000000 23000051 OK",
+            "W 0000 00000004 00000020 OK",
+            "W 0000 0000000C 0000ABBA OK",
+            "R 0000 000000FC 00000000 ERR OTHER BadInstruction",
+        ]);
+    }
+
+    #[test]
+    fn record_roundtrip() {
+        let record = Record {
+            kind: TransferKind::Write,
+            port: 0xFFFF,
+            addr: 0x8,
+            value: 0x0100_00F0,
+            error: Some(RecordedError {
+                kind: TransferErrorKind::Fault,
+                message: "Fault".to_string(),
+            }),
+        };
+        assert_eq!(record.to_string(), "W FFFF 00000008 010000F0 ERR FAULT Fault");
+        assert_eq!(record.to_string().parse::<Record>(), Ok(record));
+        assert!("X 0000 00000000 00000000 OK".parse::<Record>().is_err());
+        let record = "R 0000 000000FC 00000000 ERR BadInstruction".parse::<Record>().unwrap();
+        assert_eq!(record.error.unwrap().kind, TransferErrorKind::Other);
+    }
+
+    #[test]
+    fn replay_session() {
+        let log = record_session();
+        let mut dap = ReplayDAP::from_reader(&log[..]).unwrap();
+        let mi = MemoryInterface::new(0);
+        assert_eq!(mi.read::<u32>(&mut dap, 0x10).unwrap(), 0xDEADBEEF);
+        mi.write(&mut dap, 0x20, 0xABBA_u16).unwrap();
+        match dap.read_register(0, AP_IDR) {
+            Err(ReplayError::Recorded(error)) => assert_eq!(error.message, "BadInstruction"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(dap.is_finished());
+        match dap.read_register(0, AP_IDR) {
+            Err(ReplayError::EndOfRecording) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn replay_divergence() {
+        let log = record_session();
+        let mut dap = ReplayDAP::from_reader(&log[..]).unwrap();
+        let mi = MemoryInterface::new(0);
+        assert_eq!(mi.read::<u32>(&mut dap, 0x10).unwrap(), 0xDEADBEEF);
+        // A different value than in the recording.
+        match mi.write(&mut dap, 0x20, 0xABBA_u32) {
+            Err(_) => (),
+            Ok(()) => panic!("divergence was not detected"),
+        }
+        match dap.write_register(0, MEM_AP_CSW, CSW_VALUE | CSW_SIZE32) {
+            Err(ReplayError::Divergence { index, expected, .. }) => {
+                assert_eq!(index, 3);
+                assert_eq!(expected.value, CSW_VALUE | CSW_SIZE16);
+            },
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(dap.remaining().len(), 4);
+    }
+
+    #[test]
+    fn replay_fault() {
+        let mi = MemoryInterface::new(0);
+        let fault = AccessPortError::Fault { ap: 0, address: 0x100, ctrl_stat: 0x20 };
+        let mut dap = RecordingDAP::new(AdiV5::new(SimulatedDap::new(0x100)), vec![]);
+        assert_eq!(mi.read::<u32>(&mut dap, 0x100), Err(fault.clone()));
+        assert_eq!(mi.read::<u32>(&mut dap, 0xFC), Ok(0));
+        let log = dap.into_inner().unwrap().1;
+        assert!(String::from_utf8_lossy(&log).lines().any(|line| line.ends_with("ERR FAULT Fault")));
+
+        // The replay clears the sticky flags and reads TAR like the recorded session.
+        let mut dap = ReplayDAP::from_reader(&log[..]).unwrap();
+        assert_eq!(mi.read::<u32>(&mut dap, 0x100), Err(fault));
+        assert_eq!(mi.read::<u32>(&mut dap, 0xFC), Ok(0));
+        assert!(dap.is_finished());
+    }
+}
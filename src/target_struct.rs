@@ -0,0 +1,209 @@
+/// The byte order of values in target memory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+/// A type with a fixed layout in target memory, like a `#[repr(C)]` struct of the firmware.
+///
+/// Implemented for the integer and float types and arrays of them. Structs get it with
+/// `target_struct!`, which lays out the fields like `#[repr(C)]` does.
+pub trait TargetStruct: Sized {
+    /// The size in target memory, including padding at the end.
+    const SIZE: usize;
+    /// The alignment in target memory, which decides the padding in front of it in a struct.
+    const ALIGN: usize;
+
+    /// Decodes a value from the first `SIZE` bytes of `bytes`.
+    fn decode(bytes: &[u8], endian: Endian) -> Self;
+
+    /// Encodes the value into the first `SIZE` bytes of `bytes`. Padding is left alone.
+    fn encode(&self, bytes: &mut [u8], endian: Endian);
+}
+
+/// Rounds `offset` up to a multiple of `align`.
+pub const fn align_up(offset: usize, align: usize) -> usize {
+    offset.div_ceil(align) * align
+}
+
+macro_rules! impl_target_struct {
+    ($($ty:ty),*) => {
+        $(
+            impl TargetStruct for $ty {
+                const SIZE: usize = core::mem::size_of::<$ty>();
+                const ALIGN: usize = core::mem::size_of::<$ty>();
+
+                fn decode(bytes: &[u8], endian: Endian) -> Self {
+                    let mut value = [0; core::mem::size_of::<$ty>()];
+                    value.copy_from_slice(&bytes[..Self::SIZE]);
+                    match endian {
+                        Endian::Little => <$ty>::from_le_bytes(value),
+                        Endian::Big => <$ty>::from_be_bytes(value),
+                    }
+                }
+
+                fn encode(&self, bytes: &mut [u8], endian: Endian) {
+                    let value = match endian {
+                        Endian::Little => self.to_le_bytes(),
+                        Endian::Big => self.to_be_bytes(),
+                    };
+                    bytes[..Self::SIZE].copy_from_slice(&value);
+                }
+            }
+        )*
+    };
+}
+
+impl_target_struct!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+impl<T: TargetStruct, const N: usize> TargetStruct for [T; N] {
+    const SIZE: usize = T::SIZE * N;
+    const ALIGN: usize = T::ALIGN;
+
+    fn decode(bytes: &[u8], endian: Endian) -> Self {
+        core::array::from_fn(|i| T::decode(&bytes[i * T::SIZE..], endian))
+    }
+
+    fn encode(&self, bytes: &mut [u8], endian: Endian) {
+        for (i, value) in self.iter().enumerate() {
+            value.encode(&mut bytes[i * T::SIZE..], endian);
+        }
+    }
+}
+
+/// Implements `TargetStruct` for a struct, laying out the listed fields in order like `#[repr(C)]`.
+///
+/// All fields have to be listed. The struct itself does not need to be `#[repr(C)]`, the
+/// layout on the host does not matter.
+///
+/// ```
+/// use coresight_rs::target_struct;
+///
+/// struct Config {
+///     magic: u32,
+///     version: u16,
+///     flags: [u8; 3],
+///     timeout: u32,
+/// }
+///
+/// target_struct!(Config { magic: u32, version: u16, flags: [u8; 3], timeout: u32 });
+/// ```
+#[macro_export]
+macro_rules! target_struct {
+    ($name:ty { $($field:ident: $ty:ty),* $(,)? }) => {
+        impl $crate::target_struct::TargetStruct for $name {
+            const SIZE: usize = {
+                let mut offset = 0;
+                $(offset = $crate::target_struct::align_up(offset, <$ty as $crate::target_struct::TargetStruct>::ALIGN)
+                    + <$ty as $crate::target_struct::TargetStruct>::SIZE;)*
+                $crate::target_struct::align_up(offset, Self::ALIGN)
+            };
+            const ALIGN: usize = {
+                let mut align = 1;
+                $(if <$ty as $crate::target_struct::TargetStruct>::ALIGN > align {
+                    align = <$ty as $crate::target_struct::TargetStruct>::ALIGN;
+                })*
+                align
+            };
+
+            #[allow(unused_assignments)]
+            fn decode(bytes: &[u8], endian: $crate::target_struct::Endian) -> Self {
+                let mut offset = 0;
+                $(
+                    offset = $crate::target_struct::align_up(offset, <$ty as $crate::target_struct::TargetStruct>::ALIGN);
+                    let $field = <$ty as $crate::target_struct::TargetStruct>::decode(&bytes[offset..], endian);
+                    offset += <$ty as $crate::target_struct::TargetStruct>::SIZE;
+                )*
+                Self { $($field),* }
+            }
+
+            #[allow(unused_assignments)]
+            fn encode(&self, bytes: &mut [u8], endian: $crate::target_struct::Endian) {
+                let mut offset = 0;
+                $(
+                    offset = $crate::target_struct::align_up(offset, <$ty as $crate::target_struct::TargetStruct>::ALIGN);
+                    $crate::target_struct::TargetStruct::encode(&self.$field, &mut bytes[offset..], endian);
+                    offset += <$ty as $crate::target_struct::TargetStruct>::SIZE;
+                )*
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Endian, TargetStruct};
+    use crate::access_port::consts::*;
+    use crate::memory_interface::MemoryInterface;
+    use crate::probes::adiv5::AdiV5;
+    use crate::probes::sim::SimulatedDap;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Header {
+        kind: u8,
+        length: u16,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Config {
+        magic: u32,
+        header: Header,
+        gain: f32,
+        serial: u64,
+        flags: [u8; 3],
+    }
+
+    crate::target_struct!(Header { kind: u8, length: u16 });
+    crate::target_struct!(Config { magic: u32, header: Header, gain: f32, serial: u64, flags: [u8; 3] });
+
+    fn config() -> Config {
+        Config {
+            magic: 0xC0FF_EE00,
+            header: Header { kind: 7, length: 0x0102 },
+            gain: 1.5,
+            serial: 0x1122_3344_5566_7788,
+            flags: [1, 2, 3],
+        }
+    }
+
+    #[test]
+    fn layout() {
+        assert_eq!((Header::SIZE, Header::ALIGN), (4, 2));
+        // magic 0..4, header 4..8, gain 8..12, padding, serial 16..24, flags 24..27, padding.
+        assert_eq!((Config::SIZE, Config::ALIGN), (32, 8));
+
+        let mut bytes = [0xAA; 32];
+        config().encode(&mut bytes, Endian::Little);
+        assert_eq!(bytes[..8], [0x00, 0xEE, 0xFF, 0xC0, 7, 0xAA, 0x02, 0x01]);
+        assert_eq!(bytes[12..16], [0xAA; 4]);
+        assert_eq!(bytes[16..28], [0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, 1, 2, 3, 0xAA]);
+        assert_eq!(Config::decode(&bytes, Endian::Little), config());
+
+        config().encode(&mut bytes, Endian::Big);
+        assert_eq!(bytes[..12], [0xC0, 0xFF, 0xEE, 0x00, 7, 0xAA, 0x01, 0x02, 0x3F, 0xC0, 0x00, 0x00]);
+        assert_eq!(Config::decode(&bytes, Endian::Big), config());
+    }
+
+    #[test]
+    fn read_write_struct() {
+        for cfg in [0, CFG_BE] {
+            let mut sim = SimulatedDap::new(0x100);
+            sim.set_cfg(cfg);
+            let mut dap = AdiV5::new(sim);
+            let mut mi = MemoryInterface::new(0x0);
+            mi.detect_endianness(&mut dap).unwrap();
+
+            dap.raw().memory[0x40..0x60].copy_from_slice(&[0xAA; 0x20]);
+            mi.write_struct(&mut dap, 0x40, &config()).unwrap();
+            assert_eq!(mi.read_struct::<Config>(&mut dap, 0x40), Ok(config()));
+            assert_eq!(mi.read::<u32>(&mut dap, 0x40), Ok(0xC0FF_EE00));
+            let magic = if cfg == CFG_BE { [0xC0, 0xFF, 0xEE, 0x00] } else { [0x00, 0xEE, 0xFF, 0xC0] };
+            assert_eq!(dap.raw().memory[0x40..0x44], magic);
+            // Padding is written as zeros.
+            assert_eq!(dap.raw().memory[0x4C..0x50], [0; 4]);
+            assert_eq!(mi.read_struct::<u16>(&mut dap, 0x46), Ok(0x0102));
+            assert_eq!(mi.read_struct::<[u8; 4]>(&mut dap, 0x58), Ok([1, 2, 3, 0]));
+        }
+    }
+}
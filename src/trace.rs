@@ -0,0 +1,248 @@
+use crate::access_port::consts::*;
+use crate::dap_access::consts::*;
+use crate::dap_access::{DAPAccess, Transfer, TransferKind, DEBUG_PORT};
+use log::Level;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// How much of the traffic `TraceDAP` logs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verbosity {
+    /// Only failed transactions are logged.
+    Errors,
+    /// Every transaction is logged in decoded form.
+    Decoded,
+    /// Every transaction is logged in decoded form followed by the raw value.
+    Raw,
+}
+
+const CTRL_STAT_FLAGS: [(u32, &str); 10] = [
+    (CTRLSTAT_ORUNDETECT, "ORUNDETECT"),
+    (CTRLSTAT_STICKYORUN, "STICKYORUN"),
+    (CTRLSTAT_STICKYCMP, "STICKYCMP"),
+    (CTRLSTAT_STICKYERR, "STICKYERR"),
+    (CTRLSTAT_READOK, "READOK"),
+    (CTRLSTAT_WDATAERR, "WDATAERR"),
+    (CDBGPWRUPREQ, "CDBGPWRUPREQ"),
+    (CDBGPWRUPACK, "CDBGPWRUPACK"),
+    (CSYSPWRUPREQ, "CSYSPWRUPREQ"),
+    (CSYSPWRUPACK, "CSYSPWRUPACK"),
+];
+
+const ABORT_FLAGS: [(u32, &str); 5] = [
+    (ABORT_DAPABORT, "DAPABORT"),
+    (ABORT_STKCMPCLR, "STKCMPCLR"),
+    (ABORT_STKERRCLR, "STKERRCLR"),
+    (ABORT_WDERRCLR, "WDERRCLR"),
+    (ABORT_ORUNERRCLR, "ORUNERRCLR"),
+];
+
+fn register_name(kind: TransferKind, port: u16, addr: u32) -> Option<&'static str> {
+    if port == DEBUG_PORT {
+        match (addr, kind) {
+            (DP_IDCODE, TransferKind::Read) => Some("IDCODE"),
+            (DP_ABORT, TransferKind::Write) => Some("ABORT"),
+            (DP_CTRL_STAT, _) => Some("CTRL/STAT"),
+            (DP_SELECT, _) => Some("SELECT"),
+            (DP_RDBUFF, TransferKind::Read) => Some("RDBUFF"),
+            (DP_RDBUFF, TransferKind::Write) => Some("TARGETSEL"),
+            _ => None,
+        }
+    } else {
+        match addr {
+            MEM_AP_CSW => Some("CSW"),
+            MEM_AP_TAR => Some("TAR"),
+            MEM_AP_TAR_UPPER => Some("TAR upper"),
+            MEM_AP_DRW => Some("DRW"),
+            MEM_AP_BD0 => Some("BD0"),
+            MEM_AP_BD1 => Some("BD1"),
+            MEM_AP_BD2 => Some("BD2"),
+            MEM_AP_BD3 => Some("BD3"),
+            AP_BASE_UPPER => Some("BASE upper"),
+            MEM_AP_CFG => Some("CFG"),
+            AP_BASE => Some("BASE"),
+            AP_IDR => Some("IDR"),
+            _ => None,
+        }
+    }
+}
+
+fn flags(value: u32, names: &[(u32, &str)]) -> String {
+    let set: Vec<&str> = names.iter().filter(|(mask, _)| value & mask != 0).map(|(_, name)| *name).collect();
+    if set.is_empty() {
+        "0".to_string()
+    } else {
+        set.join("|")
+    }
+}
+
+fn decode_value(kind: TransferKind, port: u16, addr: u32, value: u32) -> String {
+    match (port == DEBUG_PORT, addr, kind) {
+        (true, DP_ABORT, TransferKind::Write) => flags(value, &ABORT_FLAGS),
+        (true, DP_CTRL_STAT, _) => {
+            let mut decoded = flags(value, &CTRL_STAT_FLAGS);
+            if value & CTRLSTAT_TRNMODE != 0 {
+                decoded += &format!(" trnmode={}", (value & CTRLSTAT_TRNMODE) >> 2);
+            }
+            if value & MASKLANE != 0 {
+                decoded += &format!(" masklane={:#X}", (value & MASKLANE) >> 8);
+            }
+            decoded
+        },
+        (true, DP_SELECT, _) => format!(
+            "apsel={} apbank={:#X} dpbank={:#X}",
+            value >> SELECT_APSEL_SHIFT,
+            (value & SELECT_APBANKSEL_MASK) >> 4,
+            value & SELECT_DPBANKSEL_MASK
+        ),
+        (false, MEM_AP_CSW, _) => {
+            let size = match value & CSW_SIZE {
+                size @ 0..=5 => (8 << size).to_string(),
+                _ => "reserved".to_string(),
+            };
+            let addrinc = match value & CSW_ADDRINC {
+                CSW_NADDRINC => "off",
+                CSW_SADDRINC => "single",
+                CSW_PADDRINC => "packed",
+                _ => "reserved",
+            };
+            format!("size={} addrinc={}", size, addrinc)
+        },
+        _ => format!("{:#010X}", value),
+    }
+}
+
+/// Names the accessed register, e.g. `AP0 TAR =>`.
+fn location(kind: TransferKind, port: u16, addr: u32) -> String {
+    let port_name = if port == DEBUG_PORT { "DP".to_string() } else { format!("AP{}", port) };
+    let register = register_name(kind, port, addr).map_or_else(|| format!("{:#04X}", addr), |name| name.to_string());
+    let arrow = match kind {
+        TransferKind::Read => "=>",
+        TransferKind::Write => "<=",
+    };
+    format!("{} {} {}", port_name, register, arrow)
+}
+
+/// Describes a register transaction symbolically, e.g. `AP0 TAR <= 0x20000000`.
+pub fn describe(kind: TransferKind, port: u16, addr: u32, value: u32) -> String {
+    format!("{} {}", location(kind, port, addr), decode_value(kind, port, addr, value))
+}
+
+/// Logs every transaction of the wrapped DAP in decoded form through the `log` crate.
+///
+/// Successful transactions are logged at the configured level, failed ones as warnings.
+pub struct TraceDAP<D: DAPAccess> {
+    dap: D,
+    verbosity: Verbosity,
+    level: Level,
+}
+
+impl<D: DAPAccess> TraceDAP<D> {
+    pub fn new(dap: D) -> Self {
+        Self {
+            dap,
+            verbosity: Verbosity::Decoded,
+            level: Level::Trace,
+        }
+    }
+
+    pub fn into_inner(self) -> D {
+        self.dap
+    }
+
+    pub fn set_verbosity(&mut self, verbosity: Verbosity) {
+        self.verbosity = verbosity;
+    }
+
+    /// Sets the level successful transactions are logged at.
+    pub fn set_level(&mut self, level: Level) {
+        self.level = level;
+    }
+
+    fn trace(&self, kind: TransferKind, port: u16, addr: u32, value: u32, error: Option<&D::Error>) {
+        let level = if error.is_some() { Level::Warn } else { self.level };
+        if (error.is_none() && self.verbosity == Verbosity::Errors) || !log::log_enabled!(level) {
+            return;
+        }
+
+        // A failed read has no value to show.
+        let message = match (kind, error) {
+            (TransferKind::Read, Some(_)) => location(kind, port, addr),
+            _ if self.verbosity == Verbosity::Raw => format!("{} ({:#010X})", describe(kind, port, addr, value), value),
+            _ => describe(kind, port, addr, value),
+        };
+        match error {
+            Some(error) => log::log!(level, "{} failed: {:?}", message, error),
+            None => log::log!(level, "{}", message),
+        }
+    }
+}
+
+impl<D: DAPAccess> DAPAccess for TraceDAP<D> {
+    type Error = D::Error;
+
+    fn read_register(&mut self, port: u16, addr: u32) -> Result<u32, Self::Error> {
+        let result = self.dap.read_register(port, addr);
+        match result {
+            Ok(value) => self.trace(TransferKind::Read, port, addr, value, None),
+            Err(ref e) => self.trace(TransferKind::Read, port, addr, 0, Some(e)),
+        }
+        result
+    }
+
+    fn write_register(&mut self, port: u16, addr: u32, value: u32) -> Result<(), Self::Error> {
+        let result = self.dap.write_register(port, addr, value);
+        self.trace(TransferKind::Write, port, addr, value, result.as_ref().err());
+        result
+    }
+
+    fn batch(&mut self, transfers: &mut [Transfer]) -> Result<(), Self::Error> {
+        let result = self.dap.batch(transfers);
+        for transfer in transfers.iter().take_while(|t| t.done) {
+            self.trace(transfer.kind, transfer.port, transfer.addr, transfer.value, None);
+        }
+        if let Err(ref e) = result {
+            if let Some(failed) = transfers.iter().find(|t| !t.done) {
+                self.trace(failed.kind, failed.port, failed.addr, failed.value, Some(e));
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::describe;
+    use crate::access_port::consts::*;
+    use crate::dap_access::consts::*;
+    use crate::dap_access::{TransferKind, DEBUG_PORT};
+
+    #[test]
+    fn describe_ap_registers() {
+        assert_eq!(
+            describe(TransferKind::Write, 0, MEM_AP_CSW, CSW_VALUE | CSW_SIZE32),
+            "AP0 CSW <= size=32 addrinc=single"
+        );
+        assert_eq!(describe(TransferKind::Write, 0, MEM_AP_TAR, 0x2000_0000), "AP0 TAR <= 0x20000000");
+        assert_eq!(describe(TransferKind::Read, 1, MEM_AP_DRW, 0xDEAD_BEEF), "AP1 DRW => 0xDEADBEEF");
+        assert_eq!(describe(TransferKind::Read, 0, 0x40, 0x1), "AP0 0x40 => 0x00000001");
+    }
+
+    #[test]
+    fn describe_dp_registers() {
+        assert_eq!(
+            describe(TransferKind::Read, DEBUG_PORT, DP_CTRL_STAT, CDBGPWRUPACK | CSYSPWRUPACK),
+            "DP CTRL/STAT => CDBGPWRUPACK|CSYSPWRUPACK"
+        );
+        assert_eq!(
+            describe(TransferKind::Write, DEBUG_PORT, DP_ABORT, ABORT_STKERRCLR | ABORT_ORUNERRCLR),
+            "DP ABORT <= STKERRCLR|ORUNERRCLR"
+        );
+        assert_eq!(
+            describe(TransferKind::Write, DEBUG_PORT, DP_SELECT, 0x0100_00F0),
+            "DP SELECT <= apsel=1 apbank=0xF dpbank=0x0"
+        );
+        assert_eq!(describe(TransferKind::Read, DEBUG_PORT, DP_IDCODE, 0x2BA0_1477), "DP IDCODE => 0x2BA01477");
+    }
+}